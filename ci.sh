@@ -16,12 +16,29 @@ RUST_LOG=trace cargo test --release --manifest-path rnfc/Cargo.toml --features '
 cargo build --release --manifest-path rnfc-fm175xx/Cargo.toml --features ''
 cargo build --release --manifest-path rnfc-fm175xx/Cargo.toml --features 'defmt'
 cargo build --release --manifest-path rnfc-fm175xx/Cargo.toml --features 'log'
+cargo test --release --manifest-path rnfc-fm175xx/Cargo.toml
+
+cargo build --release --manifest-path rnfc-mfrc522/Cargo.toml --features ''
+cargo build --release --manifest-path rnfc-mfrc522/Cargo.toml --features 'defmt'
+cargo build --release --manifest-path rnfc-mfrc522/Cargo.toml --features 'log'
+
+cargo build --release --manifest-path rnfc-pn532/Cargo.toml --features ''
+cargo build --release --manifest-path rnfc-pn532/Cargo.toml --features 'defmt'
+cargo build --release --manifest-path rnfc-pn532/Cargo.toml --features 'log'
+cargo test --release --manifest-path rnfc-pn532/Cargo.toml
 
 cargo build --release --manifest-path rnfc-st25r39/Cargo.toml --features ''
 cargo build --release --manifest-path rnfc-st25r39/Cargo.toml --features 'defmt'
 cargo build --release --manifest-path rnfc-st25r39/Cargo.toml --features 'log'
+cargo test --release --manifest-path rnfc-st25r39/Cargo.toml
 
 cargo build --release --manifest-path rnfc-acr122u/Cargo.toml --features ''
+cargo test --release --manifest-path rnfc-acr122u/Cargo.toml
+
+cargo build --release --manifest-path rnfc-cli/Cargo.toml
+cargo test --release --manifest-path rnfc-cli/Cargo.toml
+
+cargo test --release --manifest-path rnfc-sim/Cargo.toml
 
 cargo build --release --manifest-path examples/st25r39-disco/Cargo.toml --target thumbv7em-none-eabi
 cargo build --release --manifest-path examples/fm175xx/Cargo.toml --target thumbv7em-none-eabi
@@ -94,7 +94,7 @@ async fn main(_spawner: Spawner) {
     let twim = Twim::new(p.TWISPI0, Irqs, sda, scl, config);
 
     let iface = I2cInterface::new(twim, 0x28);
-    let mut fm = Fm175xx::new(iface, npd, irq).await;
+    let mut fm = Fm175xx::new(iface, npd, irq).await.unwrap();
 
     let wup_config = WakeupConfig {
         sleep_time: 2,
@@ -104,10 +104,12 @@ async fn main(_spawner: Spawner) {
         n_drive: 1,
         p_drive: 4,
         recalibrate_interval: Some(Duration::from_secs(20 * 60)), // 20min
+        drift: None,
     };
 
     loop {
-        fm.wait_for_card(wup_config).await.unwrap();
+        let wakeup = fm.wait_for_card(wup_config).await.unwrap();
+        info!("wakeup: {:?}", wakeup);
 
         let poller = fm.start_iso14443a().await.unwrap();
         let mut poller = Poller::new(poller);
@@ -73,6 +73,7 @@ async fn main(_spawner: Spawner) {
 
     /*
     let conf = AatConfig {
+        strategy: AatStrategy::HillClimb,
         a_min: 0,
         a_max: 255,
         a_start: 128,
@@ -86,10 +87,8 @@ async fn main(_spawner: Spawner) {
         amp_target: 196,
         amp_weight: 1,
     };
-    st.mode_on().await;
-    //st.iso14443a_start().await.unwrap();
-    st.aat(conf).await;
-    info!("DONE");
+    let res = st.aat(conf).await.unwrap();
+    info!("DONE: a={} b={} cost={}", res.a, res.b, res.cost);
     return;
       */
 
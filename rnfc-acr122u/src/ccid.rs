@@ -0,0 +1,542 @@
+//! USB CCID transport.
+//!
+//! Each command is a bulk OUT message with a 10-byte header, answered by a bulk IN
+//! message with the same slot and sequence number. While the reader is still busy, it
+//! may send time extension requests before the actual response.
+
+use std::time::Duration;
+use std::{fmt, io};
+
+use log::{trace, warn};
+use nusb::transfer::RequestBuffer;
+
+use crate::with_timeout;
+
+const HEADER_LEN: usize = 10;
+
+/// Smallest `dwMaxCCIDMessageLength` allowed by the spec, and what the ACR122U reports.
+/// Used when the reader's class descriptor can't be read.
+pub const DEFAULT_MAX_MESSAGE_LEN: usize = 271;
+
+/// CCID class descriptor type.
+const CCID_DESCRIPTOR_TYPE: u8 = 0x21;
+const CCID_DESCRIPTOR_LEN: usize = 54;
+
+/// Default time to wait for each response message.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+mod msg {
+    pub const PC_TO_RDR_ICC_POWER_ON: u8 = 0x62;
+    pub const PC_TO_RDR_ICC_POWER_OFF: u8 = 0x63;
+    pub const PC_TO_RDR_ESCAPE: u8 = 0x6B;
+    pub const PC_TO_RDR_XFR_BLOCK: u8 = 0x6F;
+
+    pub const RDR_TO_PC_DATA_BLOCK: u8 = 0x80;
+    pub const RDR_TO_PC_SLOT_STATUS: u8 = 0x81;
+    pub const RDR_TO_PC_ESCAPE: u8 = 0x83;
+}
+
+const EP_OUT: u8 = 0x02;
+const EP_IN: u8 = 0x82;
+
+/// Bulk endpoints of a CCID interface.
+///
+/// Implemented for [`nusb::Interface`], and for fakes in tests.
+pub trait Endpoints {
+    /// Send a message on the bulk OUT endpoint.
+    async fn bulk_out(&mut self, data: Vec<u8>) -> Result<(), io::Error>;
+    /// Receive one bulk IN transfer, of at most `max_len` bytes.
+    async fn bulk_in(&mut self, max_len: usize) -> Result<Vec<u8>, io::Error>;
+}
+
+impl Endpoints for nusb::Interface {
+    async fn bulk_out(&mut self, data: Vec<u8>) -> Result<(), io::Error> {
+        nusb::Interface::bulk_out(self, EP_OUT, data).await.into_result()?;
+        Ok(())
+    }
+
+    async fn bulk_in(&mut self, max_len: usize) -> Result<Vec<u8>, io::Error> {
+        Ok(nusb::Interface::bulk_in(self, EP_IN, RequestBuffer::new(max_len))
+            .await
+            .into_result()?)
+    }
+}
+
+/// ICC status, from bits 0-1 of `bStatus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IccStatus {
+    /// An ICC is present and active.
+    Active,
+    /// An ICC is present and inactive.
+    Inactive,
+    /// No ICC present.
+    NotPresent,
+    /// Reserved value.
+    Reserved,
+}
+
+impl IccStatus {
+    fn from_status(status: u8) -> Self {
+        match status & 0x03 {
+            0 => Self::Active,
+            1 => Self::Inactive,
+            2 => Self::NotPresent,
+            _ => Self::Reserved,
+        }
+    }
+}
+
+/// Slot error, from `bError` when the command failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlotError {
+    CmdAborted,
+    IccMute,
+    XfrParityError,
+    XfrOverrun,
+    HwError,
+    BadAtrTs,
+    BadAtrTck,
+    IccProtocolNotSupported,
+    IccClassNotSupported,
+    ProcedureByteConflict,
+    DeactivatedProtocol,
+    BusyWithAutoSequence,
+    PinTimeout,
+    PinCancelled,
+    CmdSlotBusy,
+    /// The reader doesn't support the command.
+    CmdNotSupported,
+    /// Bad value in the command message, at this offset.
+    BadParameter(u8),
+    Other(u8),
+}
+
+impl SlotError {
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0xFF => Self::CmdAborted,
+            0xFE => Self::IccMute,
+            0xFD => Self::XfrParityError,
+            0xFC => Self::XfrOverrun,
+            0xFB => Self::HwError,
+            0xF8 => Self::BadAtrTs,
+            0xF7 => Self::BadAtrTck,
+            0xF6 => Self::IccProtocolNotSupported,
+            0xF5 => Self::IccClassNotSupported,
+            0xF4 => Self::ProcedureByteConflict,
+            0xF3 => Self::DeactivatedProtocol,
+            0xF2 => Self::BusyWithAutoSequence,
+            0xF0 => Self::PinTimeout,
+            0xEF => Self::PinCancelled,
+            0xE0 => Self::CmdSlotBusy,
+            0x00 => Self::CmdNotSupported,
+            x @ 0x01..=0x7F => Self::BadParameter(x),
+            x => Self::Other(x),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// USB transfer error.
+    Usb(io::Error),
+    /// No response in time.
+    Timeout,
+    /// Malformed response, or with an unexpected message type.
+    Protocol(&'static str),
+    /// The reader reported the command failed.
+    Slot { icc: IccStatus, error: SlotError },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usb(e) => write!(f, "USB error: {}", e),
+            Error::Timeout => write!(f, "CCID response timeout"),
+            Error::Protocol(msg) => write!(f, "CCID protocol error: {}", msg),
+            Error::Slot { icc, error } => write!(f, "CCID slot error {:?}, ICC {:?}", error, icc),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Usb(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(val: io::Error) -> Self {
+        Error::Usb(val)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(val: Error) -> Self {
+        match val {
+            Error::Usb(e) => e,
+            Error::Timeout => io::Error::new(io::ErrorKind::TimedOut, val),
+            e => io::Error::other(e),
+        }
+    }
+}
+
+/// Get `dwMaxCCIDMessageLength` from the CCID class descriptor.
+///
+/// Returns `None` if `desc` isn't one, or if the value is below the spec minimum.
+pub fn max_message_len(desc: &[u8]) -> Option<usize> {
+    if desc.len() < CCID_DESCRIPTOR_LEN || desc[1] != CCID_DESCRIPTOR_TYPE {
+        return None;
+    }
+    let len = u32::from_le_bytes([desc[44], desc[45], desc[46], desc[47]]) as usize;
+    (len >= DEFAULT_MAX_MESSAGE_LEN).then_some(len)
+}
+
+/// A response message, with the header fields the caller may care about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub icc: IccStatus,
+    /// `bChainParameter` for data blocks, `bClockStatus` for slot status.
+    pub param: u8,
+    pub data: Vec<u8>,
+}
+
+/// CCID transport for one slot.
+pub struct Ccid<E> {
+    ep: E,
+    slot: u8,
+    seq: u8,
+    timeout: Duration,
+    max_message_len: usize,
+}
+
+impl<E: Endpoints> Ccid<E> {
+    pub fn new(ep: E) -> Self {
+        Self {
+            ep,
+            slot: 0,
+            seq: 0,
+            timeout: DEFAULT_TIMEOUT,
+            max_message_len: DEFAULT_MAX_MESSAGE_LEN,
+        }
+    }
+
+    /// Set the max message length the reader accepts and sends, from its class descriptor.
+    /// See [`max_message_len`](self::max_message_len).
+    ///
+    /// Bulk IN transfers are requested with this size.
+    pub fn set_max_message_len(&mut self, len: usize) {
+        self.max_message_len = len;
+    }
+
+    /// Max message length, including the header.
+    pub fn max_message_len(&self) -> usize {
+        self.max_message_len
+    }
+
+    /// Set the time to wait for each response message.
+    ///
+    /// A time extension request from the reader restarts the wait.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Power on the slot. Returns the ATR.
+    pub async fn power_on(&mut self) -> Result<Vec<u8>, Error> {
+        // bPowerSelect: 5V.
+        let resp = self
+            .command(
+                msg::PC_TO_RDR_ICC_POWER_ON,
+                [0x01, 0x00, 0x00],
+                &[],
+                msg::RDR_TO_PC_DATA_BLOCK,
+            )
+            .await?;
+        Ok(resp.data)
+    }
+
+    /// Power off the slot.
+    pub async fn power_off(&mut self) -> Result<IccStatus, Error> {
+        let resp = self
+            .command(msg::PC_TO_RDR_ICC_POWER_OFF, [0; 3], &[], msg::RDR_TO_PC_SLOT_STATUS)
+            .await?;
+        Ok(resp.icc)
+    }
+
+    /// Send a block to the ICC with `PC_to_RDR_XfrBlock`, returns the response block.
+    pub async fn xfr_block(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let resp = self
+            .command(msg::PC_TO_RDR_XFR_BLOCK, [0; 3], data, msg::RDR_TO_PC_DATA_BLOCK)
+            .await?;
+        Ok(resp.data)
+    }
+
+    /// Send a vendor command to the reader with `PC_to_RDR_Escape`, returns its response.
+    pub async fn escape(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let resp = self
+            .command(msg::PC_TO_RDR_ESCAPE, [0; 3], data, msg::RDR_TO_PC_ESCAPE)
+            .await?;
+        Ok(resp.data)
+    }
+
+    /// Send a command message and wait for its response.
+    ///
+    /// `params` are the 3 message-specific header bytes.
+    pub async fn command(&mut self, msg_type: u8, params: [u8; 3], data: &[u8], resp_type: u8) -> Result<Response, Error> {
+        if HEADER_LEN + data.len() > self.max_message_len {
+            return Err(Error::Protocol("command too long"));
+        }
+
+        let seq = self.seq;
+        self.seq = self.seq.wrapping_add(1);
+
+        let mut buf = Vec::with_capacity(HEADER_LEN + data.len());
+        buf.push(msg_type);
+        buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        buf.push(self.slot);
+        buf.push(seq);
+        buf.extend_from_slice(&params);
+        buf.extend_from_slice(data);
+
+        trace!("ccid tx: {:02x?}", buf);
+        self.ep.bulk_out(buf).await?;
+
+        loop {
+            let msg = self.read_message().await?;
+            let (typ, slot, msg_seq, status, error, param) = (msg[0], msg[5], msg[6], msg[7], msg[8], msg[9]);
+
+            if slot != self.slot || msg_seq != seq {
+                // Left over from a command that timed out earlier.
+                warn!("ccid: skipping response for slot {} seq {}, want seq {}", slot, msg_seq, seq);
+                continue;
+            }
+
+            let icc = IccStatus::from_status(status);
+            match status >> 6 {
+                0 => {}
+                1 => {
+                    return Err(Error::Slot {
+                        icc,
+                        error: SlotError::from_code(error),
+                    })
+                }
+                2 => {
+                    trace!("ccid: time extension requested, multiplier {}", error);
+                    continue;
+                }
+                _ => return Err(Error::Protocol("reserved command status")),
+            }
+
+            if typ != resp_type {
+                return Err(Error::Protocol("unexpected message type"));
+            }
+
+            return Ok(Response {
+                icc,
+                param,
+                data: msg[HEADER_LEN..].to_vec(),
+            });
+        }
+    }
+
+    /// Read a whole message, which may take several bulk IN transfers.
+    async fn read_message(&mut self) -> Result<Vec<u8>, Error> {
+        let mut msg = self.bulk_in().await?;
+        if msg.len() < HEADER_LEN {
+            return Err(Error::Protocol("message too short"));
+        }
+        let len = u32::from_le_bytes([msg[1], msg[2], msg[3], msg[4]]) as usize;
+        if len > self.max_message_len.saturating_sub(HEADER_LEN) {
+            return Err(Error::Protocol("message too long"));
+        }
+        while msg.len() < HEADER_LEN + len {
+            let more = self.bulk_in().await?;
+            if more.is_empty() {
+                return Err(Error::Protocol("message truncated"));
+            }
+            msg.extend_from_slice(&more);
+        }
+        if msg.len() > HEADER_LEN + len {
+            return Err(Error::Protocol("message longer than its header says"));
+        }
+        trace!("ccid rx: {:02x?}", msg);
+        Ok(msg)
+    }
+
+    async fn bulk_in(&mut self) -> Result<Vec<u8>, Error> {
+        match with_timeout(self.timeout, self.ep.bulk_in(self.max_message_len)).await {
+            Some(r) => Ok(r?),
+            None => Err(Error::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::VecDeque;
+
+    use futures_lite::future;
+    use hex_literal::hex;
+
+    use super::*;
+
+    /// Records what's sent, and answers with canned transfers. Never answers once they run out.
+    #[derive(Default)]
+    struct FakeEndpoints {
+        sent: Vec<Vec<u8>>,
+        responses: VecDeque<Vec<u8>>,
+    }
+
+    impl FakeEndpoints {
+        fn new(responses: &[&[u8]]) -> Self {
+            Self {
+                sent: Vec::new(),
+                responses: responses.iter().map(|r| r.to_vec()).collect(),
+            }
+        }
+    }
+
+    impl Endpoints for FakeEndpoints {
+        async fn bulk_out(&mut self, data: Vec<u8>) -> Result<(), io::Error> {
+            self.sent.push(data);
+            Ok(())
+        }
+
+        async fn bulk_in(&mut self, max_len: usize) -> Result<Vec<u8>, io::Error> {
+            match self.responses.pop_front() {
+                Some(r) => {
+                    assert!(r.len() <= max_len);
+                    Ok(r)
+                }
+                None => future::pending().await,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn xfr_block_sequence() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[
+            &hex!("80 02000000 00 00 00 00 00 9000"),
+            &hex!("80 01000000 00 01 00 00 00 aa"),
+        ]));
+
+        assert_eq!(ccid.xfr_block(&hex!("ff000000")).await.unwrap(), hex!("9000"));
+        assert_eq!(ccid.xfr_block(&[]).await.unwrap(), hex!("aa"));
+        assert_eq!(
+            ccid.ep.sent,
+            [
+                hex!("6f 04000000 00 00 000000 ff000000").to_vec(),
+                hex!("6f 00000000 00 01 000000").to_vec(),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn escape() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("83 01000000 00 00 00 00 00 42")]));
+        assert_eq!(ccid.escape(&hex!("01")).await.unwrap(), hex!("42"));
+        assert_eq!(ccid.ep.sent, [hex!("6b 01000000 00 00 000000 01").to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn time_extension() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[
+            &hex!("80 00000000 00 00 80 01 00"),
+            &hex!("80 00000000 00 00 80 01 00"),
+            &hex!("80 01000000 00 00 00 00 00 55"),
+        ]));
+        assert_eq!(ccid.xfr_block(&[]).await.unwrap(), hex!("55"));
+    }
+
+    #[tokio::test]
+    async fn stale_response_skipped() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[
+            &hex!("80 01000000 00 05 00 00 00 11"),
+            &hex!("80 01000000 00 00 00 00 00 22"),
+        ]));
+        assert_eq!(ccid.xfr_block(&[]).await.unwrap(), hex!("22"));
+    }
+
+    #[tokio::test]
+    async fn multi_packet() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[
+            &hex!("80 06000000 00 00 00 00 00 0102"),
+            &hex!("03040506"),
+        ]));
+        assert_eq!(ccid.xfr_block(&[]).await.unwrap(), hex!("010203040506"));
+    }
+
+    #[tokio::test]
+    async fn slot_error() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("80 00000000 00 00 42 fe 00")]));
+        match ccid.xfr_block(&[]).await {
+            Err(Error::Slot { icc, error }) => {
+                assert_eq!(icc, IccStatus::NotPresent);
+                assert_eq!(error, SlotError::IccMute);
+            }
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[tokio::test]
+    async fn unexpected_message_type() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("81 00000000 00 00 00 00 00")]));
+        assert!(matches!(ccid.xfr_block(&[]).await, Err(Error::Protocol(_))));
+
+        // Shorter than the header: nothing fits.
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("80 00000000 00 01 00 00 00")]));
+        ccid.set_max_message_len(HEADER_LEN - 1);
+        assert!(matches!(ccid.xfr_block(&[]).await, Err(Error::Protocol(_))));
+        assert!(ccid.ep.sent.is_empty());
+    }
+
+    #[tokio::test]
+    async fn truncated_header() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("80 00000000")]));
+        assert!(matches!(ccid.xfr_block(&[]).await, Err(Error::Protocol(_))));
+    }
+
+    #[tokio::test]
+    async fn max_message_len_limits() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[&hex!("80 04000000 00 01 00 00 00")]));
+        ccid.set_max_message_len(HEADER_LEN + 3);
+        assert!(matches!(ccid.xfr_block(&[0; 4]).await, Err(Error::Protocol(_))));
+        assert!(ccid.ep.sent.is_empty());
+        assert!(matches!(ccid.xfr_block(&[]).await, Err(Error::Protocol(_))));
+    }
+
+    #[test]
+    fn class_descriptor() {
+        let mut desc = [0; CCID_DESCRIPTOR_LEN];
+        desc[0] = CCID_DESCRIPTOR_LEN as u8;
+        desc[1] = CCID_DESCRIPTOR_TYPE;
+        desc[44..48].copy_from_slice(&271u32.to_le_bytes());
+        assert_eq!(max_message_len(&desc), Some(271));
+
+        desc[44..48].copy_from_slice(&64u32.to_le_bytes());
+        assert_eq!(max_message_len(&desc), None);
+
+        desc[1] = 0x24;
+        assert_eq!(max_message_len(&desc), None);
+        assert_eq!(max_message_len(&hex!("09 04 00 00 02 0b 00 00 00")), None);
+    }
+
+    #[tokio::test]
+    async fn timeout() {
+        let mut ccid = Ccid::new(FakeEndpoints::new(&[]));
+        ccid.set_timeout(Duration::from_millis(10));
+        assert!(matches!(ccid.xfr_block(&[]).await, Err(Error::Timeout)));
+
+        // Late response to the first command is skipped.
+        ccid.ep.responses.push_back(hex!("80 00000000 00 00 00 00 00").to_vec());
+        ccid.ep.responses.push_back(hex!("80 01000000 00 01 00 00 00 33").to_vec());
+        assert_eq!(ccid.xfr_block(&[]).await.unwrap(), hex!("33"));
+    }
+}
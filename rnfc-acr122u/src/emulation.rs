@@ -0,0 +1,97 @@
+//! Card emulation with `TgInitAsTarget`: the reader acts as an ISO14443A card.
+//!
+//! The PN53x answers RATS and runs the ISO-DEP block protocol itself, so
+//! `TgGetData` and `TgSetData` carry whole APDUs.
+
+use std::io::Error;
+
+use log::{debug, trace};
+use rnfc_pn532::pn53x::{self, cmd, param, Status, TargetMode};
+use rnfc_traits::card_emulation::{Iso14443aConfig, IsoDepCard};
+
+use crate::{pn53x_error, Device};
+
+/// The reader, activated as a card by another reader. See [`Device::emulate`].
+pub struct EmulatedCard<'a> {
+    dev: &'a mut Device,
+    /// How the card was activated.
+    pub mode: TargetMode,
+    /// The first command from the other reader, as reported by `TgInitAsTarget`.
+    pub initiator_command: Vec<u8>,
+}
+
+impl Device {
+    /// Act as an ISO14443A card with `config`, and wait for another reader to activate it.
+    ///
+    /// See [`pn53x::init_as_target`] for what the PN53x lets us configure. This waits
+    /// until a reader shows up, so set a [timeout](Self::set_timeout) long enough.
+    pub async fn emulate(&mut self, config: &Iso14443aConfig<'_>) -> Result<EmulatedCard<'_>, Error> {
+        self.set_parameters(self.params | param::ISO14443_4_PICC).await?;
+
+        let mut data = [0; pn53x::MAX_DATA_LEN];
+        let n = pn53x::init_as_target(config, &mut data).map_err(pn53x_error)?;
+        let res = self.pn53x_cmd(cmd::TG_INIT_AS_TARGET, &data[..n]).await?;
+        let (mode, command) = pn53x::parse_init_as_target(&res).map_err(pn53x_error)?;
+        debug!("activated as target: {:02x?}", mode);
+        trace!("initiator command: {:02x?}", command);
+
+        Ok(EmulatedCard {
+            initiator_command: command.to_vec(),
+            mode,
+            dev: self,
+        })
+    }
+}
+
+impl EmulatedCard<'_> {
+    /// Run a target command, and check the status byte. Returns the data after it.
+    async fn status_cmd(&mut self, code: u8, data: &[u8]) -> Result<(Status, Vec<u8>), Error> {
+        let mut res = self.dev.pn53x_cmd(code, data).await?;
+        if res.is_empty() {
+            return Err(Error::other("target command failed, no status"));
+        }
+        let status = Status(res.remove(0));
+        status.check().map_err(pn53x_error)?;
+        Ok((status, res))
+    }
+}
+
+impl IsoDepCard for EmulatedCard<'_> {
+    type Error = Error;
+
+    async fn receive(&mut self, rx: &mut [u8]) -> Result<usize, Self::Error> {
+        // Ask for the rest while the MI bit is set.
+        let mut len = 0;
+        loop {
+            let (status, data) = self.status_cmd(cmd::TG_GET_DATA, &[]).await?;
+            if len + data.len() > rx.len() {
+                return Err(pn53x_error(pn53x::Error::BufferTooSmall));
+            }
+            rx[len..][..data.len()].copy_from_slice(&data);
+            len += data.len();
+
+            if !status.more_info() {
+                return Ok(len);
+            }
+        }
+    }
+
+    async fn respond(&mut self, tx: &[u8]) -> Result<(), Self::Error> {
+        // Data per command, after the TFI and command code.
+        let max_chunk = self.dev.max_pn53x_len() - 2;
+
+        // Chain with TgSetMetaData, which sets the MI bit, and end with TgSetData.
+        let mut chunks = tx.chunks(max_chunk).peekable();
+        if chunks.peek().is_none() {
+            self.status_cmd(cmd::TG_SET_DATA, &[]).await?;
+        }
+        while let Some(chunk) = chunks.next() {
+            let code = match chunks.peek() {
+                Some(_) => cmd::TG_SET_META_DATA,
+                None => cmd::TG_SET_DATA,
+            };
+            self.status_cmd(code, chunk).await?;
+        }
+        Ok(())
+    }
+}
@@ -1,193 +1,481 @@
+#![allow(async_fn_in_trait)]
+
+pub mod ccid;
+mod emulation;
+mod native;
+mod peripherals;
+mod usb;
+
+use std::future::Future;
 use std::io::Error;
+use std::time::Duration;
 
-use hex_literal::hex;
-use log::{trace, warn};
-use nusb::transfer::RequestBuffer;
+use futures_lite::future;
+use futures_timer::Delay;
+use log::{debug, trace, warn};
+use nusb::transfer::{Direction, EndpointType};
 use nusb::Interface;
-use rnfc_traits::iso_dep::Reader;
+use rnfc_pn532::iso14443a::{Error as ThruError, Thru};
+use rnfc_pn532::pn53x::{self, cmd, param, Pn53x, Status};
+pub use rnfc_pn532::pn53x::{AutoPoll, Modulation, Target, TargetA, TargetB, TargetF, TargetJewel};
+use rnfc_traits::{iso14443a, iso14443a_ll, iso_dep};
+
+use crate::ccid::Ccid;
+pub use crate::emulation::EmulatedCard;
+use crate::native::Native;
+pub use crate::peripherals::{Buzzer, LedBuzzer, LedState, PiccParams};
+pub use crate::usb::{list, Model, ReaderEvent, ReaderInfo, Watcher};
 
 pub struct Device {
-    iface: Interface,
+    model: Model,
+    transport: Transport,
+    /// Last `SetParameters` flags.
+    params: u8,
+}
+
+enum Transport {
+    /// Pseudo-APDUs over CCID.
+    Ccid(Ccid<Interface>),
+    /// Native frames over bulk endpoints.
+    Native(Native),
 }
 
 pub struct Card<'a> {
     dev: &'a mut Device,
+    tg: u8,
 
     pub atqa: [u8; 2],
     pub sak: u8,
     pub uid: Vec<u8>,
     pub ats: Vec<u8>,
+
+    thru: Option<Thru>,
+}
+
+impl<'a> Card<'a> {
+    fn new(dev: &'a mut Device, target: TargetA) -> Self {
+        trace!("atqa: {:02x?}", target.atqa);
+        trace!("sak: {:02x?}", target.sak);
+        trace!("uid: {:02x?}", target.uid);
+        trace!("ats: {:02x?}", target.ats);
+
+        Self {
+            dev,
+            tg: target.tg,
+            atqa: target.atqa,
+            sak: target.sak,
+            uid: target.uid.to_vec(),
+            ats: target.ats.to_vec(),
+            thru: None,
+        }
+    }
+
+    /// Enable or disable the parity bits for raw frames. Needed for MIFARE Classic
+    /// after authentication, since the parity bits are encrypted.
+    pub async fn set_parity(&mut self, enabled: bool) -> Result<(), ThruError<Error>> {
+        // Set up the CIU for raw frames the first time they're used.
+        let thru = match &mut self.thru {
+            Some(thru) => thru,
+            None => self.thru.insert(Thru::new(&mut *self.dev).await?),
+        };
+        thru.set_parity(&mut *self.dev, enabled).await
+    }
+}
+
+impl iso14443a::Reader for Card<'_> {
+    type Error = ThruError<Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        // Set up the CIU for raw frames the first time they're used.
+        let thru = match &mut self.thru {
+            Some(thru) => thru,
+            None => self.thru.insert(Thru::new(&mut *self.dev).await?),
+        };
+        let bits = thru
+            .transceive(&mut *self.dev, tx, rx, iso14443a_ll::Frame::Standard { timeout_1fc })
+            .await?;
+        Ok(bits / 8)
+    }
+
+    fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    fn atqa(&self) -> [u8; 2] {
+        self.atqa
+    }
+
+    fn sak(&self) -> u8 {
+        self.sak
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        Delay::new(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
 }
 
-impl<'a> Reader for Card<'a> {
+impl iso_dep::Reader for Card<'_> {
     type Error = Error;
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
-        let mut data = vec![0; 1 + tx.len()];
-        data[0] = 0x01;
-        data[1..].copy_from_slice(tx);
+        self.dev.data_exchange(self.tg, tx, rx).await
+    }
+}
 
-        let res = self.dev.pn53x_cmd(0x40, &data).await?;
+/// A card of another technology than ISO14443A, talked to with `InDataExchange`.
+pub struct TargetCard<'a, T> {
+    dev: &'a mut Device,
+    tg: u8,
+    pub target: T,
+}
 
-        // check status byte
-        if res[0] != 0x00 {
-            return Err(Error::other(format!("transceive failed, status=0x{:02x}", res[0])));
-        }
+impl<T> TargetCard<'_, T> {
+    /// Send a command to the card, and receive its response. Returns the response length.
+    ///
+    /// For FeliCa, `tx` and `rx` start with the length byte.
+    pub async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Error> {
+        self.dev.data_exchange(self.tg, tx, rx).await
+    }
+}
 
-        rx[..res.len() - 1].copy_from_slice(&res[1..]);
-        Ok(res.len() - 1)
+/// The PN53x does the ISO14443B activation with ATTRIB, so the card is ready for ISO-DEP.
+impl iso_dep::Reader for TargetCard<'_, TargetB> {
+    type Error = Error;
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        self.dev.data_exchange(self.tg, tx, rx).await
     }
 }
 
-const EP_OUT: u8 = 0x02;
-const EP_IN: u8 = 0x82;
+/// A card found by [`Device::poll_for`] or [`Device::auto_poll`], tagged with its technology.
+pub enum AnyCard<'a> {
+    A(Card<'a>),
+    B(TargetCard<'a, TargetB>),
+    F(TargetCard<'a, TargetF>),
+    Jewel(TargetCard<'a, TargetJewel>),
+}
+
+impl<'a> AnyCard<'a> {
+    fn new(dev: &'a mut Device, target: Target) -> Self {
+        debug!("found card: {:02x?}", target);
+        let tg = target.tg();
+        match target {
+            Target::A(target) => AnyCard::A(Card::new(dev, target)),
+            Target::B(target) => AnyCard::B(TargetCard { dev, tg, target }),
+            Target::F(target) => AnyCard::F(TargetCard { dev, tg, target }),
+            Target::Jewel(target) => AnyCard::Jewel(TargetCard { dev, tg, target }),
+        }
+    }
+
+    pub fn modulation(&self) -> Modulation {
+        match self {
+            AnyCard::A(_) => Modulation::TypeA106,
+            AnyCard::B(_) => Modulation::TypeB106,
+            // The bit rate isn't in the target data, both FeliCa rates look the same.
+            AnyCard::F(_) => Modulation::Felica212,
+            AnyCard::Jewel(_) => Modulation::Jewel106,
+        }
+    }
+}
 
 impl Device {
+    /// Open the first reader found.
     pub async fn new() -> Result<Self, Error> {
-        let di = nusb::list_devices()?
-            .find(|d| d.vendor_id() == 0x072f && d.product_id() == 0x2200)
+        let reader = list()?
+            .into_iter()
+            .next()
             .ok_or_else(|| Error::other("no USB device found"))?;
-        trace!("Device info: {di:?}");
+        Self::open(&reader).await
+    }
 
-        let device = di.open().unwrap();
+    /// Open a reader found by [`list`] or a [`Watcher`].
+    pub async fn open(reader: &ReaderInfo) -> Result<Self, Error> {
+        debug!("opening {}", reader);
+        trace!("Device info: {:?}", reader.info);
+
+        let device = reader.info.open()?;
         if let Err(e) = device.reset() {
             warn!("acr122u reset failed: {}", e);
         }
         let iface = device.claim_interface(0)?;
 
-        let mut this = Self { iface };
+        let transport = match reader.model {
+            Model::Acr122u | Model::Acr1252u => {
+                let mut ccid = Ccid::new(iface);
+                match ccid_max_message_len(&device) {
+                    Some(len) => ccid.set_max_message_len(len),
+                    None => warn!("CCID class descriptor not found, assuming the ACR122U defaults"),
+                }
+                trace!("max CCID message length: {}", ccid.max_message_len());
+                Transport::Ccid(ccid)
+            }
+            Model::Pn533 => {
+                let (ep_out, ep_in) = bulk_endpoints(&device).ok_or_else(|| Error::other("bulk endpoints not found"))?;
+                Transport::Native(Native::new(iface, ep_out, ep_in))
+            }
+        };
+
+        let mut this = Self {
+            model: reader.model,
+            transport,
+            params: 0,
+        };
 
         this.init().await?;
 
         Ok(this)
     }
 
-    async fn init(&mut self) -> Result<(), Error> {
-        // turn on
-        self.transfer(Vec::from(hex!("62000000000000010000"))).await?;
+    pub fn model(&self) -> Model {
+        self.model
+    }
 
-        // set PICC operating parameter: disable everything.
-        self.transfer(Vec::from(hex!("6f050000000000000000 ff00510000"))).await?;
+    async fn init(&mut self) -> Result<(), Error> {
+        if let Transport::Ccid(ccid) = &mut self.transport {
+            let atr = ccid.power_on().await?;
+            trace!("atr: {:02x?}", atr);
+        }
+        if self.model == Model::Acr122u {
+            // Disable everything, the PN53x commands do the polling.
+            self.set_picc_params(PiccParams::default()).await?;
+        }
 
         // GetFirmwareVersion
         // This seems to "abort" previous command, retry it a few times?
         let mut res = Ok(());
         for _ in 0..3 {
-            res = self.pn53x_cmd(0x02, &[]).await.map(|_| ());
+            res = self.pn53x_cmd(cmd::GET_FIRMWARE_VERSION, &[]).await.map(|_| ());
             if res.is_ok() {
                 break;
             }
         }
         res?;
 
-        // SetParameters: Enable auto-RATS, auto-ATR_RES
-        self.pn53x_cmd(0x12, &[0x14]).await?;
+        self.set_auto_rats(true).await?;
+
+        Ok(())
+    }
+
+    /// Enable or disable sending RATS when a card supporting ISO-DEP is found by [`poll`](Self::poll).
+    ///
+    /// With it disabled, cards are only selected, and can be talked to with raw frames
+    /// through [`iso14443a::Reader`], same as with the embedded readers.
+    pub async fn set_auto_rats(&mut self, enabled: bool) -> Result<(), Error> {
+        let params = if enabled {
+            self.params | param::AUTOMATIC_RATS
+        } else {
+            self.params & !param::AUTOMATIC_RATS
+        };
+        self.set_parameters(params | param::AUTOMATIC_ATR_RES).await
+    }
 
+    async fn set_parameters(&mut self, params: u8) -> Result<(), Error> {
+        self.pn53x_cmd(cmd::SET_PARAMETERS, &[params]).await?;
+        self.params = params;
         Ok(())
     }
 
+    /// Set the time to wait for each response from the reader. See [`Ccid::set_timeout`].
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        match &mut self.transport {
+            Transport::Ccid(ccid) => ccid.set_timeout(timeout),
+            Transport::Native(native) => native.set_timeout(timeout),
+        }
+    }
+
+    /// Beep once, blinking the green LED, and leave the red LED on.
     pub async fn beep(&mut self) -> Result<(), Error> {
-        self.transfer(Vec::from(hex!("6f090000000000000000 ff0040ad0402000101")))
-            .await?;
+        self.led_buzzer(&LedBuzzer {
+            red: Some(true),
+            green: Some(false),
+            blink_red: None,
+            blink_green: Some(true),
+            t1: 2,
+            t2: 0,
+            repetitions: 1,
+            buzzer: Buzzer::T1,
+        })
+        .await?;
         Ok(())
     }
 
     pub async fn poll(&mut self) -> Result<Card<'_>, Error> {
-        let res = self.pn53x_cmd(0x4a, &[0x01, 0x00]).await?;
+        let res = self
+            .pn53x_cmd(cmd::IN_LIST_PASSIVE_TARGET, &pn53x::list_passive_target_a())
+            .await?;
 
-        if res[0] != 0x01 {
-            return Err(Error::other("no card present"));
+        let target = pn53x::parse_list_passive_target_a(&res)
+            .map_err(pn53x_error)?
+            .ok_or_else(|| Error::other("no card present"))?;
+
+        Ok(Card::new(self, target))
+    }
+
+    /// Look for a card with `modulation`, and select it. Returns `None` if there's no card.
+    pub async fn poll_for(&mut self, modulation: Modulation) -> Result<Option<AnyCard<'_>>, Error> {
+        let res = self
+            .pn53x_cmd(cmd::IN_LIST_PASSIVE_TARGET, &pn53x::list_passive_target(modulation))
+            .await?;
+
+        match pn53x::parse_list_passive_target(modulation, &res).map_err(pn53x_error)? {
+            None => Ok(None),
+            Some(target) => Ok(Some(AnyCard::new(self, target))),
         }
-        assert!(res[1] == 0x01);
-
-        let atqa: [u8; 2] = res[2..4].try_into().unwrap();
-        let sak = res[4];
-        let uid_len = res[5] as usize;
-        let uid = res[6..][..uid_len].to_vec();
-        let ats_len = res[6 + uid_len] as usize;
-        let ats = res[6 + uid_len..][..ats_len].to_vec();
-
-        trace!("atqa: {:02x?}", atqa);
-        trace!("sak: {:02x?}", sak);
-        trace!("uid: {:02x?}", uid);
-        trace!("ats: {:02x?}", ats);
-
-        Ok(Card {
-            dev: self,
-            atqa,
-            sak,
-            uid,
-            ats,
-        })
     }
 
-    async fn pn53x_cmd(&mut self, code: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
-        assert!(data.len() <= 255);
-
-        let mut buf = vec![0; 10 + 5 + 2 + data.len()];
-        // Build CCID header
-        buf[0] = 0x6f;
-        buf[1..5].copy_from_slice(&(5 + 2 + data.len() as u32).to_le_bytes());
-        // Build fake APDU header
-        buf[10] = 0xFF;
-        buf[14] = (2 + data.len()) as u8;
-        // Build PN53x header
-        buf[15] = 0xd4;
-        buf[16] = code;
-        // data payload
-        buf[17..].copy_from_slice(data);
-
-        // do it!
-        let res = self.transfer(buf).await?;
-
-        // Strip CCID header
-        if res.len() < 10 {
-            return Err(Error::other(format!("unexpected CCID response length {}", res.len())));
+    /// Let the reader poll for the target types in `config` with `InAutoPoll`, and select
+    /// the first card found. Returns `None` if there's no card after all the rounds.
+    ///
+    /// With endless rounds, set a [timeout](Self::set_timeout) long enough for the card to show up.
+    pub async fn auto_poll(&mut self, config: &AutoPoll<'_>) -> Result<Option<AnyCard<'_>>, Error> {
+        let mut data = [0; 17];
+        let n = config.encode(&mut data).map_err(pn53x_error)?;
+        let res = self.pn53x_cmd(cmd::IN_AUTO_POLL, &data[..n]).await?;
+
+        let targets = pn53x::parse_auto_poll(&res).map_err(pn53x_error)?;
+        match targets.into_iter().next() {
+            None => Ok(None),
+            Some(target) => Ok(Some(AnyCard::new(self, target))),
         }
-        if res[0] != 0x80 {
-            return Err(Error::other(format!("unexpected CCID response 0x{:02x}", res[0])));
-        };
-        if res[0] != 0x00 && res[8] != 0x81 {
-            return Err(Error::other(format!("CCID error 0x{:02x}", res[8])));
+    }
+
+    /// Run `InDataExchange` with target `tg`, chaining with the MI bit in both directions
+    /// when `tx` or the response don't fit in one command.
+    async fn data_exchange(&mut self, tg: u8, tx: &[u8], rx: &mut [u8]) -> Result<usize, Error> {
+        // Data per InDataExchange, after the TFI, command code and Tg.
+        let max_chunk = self.max_pn53x_len() - 3;
+
+        // Send in chunks, with the MI bit set in all but the last one.
+        let mut pos = 0;
+        let mut res = loop {
+            let chunk = &tx[pos..][..(tx.len() - pos).min(max_chunk)];
+            pos += chunk.len();
+            let more = pos < tx.len();
+
+            let mut data = Vec::with_capacity(1 + chunk.len());
+            data.push(tg | if more { pn53x::TG_MORE_INFO } else { 0 });
+            data.extend_from_slice(chunk);
+            let res = self.exchange(&data).await?;
+            if !more {
+                break res;
+            }
         };
-        let len = u32::from_le_bytes(res[1..5].try_into().unwrap()) as usize;
-        if res.len() < 10 + len {
-            return Err(Error::other(format!("CCID resp too short: want {} got {}", len, res.len())));
+
+        // Receive, asking for the rest while the MI bit is set.
+        let mut len = 0;
+        loop {
+            let data = &res[1..];
+            if len + data.len() > rx.len() {
+                return Err(pn53x_error(pn53x::Error::BufferTooSmall));
+            }
+            rx[len..][..data.len()].copy_from_slice(data);
+            len += data.len();
+
+            if !Status(res[0]).more_info() {
+                return Ok(len);
+            }
+            res = self.exchange(&[tg]).await?;
         }
-        let res = &res[10..][..len];
+    }
 
-        // Strip fake APDU footer
-        if res.len() < 2 {
-            return Err(Error::other(format!("APDU resp too short: {}", res.len())));
+    /// Run one `InDataExchange`, and check the status byte.
+    async fn exchange(&mut self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let res = self.pn53x_cmd(cmd::IN_DATA_EXCHANGE, data).await?;
+        let status = Status(*res.first().ok_or_else(|| Error::other("transceive failed, no status"))?);
+        status.check().map_err(pn53x_error)?;
+        Ok(res)
+    }
+
+    /// Max length of a PN53x command payload, including the TFI and command code.
+    ///
+    /// Over CCID, limited by the 1-byte Lc of the pseudo-APDU, and the CCID message length.
+    fn max_pn53x_len(&self) -> usize {
+        match &self.transport {
+            Transport::Ccid(ccid) => ccid.max_message_len().saturating_sub(10 + 5).min(255),
+            Transport::Native(_) => pn53x::MAX_DATA_LEN,
         }
-        let apdu_result = &res[res.len() - 2..];
-        if apdu_result != &[0x90, 0x00] {
-            return Err(Error::other(format!("APDU response code {:02x?}", apdu_result)));
+    }
+
+    async fn pn53x_cmd(&mut self, code: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut payload = [0; pn53x::MAX_DATA_LEN];
+        let len = pn53x::encode_command(code, data, &mut payload).map_err(pn53x_error)?;
+        if len > self.max_pn53x_len() {
+            return Err(Error::other(format!("PN53x command too long: {}", len)));
         }
-        let res = &res[..res.len() - 2];
+
+        let res = match &mut self.transport {
+            Transport::Ccid(ccid) => {
+                // Fake APDU header
+                let mut apdu = Vec::with_capacity(5 + len);
+                apdu.extend_from_slice(&[0xFF, 0x00, 0x00, 0x00, len as u8]);
+                apdu.extend_from_slice(&payload[..len]);
+
+                // do it!
+                let mut res = ccid.xfr_block(&apdu).await?;
+
+                // Strip fake APDU footer
+                if res.len() < 2 {
+                    return Err(Error::other(format!("APDU resp too short: {}", res.len())));
+                }
+                let apdu_result = &res[res.len() - 2..];
+                if apdu_result != [0x90, 0x00] {
+                    return Err(Error::other(format!("APDU response code {:02x?}", apdu_result)));
+                }
+                res.truncate(res.len() - 2);
+                res
+            }
+            Transport::Native(native) => native.command(&payload[..len]).await?,
+        };
 
         // Strip PN53x header.
-        if res.len() < 2 {
-            return Err(Error::other(format!("PN53x resp too short: {}", res.len())));
-        }
-        if res[0] != 0xd5 {
-            return Err(Error::other(format!("PN53x unexpected resp 0x{:02x}", res[0])));
-        }
-        if res[1] != code + 1 {
-            return Err(Error::other(format!(
-                "PN53x unexpected resp cmd: want 0x{:02x}, got 0x{:02x}",
-                code + 1,
-                res[1],
-            )));
-        }
+        let res = pn53x::decode_response(code, &res).map_err(pn53x_error)?;
 
-        Ok(res[2..].to_vec())
+        Ok(res.to_vec())
     }
+}
 
-    async fn transfer(&mut self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
-        trace!("tx: {:02x?}", data);
-        self.iface.bulk_out(EP_OUT, data).await.into_result()?;
-        let res = self.iface.bulk_in(EP_IN, RequestBuffer::new(256)).await.into_result()?;
-        trace!("rx: {:02x?}", res);
-        Ok(res)
+impl Pn53x for Device {
+    type Error = Error;
+
+    async fn command(&mut self, cmd: u8, data: &[u8], resp: &mut [u8]) -> Result<usize, Self::Error> {
+        let res = self.pn53x_cmd(cmd, data).await?;
+        if res.len() > resp.len() {
+            return Err(pn53x_error(pn53x::Error::BufferTooSmall));
+        }
+        resp[..res.len()].copy_from_slice(&res);
+        Ok(res.len())
     }
 }
+
+/// Find `dwMaxCCIDMessageLength` in the class descriptor of the CCID interface.
+fn ccid_max_message_len(device: &nusb::Device) -> Option<usize> {
+    let config = device.active_configuration().ok()?;
+    config
+        .interface_alt_settings()
+        .filter(|alt| alt.interface_number() == 0)
+        .find_map(|alt| alt.descriptors().find_map(|desc| ccid::max_message_len(&desc)))
+}
+
+/// Find the bulk OUT and IN endpoints of interface 0.
+fn bulk_endpoints(device: &nusb::Device) -> Option<(u8, u8)> {
+    let config = device.active_configuration().ok()?;
+    let alt = config.interface_alt_settings().find(|alt| alt.interface_number() == 0)?;
+    let find = |dir| {
+        alt.endpoints()
+            .find(|ep| ep.transfer_type() == EndpointType::Bulk && ep.direction() == dir)
+            .map(|ep| ep.address())
+    };
+    Some((find(Direction::Out)?, find(Direction::In)?))
+}
+
+/// Run `fut`, giving up after `timeout`.
+pub(crate) async fn with_timeout<F: Future>(timeout: Duration, fut: F) -> Option<F::Output> {
+    let timer = async {
+        Delay::new(timeout).await;
+        None
+    };
+    future::or(async { Some(fut.await) }, timer).await
+}
+
+fn pn53x_error(e: pn53x::Error) -> Error {
+    Error::other(format!("PN53x error: {:?}", e))
+}
@@ -0,0 +1,84 @@
+//! PN53x native frames over USB bulk endpoints, for PN533-based readers.
+//!
+//! Same frames as the PN532 uses over serial links, see [`rnfc_pn532::frame`].
+
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+
+use log::{trace, warn};
+use nusb::transfer::RequestBuffer;
+use nusb::Interface;
+use rnfc_pn532::frame::{self, Frame, ACK, MAX_FRAME_LEN};
+
+use crate::with_timeout;
+
+/// Time for the chip to acknowledge a command.
+const ACK_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub(crate) struct Native {
+    iface: Interface,
+    ep_out: u8,
+    ep_in: u8,
+    timeout: Duration,
+}
+
+impl Native {
+    pub fn new(iface: Interface, ep_out: u8, ep_in: u8) -> Self {
+        Self {
+            iface,
+            ep_out,
+            ep_in,
+            timeout: crate::ccid::DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Send a PN53x command payload (`D4 cmd data..`), and return the response payload.
+    pub async fn command(&mut self, payload: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut buf = vec![0; MAX_FRAME_LEN];
+        let n = frame::encode(payload, &mut buf).ok_or_else(|| Error::other("PN53x command too long"))?;
+        buf.truncate(n);
+        trace!("native tx: {:02x?}", buf);
+        self.iface.bulk_out(self.ep_out, buf).await.into_result()?;
+
+        let ack = self.read_frame(ACK_TIMEOUT).await?;
+        if !matches!(frame::decode(&ack), Ok((Frame::Ack, _))) {
+            return Err(Error::other("expected ACK"));
+        }
+
+        let resp = self.read_frame(self.timeout).await?;
+        match frame::decode(&resp) {
+            Ok((Frame::Data(d), _)) => Ok(d.to_vec()),
+            Ok((Frame::Error, _)) => Err(Error::other("PN53x syntax error frame")),
+            _ => Err(Error::other("expected response frame")),
+        }
+    }
+
+    /// Read until there's a whole frame. Returns the bytes read, starting with the frame.
+    async fn read_frame(&mut self, timeout: Duration) -> Result<Vec<u8>, Error> {
+        let mut buf = Vec::new();
+        loop {
+            let read = self.iface.bulk_in(self.ep_in, RequestBuffer::new(MAX_FRAME_LEN));
+            let data = match with_timeout(timeout, read).await {
+                Some(r) => r.into_result()?,
+                None => {
+                    warn!("timeout waiting for PN53x frame, aborting command");
+                    // An ACK from the host aborts the running command.
+                    self.iface.bulk_out(self.ep_out, ACK.to_vec()).await.into_result()?;
+                    return Err(Error::new(ErrorKind::TimedOut, "PN53x timeout"));
+                }
+            };
+            trace!("native rx: {:02x?}", data);
+            buf.extend_from_slice(&data);
+
+            match frame::decode(&buf) {
+                Ok(_) => return Ok(buf),
+                Err(frame::DecodeError::Incomplete) if !data.is_empty() => continue,
+                Err(e) => return Err(Error::other(format!("PN53x frame error: {:?}", e))),
+            }
+        }
+    }
+}
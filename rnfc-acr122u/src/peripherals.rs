@@ -0,0 +1,208 @@
+//! ACR122U reader commands: LEDs, buzzer and reader settings.
+//!
+//! These are pseudo-APDUs handled by the reader itself, with `FF 00` as CLA and INS.
+
+use std::io::Error;
+
+use rnfc_pn532::pn53x::{cmd, rf_item};
+
+use crate::{Device, Model, Transport};
+
+/// State of the two LEDs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LedState {
+    pub red: bool,
+    pub green: bool,
+}
+
+/// When the buzzer sounds during a [`LedBuzzer`] blink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Buzzer {
+    #[default]
+    Off = 0x00,
+    /// During the first half of each blink.
+    T1 = 0x01,
+    /// During the second half of each blink.
+    T2 = 0x02,
+    /// During the whole blink.
+    Both = 0x03,
+}
+
+/// LED and buzzer control.
+///
+/// The LEDs blink `repetitions` times, then settle in their final state.
+/// Without blinking, the final state is set right away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LedBuzzer {
+    /// Final state of the red LED, `None` to leave it as it is.
+    pub red: Option<bool>,
+    /// Final state of the green LED, `None` to leave it as it is.
+    pub green: Option<bool>,
+    /// Blink the red LED, starting in this state. `None` to not blink it.
+    pub blink_red: Option<bool>,
+    /// Blink the green LED, starting in this state. `None` to not blink it.
+    pub blink_green: Option<bool>,
+    /// Duration of the first half of each blink, in units of 100ms.
+    pub t1: u8,
+    /// Duration of the second half of each blink, in units of 100ms.
+    pub t2: u8,
+    pub repetitions: u8,
+    pub buzzer: Buzzer,
+}
+
+impl LedBuzzer {
+    /// Set the LEDs, without blinking nor sounding the buzzer.
+    pub fn leds(state: LedState) -> Self {
+        Self {
+            red: Some(state.red),
+            green: Some(state.green),
+            ..Default::default()
+        }
+    }
+
+    /// Sound the buzzer `repetitions` times, for `t1` units of 100ms with `t2` units in between.
+    /// The LEDs are left as they are.
+    pub fn beep(t1: u8, t2: u8, repetitions: u8) -> Self {
+        Self {
+            t1,
+            t2,
+            repetitions,
+            buzzer: Buzzer::T1,
+            ..Default::default()
+        }
+    }
+
+    fn encode(&self) -> [u8; 5] {
+        let bit = |v: Option<bool>, state: u8, mask: u8| match v {
+            None => 0,
+            Some(false) => mask,
+            Some(true) => mask | state,
+        };
+        let control = bit(self.red, 0x01, 0x04)
+            | bit(self.green, 0x02, 0x08)
+            | bit(self.blink_red, 0x10, 0x40)
+            | bit(self.blink_green, 0x20, 0x80);
+        [control, self.t1, self.t2, self.repetitions, self.buzzer as u8]
+    }
+}
+
+/// PICC operating parameter: what the reader does on its own when a card shows up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PiccParams {
+    /// Poll for cards automatically.
+    pub auto_polling: bool,
+    /// Send RATS to ISO14443-4A cards automatically.
+    pub auto_ats: bool,
+    /// Poll every 250ms instead of every 500ms.
+    pub fast_polling: bool,
+    pub felica_424: bool,
+    pub felica_212: bool,
+    pub topaz: bool,
+    pub iso14443b: bool,
+    pub iso14443a: bool,
+}
+
+impl PiccParams {
+    pub fn from_byte(val: u8) -> Self {
+        Self {
+            auto_polling: val & 0x80 != 0,
+            auto_ats: val & 0x40 != 0,
+            fast_polling: val & 0x20 != 0,
+            felica_424: val & 0x10 != 0,
+            felica_212: val & 0x08 != 0,
+            topaz: val & 0x04 != 0,
+            iso14443b: val & 0x02 != 0,
+            iso14443a: val & 0x01 != 0,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        (self.auto_polling as u8) << 7
+            | (self.auto_ats as u8) << 6
+            | (self.fast_polling as u8) << 5
+            | (self.felica_424 as u8) << 4
+            | (self.felica_212 as u8) << 3
+            | (self.topaz as u8) << 2
+            | (self.iso14443b as u8) << 1
+            | (self.iso14443a as u8)
+    }
+}
+
+impl Device {
+    /// Send a pseudo-APDU `FF 00 p1 p2 Lc data`. Returns the whole response.
+    async fn pseudo_apdu(&mut self, p1: u8, p2: u8, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let (Model::Acr122u, Transport::Ccid(ccid)) = (self.model, &mut self.transport) else {
+            return Err(Error::other(format!("{:?} doesn't support ACR122U commands", self.model)));
+        };
+
+        let mut apdu = vec![0xFF, 0x00, p1, p2, data.len() as u8];
+        apdu.extend_from_slice(data);
+        Ok(ccid.xfr_block(&apdu).await?)
+    }
+
+    /// Send a pseudo-APDU answered with `90 xx`. Returns the `xx` byte.
+    async fn pseudo_apdu_status(&mut self, p1: u8, p2: u8, data: &[u8]) -> Result<u8, Error> {
+        let res = self.pseudo_apdu(p1, p2, data).await?;
+        match res[..] {
+            [0x90, val] => Ok(val),
+            _ => Err(Error::other(format!("APDU response code {:02x?}", res))),
+        }
+    }
+
+    /// Control the LEDs and the buzzer. Returns the LED state afterwards.
+    pub async fn led_buzzer(&mut self, control: &LedBuzzer) -> Result<LedState, Error> {
+        let [state, data @ ..] = control.encode();
+        let res = self.pseudo_apdu_status(0x40, state, &data).await?;
+        Ok(LedState {
+            red: res & 0x01 != 0,
+            green: res & 0x02 != 0,
+        })
+    }
+
+    /// Set the LEDs.
+    pub async fn set_leds(&mut self, state: LedState) -> Result<(), Error> {
+        self.led_buzzer(&LedBuzzer::leds(state)).await?;
+        Ok(())
+    }
+
+    /// Enable or disable the buzzer sounding when a card is detected.
+    pub async fn set_buzzer_on_detection(&mut self, enabled: bool) -> Result<(), Error> {
+        let p2 = if enabled { 0xFF } else { 0x00 };
+        self.pseudo_apdu_status(0x52, p2, &[]).await?;
+        Ok(())
+    }
+
+    /// Reader firmware version, like `ACR122U201`.
+    pub async fn reader_firmware_version(&mut self) -> Result<String, Error> {
+        let res = self.pseudo_apdu(0x48, 0x00, &[]).await?;
+        String::from_utf8(res).map_err(|_| Error::other("firmware version is not ASCII"))
+    }
+
+    pub async fn picc_params(&mut self) -> Result<PiccParams, Error> {
+        let val = self.pseudo_apdu_status(0x50, 0x00, &[]).await?;
+        Ok(PiccParams::from_byte(val))
+    }
+
+    /// Set the PICC operating parameter.
+    ///
+    /// Keep automatic polling off while using [`poll`](Self::poll) and the other PN53x commands.
+    pub async fn set_picc_params(&mut self, params: PiccParams) -> Result<(), Error> {
+        self.pseudo_apdu_status(0x51, params.to_byte(), &[]).await?;
+        Ok(())
+    }
+
+    /// Set how long the reader waits for a card response, in units of 5s.
+    /// 0 disables the timeout check, 0xFF waits forever.
+    ///
+    /// This is the reader's own timeout. The host side one is set with [`set_timeout`](Self::set_timeout).
+    pub async fn set_card_timeout(&mut self, units: u8) -> Result<(), Error> {
+        self.pseudo_apdu_status(0x41, units, &[]).await?;
+        Ok(())
+    }
+
+    /// Turn the antenna field on or off.
+    pub async fn set_antenna(&mut self, on: bool) -> Result<(), Error> {
+        self.pn53x_cmd(cmd::RF_CONFIGURATION, &[rf_item::RF_FIELD, on as u8]).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,212 @@
+//! Finding readers, and watching for them being plugged in and out.
+
+use std::fmt;
+use std::io::Error;
+
+use futures_lite::StreamExt;
+use nusb::hotplug::HotplugEvent;
+use nusb::{DeviceId, DeviceInfo};
+
+/// Supported reader models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    /// ACS ACR122U, PN532 behind a CCID interface.
+    Acr122u,
+    /// ACS ACR1252U, takes the same pseudo-APDUs over CCID.
+    Acr1252u,
+    /// PN533-based readers (SCM SCL3711, Sony RC-S360, NXP PN533 demo board), native frames over bulk endpoints.
+    Pn533,
+}
+
+/// USB vendor and product IDs of the supported readers.
+const IDS: &[(u16, u16, Model)] = &[
+    (0x072f, 0x2200, Model::Acr122u),
+    (0x072f, 0x223b, Model::Acr1252u),
+    (0x04cc, 0x2533, Model::Pn533),
+    (0x04e6, 0x5591, Model::Pn533),
+    (0x054c, 0x02e1, Model::Pn533),
+];
+
+impl Model {
+    pub fn from_ids(vendor_id: u16, product_id: u16) -> Option<Self> {
+        IDS.iter()
+            .find(|(v, p, _)| *v == vendor_id && *p == product_id)
+            .map(|(_, _, m)| *m)
+    }
+}
+
+/// A connected reader, as found by [`list`] or a [`Watcher`].
+#[derive(Debug, Clone)]
+pub struct ReaderInfo {
+    pub model: Model,
+    pub(crate) info: DeviceInfo,
+}
+
+impl ReaderInfo {
+    fn from_device(info: DeviceInfo) -> Option<Self> {
+        let model = Model::from_ids(info.vendor_id(), info.product_id())?;
+        Some(Self { model, info })
+    }
+
+    /// Opaque ID, stays the same while the reader is connected.
+    pub fn id(&self) -> DeviceId {
+        self.info.id()
+    }
+
+    pub fn bus_number(&self) -> u8 {
+        self.info.bus_number()
+    }
+
+    pub fn device_address(&self) -> u8 {
+        self.info.device_address()
+    }
+
+    /// Where the reader is plugged in, e.g. `1-2.3` (bus 1, hub port 2, port 3) on Linux.
+    /// Unlike the device address, stays the same when the reader is plugged again in the same port.
+    ///
+    /// `None` on platforms where nusb doesn't report the location.
+    pub fn port_location(&self) -> Option<String> {
+        port_location(&self.info)
+    }
+
+    pub fn serial_number(&self) -> Option<&str> {
+        self.info.serial_number()
+    }
+
+    pub fn product_string(&self) -> Option<&str> {
+        self.info.product_string()
+    }
+
+    /// The underlying USB device info.
+    pub fn usb(&self) -> &DeviceInfo {
+        &self.info
+    }
+}
+
+impl fmt::Display for ReaderInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port_location() {
+            Some(location) => write!(f, "{:?} at {}", self.model, location)?,
+            None => write!(
+                f,
+                "{:?} on bus {} address {}",
+                self.model,
+                self.bus_number(),
+                self.device_address()
+            )?,
+        }
+        if let Some(serial) = self.serial_number() {
+            write!(f, ", serial {}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn port_location(info: &DeviceInfo) -> Option<String> {
+    sysfs_port(info.sysfs_path())
+}
+
+#[cfg(target_os = "windows")]
+fn port_location(info: &DeviceInfo) -> Option<String> {
+    Some(format!(
+        "{}#{}",
+        info.parent_instance_id().to_string_lossy(),
+        info.port_number()
+    ))
+}
+
+#[cfg(target_os = "macos")]
+fn port_location(info: &DeviceInfo) -> Option<String> {
+    Some(format!("{:08x}", info.location_id()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "windows", target_os = "macos")))]
+fn port_location(_info: &DeviceInfo) -> Option<String> {
+    None
+}
+
+/// The sysfs device name is the port location: `<bus>-<port>[.<port>...]`.
+/// Root hubs are named `usb<bus>` instead, and have no port.
+#[cfg_attr(not(any(target_os = "linux", target_os = "android")), allow(unused))]
+fn sysfs_port(path: &std::path::Path) -> Option<String> {
+    let name = path.file_name()?.to_str()?;
+    name.contains('-').then(|| name.to_string())
+}
+
+/// List the connected readers.
+pub fn list() -> Result<Vec<ReaderInfo>, Error> {
+    Ok(nusb::list_devices()?.filter_map(ReaderInfo::from_device).collect())
+}
+
+#[derive(Debug, Clone)]
+pub enum ReaderEvent {
+    Connected(ReaderInfo),
+    Disconnected(ReaderInfo),
+}
+
+/// Watches for readers being connected and disconnected.
+pub struct Watcher {
+    watch: nusb::hotplug::HotplugWatch,
+    readers: Vec<ReaderInfo>,
+}
+
+impl Watcher {
+    /// Start watching. Readers already connected are in [`readers`](Self::readers),
+    /// events are only reported for changes after this.
+    pub fn new() -> Result<Self, Error> {
+        // Start watching before listing, so nothing connected in between is missed.
+        let watch = nusb::watch_devices()?;
+        let readers = list()?;
+        Ok(Self { watch, readers })
+    }
+
+    /// The readers currently connected.
+    pub fn readers(&self) -> &[ReaderInfo] {
+        &self.readers
+    }
+
+    /// Wait for the next reader to be connected or disconnected.
+    ///
+    /// Returns `None` if the OS stops reporting events.
+    pub async fn next(&mut self) -> Option<ReaderEvent> {
+        loop {
+            match self.watch.next().await? {
+                HotplugEvent::Connected(info) => {
+                    let Some(reader) = ReaderInfo::from_device(info) else {
+                        continue;
+                    };
+                    // Already listed if it was connected while starting the watch.
+                    if self.readers.iter().any(|r| r.id() == reader.id()) {
+                        continue;
+                    }
+                    self.readers.push(reader.clone());
+                    return Some(ReaderEvent::Connected(reader));
+                }
+                HotplugEvent::Disconnected(id) => {
+                    let Some(i) = self.readers.iter().position(|r| r.id() == id) else {
+                        continue;
+                    };
+                    return Some(ReaderEvent::Disconnected(self.readers.remove(i)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn sysfs_port_location() {
+        assert_eq!(sysfs_port(Path::new("/sys/bus/usb/devices/1-2.3")).as_deref(), Some("1-2.3"));
+        assert_eq!(
+            sysfs_port(Path::new("/sys/devices/pci0000:00/0000:00:14.0/usb3/3-1")).as_deref(),
+            Some("3-1")
+        );
+        assert_eq!(sysfs_port(Path::new("/sys/bus/usb/devices/usb1")), None);
+    }
+}
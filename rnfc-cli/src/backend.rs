@@ -0,0 +1,68 @@
+//! Readers the tool can use, picked with `--reader`.
+//!
+//! A backend finds one ISO14443A card at a time, and gives access to it with both raw
+//! frames and ISO-DEP. To add one, implement [`Backend`] and add it to [`ReaderKind`].
+
+use rnfc_traits::{iso14443a, iso_dep};
+
+use crate::Error;
+
+/// A selected ISO14443A card.
+pub trait Tag: iso14443a::Reader + iso_dep::Reader {
+    /// ATS, including the TL byte. Empty if the card is not ISO-DEP.
+    fn ats(&self) -> &[u8];
+}
+
+pub trait Backend {
+    type Tag<'a>: Tag
+    where
+        Self: 'a;
+
+    /// Select the card in the field.
+    async fn poll(&mut self) -> Result<Self::Tag<'_>, Error>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReaderKind {
+    /// ACR122U, ACR1252U or PN533 over USB.
+    Acr122u,
+}
+
+impl ReaderKind {
+    pub const ALL: &'static [(&'static str, ReaderKind)] = &[("acr122u", ReaderKind::Acr122u)];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().find(|(n, _)| *n == name).map(|(_, kind)| *kind)
+    }
+}
+
+pub struct Acr122u {
+    dev: rnfc_acr122u::Device,
+}
+
+impl Acr122u {
+    /// Open the reader at `index` in the list of connected readers.
+    pub async fn open(index: usize) -> Result<Self, Error> {
+        let readers = rnfc_acr122u::list()?;
+        let reader = readers
+            .get(index)
+            .ok_or_else(|| format!("reader {} not found, {} connected", index, readers.len()))?;
+        log::info!("using {}", reader);
+        let dev = rnfc_acr122u::Device::open(reader).await?;
+        Ok(Self { dev })
+    }
+}
+
+impl Backend for Acr122u {
+    type Tag<'a> = rnfc_acr122u::Card<'a>;
+
+    async fn poll(&mut self) -> Result<Self::Tag<'_>, Error> {
+        Ok(self.dev.poll().await?)
+    }
+}
+
+impl Tag for rnfc_acr122u::Card<'_> {
+    fn ats(&self) -> &[u8] {
+        &self.ats
+    }
+}
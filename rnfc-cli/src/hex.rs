@@ -0,0 +1,57 @@
+//! Hex input and output, for APDUs and NDEF messages.
+
+use std::fmt;
+
+/// Parse hex digits, ignoring whitespace and `:` separators.
+pub fn parse(s: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = s.bytes().filter(|b| !b.is_ascii_whitespace() && *b != b':').collect();
+    if digits.len() % 2 != 0 {
+        return Err(format!("odd number of hex digits in {:?}", s));
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|_| format!("bad hex in {:?}", s))?;
+            u8::from_str_radix(pair, 16).map_err(|_| format!("bad hex {:?} in {:?}", pair, s))
+        })
+        .collect()
+}
+
+/// Formats bytes as uppercase hex, separated by spaces.
+pub struct Hex<'a>(pub &'a [u8]);
+
+impl fmt::Display for Hex<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, b) in self.0.iter().enumerate() {
+            if i != 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:02X}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_separators() {
+        assert_eq!(parse("00 a4 04:00").unwrap(), [0x00, 0xA4, 0x04, 0x00]);
+        assert_eq!(parse("00A40400").unwrap(), [0x00, 0xA4, 0x04, 0x00]);
+        assert_eq!(parse("").unwrap(), []);
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(parse("0").is_err());
+        assert!(parse("0g").is_err());
+        assert!(parse("é0").is_err());
+    }
+
+    #[test]
+    fn format() {
+        assert_eq!(Hex(&[0x90, 0x00, 0x0a]).to_string(), "90 00 0A");
+    }
+}
@@ -0,0 +1,286 @@
+#![allow(async_fn_in_trait)]
+
+mod backend;
+mod hex;
+mod type2;
+mod type4;
+
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use rnfc_traits::iso14443a::Reader as _;
+use rnfc_traits::iso_dep;
+
+use crate::backend::{Acr122u, Backend, ReaderKind, Tag};
+use crate::hex::Hex;
+
+type Error = Box<dyn std::error::Error>;
+
+const USAGE: &str = "\
+Usage: rnfc [OPTIONS] COMMAND
+
+Commands:
+  scan                         Print UID, ATQA, SAK and ATS of the card
+  apdu [APDU...]               Send the APDUs given in hex, or read them from stdin, one per line
+  apdu --script FILE           Send the APDUs in FILE, one per line
+  dump FILE                    Read the whole memory of a Type 2 tag into FILE
+  ndef read [FILE]             Read the NDEF message into FILE, or print it in hex
+  ndef write FILE              Write the NDEF message in FILE
+  ndef write --hex HEX         Write the NDEF message given in hex
+
+Options:
+  --reader NAME                Reader backend: acr122u (default)
+  --device INDEX               Which of the connected readers to use, default 0
+  -h, --help                   Print this help
+
+Set RUST_LOG=debug to see what goes over the air.";
+
+struct Args {
+    reader: ReaderKind,
+    device: usize,
+    command: Command,
+}
+
+enum Command {
+    Scan,
+    Apdu { apdus: Vec<Vec<u8>>, script: Option<PathBuf> },
+    Dump { file: PathBuf },
+    NdefRead { file: Option<PathBuf> },
+    NdefWrite { msg: NdefSource },
+}
+
+enum NdefSource {
+    File(PathBuf),
+    Hex(Vec<u8>),
+}
+
+impl Args {
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut reader = ReaderKind::Acr122u;
+        let mut device = 0;
+        let mut rest = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--reader" => {
+                    let name = args.next().ok_or("--reader needs a name")?;
+                    reader = ReaderKind::from_name(&name).ok_or_else(|| {
+                        let names: Vec<_> = ReaderKind::ALL.iter().map(|(n, _)| *n).collect();
+                        format!("unknown reader {:?}, expected one of {}", name, names.join(", "))
+                    })?;
+                }
+                "--device" => {
+                    let index = args.next().ok_or("--device needs an index")?;
+                    device = index.parse().map_err(|_| format!("bad device index {:?}", index))?;
+                }
+                _ => rest.push(arg),
+            }
+        }
+
+        let mut rest = rest.into_iter();
+        let command = match rest.next().as_deref() {
+            Some("scan") => Command::Scan,
+            Some("apdu") => {
+                let mut apdus = Vec::new();
+                let mut script = None;
+                while let Some(arg) = rest.next() {
+                    match arg.as_str() {
+                        "--script" => script = Some(rest.next().ok_or("--script needs a file")?.into()),
+                        _ => apdus.push(hex::parse(&arg)?),
+                    }
+                }
+                if script.is_some() && !apdus.is_empty() {
+                    return Err("give either APDUs or --script, not both".into());
+                }
+                Command::Apdu { apdus, script }
+            }
+            Some("dump") => Command::Dump {
+                file: rest.next().ok_or("dump needs a file")?.into(),
+            },
+            Some("ndef") => match rest.next().as_deref() {
+                Some("read") => Command::NdefRead {
+                    file: rest.next().map(PathBuf::from),
+                },
+                Some("write") => match rest.next().as_deref() {
+                    Some("--hex") => Command::NdefWrite {
+                        msg: NdefSource::Hex(hex::parse(&rest.next().ok_or("--hex needs a message")?)?),
+                    },
+                    Some(file) => Command::NdefWrite {
+                        msg: NdefSource::File(file.into()),
+                    },
+                    None => return Err("ndef write needs a file".into()),
+                },
+                _ => return Err("expected ndef read or ndef write".into()),
+            },
+            Some(cmd) => return Err(format!("unknown command {:?}", cmd)),
+            None => return Err("no command given".into()),
+        };
+        if let Some(arg) = rest.next() {
+            return Err(format!("unexpected argument {:?}", arg));
+        }
+
+        Ok(Self { reader, device, command })
+    }
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|a| a == "-h" || a == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let args = match Args::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let res = match args.reader {
+        ReaderKind::Acr122u => match Acr122u::open(args.device).await {
+            Ok(mut backend) => run(&mut backend, args.command).await,
+            Err(e) => Err(e),
+        },
+    };
+    match res {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run<B: Backend>(backend: &mut B, command: Command) -> Result<(), Error> {
+    let mut tag = backend.poll().await?;
+
+    match command {
+        Command::Scan => {
+            println!("UID:  {}", Hex(tag.uid()));
+            println!("ATQA: {}", Hex(&tag.atqa()));
+            println!("SAK:  {:02X}", tag.sak());
+            if !tag.ats().is_empty() {
+                println!("ATS:  {}", Hex(tag.ats()));
+            }
+        }
+        Command::Apdu { apdus, script } => {
+            check_iso_dep(&tag)?;
+            if let Some(script) = script {
+                let file = std::io::BufReader::new(std::fs::File::open(&script)?);
+                apdu_lines(&mut tag, file, false).await?;
+            } else if apdus.is_empty() {
+                let stdin = std::io::stdin();
+                let interactive = stdin.is_terminal();
+                apdu_lines(&mut tag, stdin.lock(), interactive).await?;
+            } else {
+                for apdu in apdus {
+                    send_apdu(&mut tag, &apdu, true).await?;
+                }
+            }
+        }
+        Command::Dump { file } => {
+            if !type2::is_type2(tag.sak()) {
+                return Err(format!("can't dump this card, only Type 2 tags are supported (SAK {:02X})", tag.sak()).into());
+            }
+            let mem = type2::dump(&mut tag).await?;
+            std::fs::write(&file, &mem)?;
+            println!("{} bytes written to {}", mem.len(), file.display());
+        }
+        Command::NdefRead { file } => {
+            let msg = if !tag.ats().is_empty() {
+                type4::read_ndef(&mut tag).await?
+            } else if type2::is_type2(tag.sak()) {
+                type2::read_ndef(&mut tag).await?
+            } else {
+                return Err(unsupported_ndef(&tag));
+            };
+            match file {
+                Some(file) => {
+                    std::fs::write(&file, &msg)?;
+                    println!("{} bytes written to {}", msg.len(), file.display());
+                }
+                None => println!("{}", Hex(&msg)),
+            }
+        }
+        Command::NdefWrite { msg } => {
+            let msg = match msg {
+                NdefSource::File(file) => std::fs::read(file)?,
+                NdefSource::Hex(msg) => msg,
+            };
+            if !tag.ats().is_empty() {
+                type4::write_ndef(&mut tag, &msg).await?
+            } else if type2::is_type2(tag.sak()) {
+                type2::write_ndef(&mut tag, &msg).await?
+            } else {
+                return Err(unsupported_ndef(&tag));
+            }
+            println!("{} bytes written", msg.len());
+        }
+    }
+    Ok(())
+}
+
+fn check_iso_dep<T: Tag>(tag: &T) -> Result<(), Error> {
+    if tag.ats().is_empty() {
+        return Err(format!("card doesn't support ISO-DEP (SAK {:02X})", tag.sak()).into());
+    }
+    Ok(())
+}
+
+fn unsupported_ndef<T: Tag>(tag: &T) -> Error {
+    format!("no NDEF support for this card (SAK {:02X})", tag.sak()).into()
+}
+
+/// Send the APDUs in `lines`, one per line. Empty lines and `#` comments are skipped.
+///
+/// When `interactive`, prompt for each line and keep going after errors. Otherwise,
+/// echo each APDU and stop at the first error.
+async fn apdu_lines<T: Tag>(tag: &mut T, lines: impl BufRead, interactive: bool) -> Result<(), Error> {
+    let prompt = || {
+        if interactive {
+            print!("> ");
+            let _ = std::io::stdout().flush();
+        }
+    };
+
+    prompt();
+    for line in lines.lines() {
+        let line = line?;
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if !line.is_empty() {
+            let res = match hex::parse(line) {
+                Ok(apdu) => send_apdu(tag, &apdu, !interactive).await,
+                Err(e) => Err(e.into()),
+            };
+            match res {
+                Ok(()) => {}
+                Err(e) if interactive => eprintln!("error: {}", e),
+                Err(e) => return Err(e),
+            }
+        }
+        prompt();
+    }
+    if interactive {
+        println!();
+    }
+    Ok(())
+}
+
+/// Send one APDU, and print the response. If `echo`, print the APDU too.
+async fn send_apdu<T: Tag>(tag: &mut T, apdu: &[u8], echo: bool) -> Result<(), Error> {
+    if echo {
+        println!("> {}", Hex(apdu));
+    }
+    let mut rx = vec![0; 65538];
+    let n = iso_dep::Reader::transceive(tag, apdu, &mut rx)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    println!("< {}", Hex(&rx[..n]));
+    Ok(())
+}
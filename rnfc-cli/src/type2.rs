@@ -0,0 +1,233 @@
+//! NFC Forum Type 2 tags: MIFARE Ultralight, NTAG.
+//!
+//! Memory is in 4-byte pages. `READ` returns 4 pages at once, `WRITE` writes one.
+//! Page 3 is the capability container, the data area starts at page 4.
+
+use rnfc_traits::iso14443a;
+
+use crate::Error;
+
+const CMD_READ: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA2;
+/// 5ms, the longest a `WRITE` can take.
+const TIMEOUT_1FC: u32 = 67_800;
+
+const PAGE_LEN: usize = 4;
+/// First page of the data area.
+const DATA_PAGE: u8 = 4;
+/// Max pages, 256 with 1-byte page addresses.
+const MAX_PAGES: usize = 256;
+
+const CC_MAGIC: u8 = 0xE1;
+
+const TLV_NULL: u8 = 0x00;
+const TLV_NDEF: u8 = 0x03;
+const TLV_TERMINATOR: u8 = 0xFE;
+
+/// A Type 2 tag: SAK says it's neither ISO-DEP nor MIFARE Classic.
+pub fn is_type2(sak: u8) -> bool {
+    sak == 0x00
+}
+
+/// Read 4 pages starting at `page`.
+async fn read<T: iso14443a::Reader>(tag: &mut T, page: u8) -> Result<[u8; 16], Error> {
+    let mut rx = [0; 16];
+    let n = tag
+        .transceive(&[CMD_READ, page], &mut rx, TIMEOUT_1FC)
+        .await
+        .map_err(|e| format!("READ page {} failed: {:?}", page, e))?;
+    if n != rx.len() {
+        return Err(format!("READ page {}: got {} bytes, NAK?", page, n).into());
+    }
+    Ok(rx)
+}
+
+/// Write one page, and read it back to check it was written.
+///
+/// The 4-bit ACK doesn't make it through all readers, so the read back is what counts.
+async fn write<T: iso14443a::Reader>(tag: &mut T, page: u8, data: [u8; 4]) -> Result<(), Error> {
+    let tx = [CMD_WRITE, page, data[0], data[1], data[2], data[3]];
+    if let Err(e) = tag.transceive(&tx, &mut [0; 1], TIMEOUT_1FC).await {
+        log::debug!("WRITE page {}: {:?}", page, e);
+    }
+    if read(tag, page).await?[..PAGE_LEN] != data {
+        return Err(format!("WRITE page {} failed", page).into());
+    }
+    Ok(())
+}
+
+/// Read the whole memory, until the tag NAKs or the addresses wrap around.
+pub async fn dump<T: iso14443a::Reader>(tag: &mut T) -> Result<Vec<u8>, Error> {
+    let mut mem = Vec::new();
+    for page in (0..MAX_PAGES).step_by(4) {
+        let data = match read(tag, page as u8).await {
+            Ok(data) => data,
+            // The tag only answers a READ past the end with a NAK.
+            Err(e) if page != 0 => {
+                log::debug!("end of memory at page {}: {}", page, e);
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        // MIFARE Ultralight wraps around to page 0 instead.
+        if page != 0 && data == mem[..16] {
+            log::debug!("end of memory at page {}, wrapped around", page);
+            break;
+        }
+        mem.extend_from_slice(&data);
+    }
+    Ok(mem)
+}
+
+/// Capability container, page 3.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cc {
+    pub version: u8,
+    /// Size of the data area in bytes.
+    pub data_len: usize,
+    pub writable: bool,
+}
+
+impl Cc {
+    pub fn parse(page: &[u8]) -> Result<Self, Error> {
+        match *page {
+            [CC_MAGIC, version, size, access, ..] => Ok(Self {
+                version,
+                data_len: size as usize * 8,
+                writable: access & 0x0F == 0,
+            }),
+            _ => Err(format!("not formatted for NDEF, CC {:02x?}", page).into()),
+        }
+    }
+}
+
+/// Read the data area, as described by the capability container.
+async fn read_data<T: iso14443a::Reader>(tag: &mut T) -> Result<(Cc, Vec<u8>), Error> {
+    let cc = Cc::parse(&read(tag, 3).await?[..PAGE_LEN])?;
+    log::debug!("{:?}", cc);
+
+    let mut data = Vec::with_capacity(cc.data_len + 16);
+    while data.len() < cc.data_len {
+        let page = DATA_PAGE as usize + data.len() / PAGE_LEN;
+        data.extend_from_slice(&read(tag, page as u8).await?);
+    }
+    data.truncate(cc.data_len);
+    Ok((cc, data))
+}
+
+/// Find the NDEF message TLV in the data area. Returns `None` if there's none.
+pub fn find_ndef(data: &[u8]) -> Result<Option<&[u8]>, Error> {
+    let mut pos = 0;
+    while let Some(&typ) = data.get(pos) {
+        pos += 1;
+        match typ {
+            TLV_NULL => continue,
+            TLV_TERMINATOR => break,
+            _ => {}
+        }
+
+        let len = match *data.get(pos).ok_or("truncated TLV")? {
+            0xFF => {
+                let len = data.get(pos + 1..pos + 3).ok_or("truncated TLV")?;
+                pos += 3;
+                u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            len => {
+                pos += 1;
+                len as usize
+            }
+        };
+        let value = data.get(pos..pos + len).ok_or("truncated TLV")?;
+        if typ == TLV_NDEF {
+            return Ok(Some(value));
+        }
+        pos += len;
+    }
+    Ok(None)
+}
+
+/// Build the NDEF message TLV for `msg`, followed by a terminator TLV.
+pub fn ndef_tlv(msg: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut tlv = vec![TLV_NDEF];
+    match msg.len() {
+        len @ 0..=0xFE => tlv.push(len as u8),
+        len @ 0xFF..=0xFFFE => {
+            tlv.push(0xFF);
+            tlv.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => return Err(format!("NDEF message too long: {} bytes", len).into()),
+    }
+    tlv.extend_from_slice(msg);
+    tlv.push(TLV_TERMINATOR);
+    Ok(tlv)
+}
+
+pub async fn read_ndef<T: iso14443a::Reader>(tag: &mut T) -> Result<Vec<u8>, Error> {
+    let (_, data) = read_data(tag).await?;
+    let msg = find_ndef(&data)?.ok_or("no NDEF message on the tag")?;
+    Ok(msg.to_vec())
+}
+
+pub async fn write_ndef<T: iso14443a::Reader>(tag: &mut T, msg: &[u8]) -> Result<(), Error> {
+    let cc = Cc::parse(&read(tag, 3).await?[..PAGE_LEN])?;
+    if !cc.writable {
+        return Err("tag is read-only".into());
+    }
+    let tlv = ndef_tlv(msg)?;
+    if tlv.len() > cc.data_len {
+        return Err(format!("NDEF message too long: {} bytes, room for {}", tlv.len(), cc.data_len).into());
+    }
+
+    for (i, chunk) in tlv.chunks(PAGE_LEN).enumerate() {
+        let mut page = [0; PAGE_LEN];
+        page[..chunk.len()].copy_from_slice(chunk);
+        write(tag, DATA_PAGE + i as u8, page).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cc() {
+        let cc = Cc::parse(&[0xE1, 0x10, 0x12, 0x00]).unwrap();
+        assert_eq!(
+            cc,
+            Cc {
+                version: 0x10,
+                data_len: 144,
+                writable: true
+            }
+        );
+        assert!(!Cc::parse(&[0xE1, 0x10, 0x12, 0x0F]).unwrap().writable);
+        assert!(Cc::parse(&[0x00, 0x00, 0x00, 0x00]).is_err());
+    }
+
+    #[test]
+    fn find_ndef_after_other_tlvs() {
+        // NULL, lock control TLV, NDEF, terminator.
+        let data = [0x00, 0x01, 0x03, 0xA0, 0x0C, 0x34, 0x03, 0x02, 0xD0, 0x00, 0xFE, 0x00];
+        assert_eq!(find_ndef(&data).unwrap(), Some(&[0xD0, 0x00][..]));
+    }
+
+    #[test]
+    fn find_ndef_none() {
+        assert_eq!(find_ndef(&[0xFE, 0x03, 0x01, 0x00]).unwrap(), None);
+        assert_eq!(find_ndef(&[0x00, 0x00]).unwrap(), None);
+        assert!(find_ndef(&[0x03, 0x05, 0xD0]).is_err());
+    }
+
+    #[test]
+    fn tlv_round_trip() {
+        let short = [0xD0, 0x00];
+        assert_eq!(ndef_tlv(&short).unwrap(), [0x03, 0x02, 0xD0, 0x00, 0xFE]);
+        assert_eq!(find_ndef(&ndef_tlv(&short).unwrap()).unwrap(), Some(&short[..]));
+
+        let long = [0xAA; 300];
+        let tlv = ndef_tlv(&long).unwrap();
+        assert_eq!(tlv[..4], [0x03, 0xFF, 0x01, 0x2C]);
+        assert_eq!(find_ndef(&tlv).unwrap(), Some(&long[..]));
+    }
+}
@@ -0,0 +1,151 @@
+//! NFC Forum Type 4 tags: NDEF in a file of an ISO7816-4 application, over ISO-DEP.
+
+use rnfc_traits::iso_dep;
+
+use crate::hex::Hex;
+use crate::Error;
+
+const NDEF_AID: [u8; 7] = [0xD2, 0x76, 0x00, 0x00, 0x85, 0x01, 0x01];
+const CC_FILE: [u8; 2] = [0xE1, 0x03];
+/// NDEF file control TLV, in the capability container.
+const TLV_NDEF_FILE: u8 = 0x04;
+
+const SW_OK: [u8; 2] = [0x90, 0x00];
+
+/// Send an APDU, and check the status word. Returns the response data.
+pub async fn apdu<T: iso_dep::Reader>(tag: &mut T, cmd: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut rx = [0; 258];
+    let n = tag
+        .transceive(cmd, &mut rx)
+        .await
+        .map_err(|e| format!("APDU {} failed: {:?}", Hex(cmd), e))?;
+    match rx[..n].split_last_chunk::<2>() {
+        Some((data, &SW_OK)) => Ok(data.to_vec()),
+        Some((_, sw)) => Err(format!("APDU {}: status {}", Hex(cmd), Hex(sw)).into()),
+        None => Err(format!("APDU {}: response too short", Hex(cmd)).into()),
+    }
+}
+
+async fn select_file<T: iso_dep::Reader>(tag: &mut T, id: [u8; 2]) -> Result<(), Error> {
+    apdu(tag, &[0x00, 0xA4, 0x00, 0x0C, 0x02, id[0], id[1]]).await?;
+    Ok(())
+}
+
+async fn read_binary<T: iso_dep::Reader>(tag: &mut T, offset: usize, len: usize) -> Result<Vec<u8>, Error> {
+    let [hi, lo] = (offset as u16).to_be_bytes();
+    apdu(tag, &[0x00, 0xB0, hi, lo, len as u8]).await
+}
+
+async fn update_binary<T: iso_dep::Reader>(tag: &mut T, offset: usize, data: &[u8]) -> Result<(), Error> {
+    let [hi, lo] = (offset as u16).to_be_bytes();
+    let mut cmd = vec![0x00, 0xD6, hi, lo, data.len() as u8];
+    cmd.extend_from_slice(data);
+    apdu(tag, &cmd).await?;
+    Ok(())
+}
+
+/// Capability container file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cc {
+    /// Max data in a `READ BINARY` response.
+    pub mle: usize,
+    /// Max data in an `UPDATE BINARY` command.
+    pub mlc: usize,
+    pub file_id: [u8; 2],
+    /// Max size of the NDEF file, including the 2-byte length.
+    pub max_len: usize,
+    pub writable: bool,
+}
+
+impl Cc {
+    pub fn parse(cc: &[u8]) -> Result<Self, Error> {
+        match *cc {
+            [_, _, _, mle0, mle1, mlc0, mlc1, TLV_NDEF_FILE, 6, id0, id1, max0, max1, _read, write, ..] => Ok(Self {
+                // Short APDUs only.
+                mle: (u16::from_be_bytes([mle0, mle1]) as usize).min(0xFF),
+                mlc: (u16::from_be_bytes([mlc0, mlc1]) as usize).min(0xFF),
+                file_id: [id0, id1],
+                max_len: u16::from_be_bytes([max0, max1]) as usize,
+                writable: write == 0x00,
+            }),
+            _ => Err(format!("unsupported capability container {}", Hex(cc)).into()),
+        }
+    }
+}
+
+/// Select the NDEF application and the NDEF file. Returns the capability container.
+async fn select_ndef<T: iso_dep::Reader>(tag: &mut T) -> Result<Cc, Error> {
+    let mut cmd = vec![0x00, 0xA4, 0x04, 0x00, NDEF_AID.len() as u8];
+    cmd.extend_from_slice(&NDEF_AID);
+    cmd.push(0x00);
+    apdu(tag, &cmd).await.map_err(|e| format!("no NDEF application: {}", e))?;
+
+    select_file(tag, CC_FILE).await?;
+    let cc = Cc::parse(&read_binary(tag, 0, 15).await?)?;
+    log::debug!("{:?}", cc);
+
+    select_file(tag, cc.file_id).await?;
+    Ok(cc)
+}
+
+pub async fn read_ndef<T: iso_dep::Reader>(tag: &mut T) -> Result<Vec<u8>, Error> {
+    let cc = select_ndef(tag).await?;
+    let nlen = read_binary(tag, 0, 2).await?;
+    let len = match *nlen {
+        [hi, lo] => u16::from_be_bytes([hi, lo]) as usize,
+        _ => return Err("bad NDEF length".into()),
+    };
+
+    let mut msg = Vec::with_capacity(len);
+    while msg.len() < len {
+        let chunk = read_binary(tag, 2 + msg.len(), (len - msg.len()).min(cc.mle)).await?;
+        if chunk.is_empty() {
+            return Err("NDEF file shorter than its length".into());
+        }
+        msg.extend_from_slice(&chunk);
+    }
+    msg.truncate(len);
+    Ok(msg)
+}
+
+pub async fn write_ndef<T: iso_dep::Reader>(tag: &mut T, msg: &[u8]) -> Result<(), Error> {
+    let cc = select_ndef(tag).await?;
+    if !cc.writable {
+        return Err("tag is read-only".into());
+    }
+    if 2 + msg.len() > cc.max_len {
+        return Err(format!("NDEF message too long: {} bytes, room for {}", msg.len(), cc.max_len - 2).into());
+    }
+
+    // Zero the length while writing, so a torn write leaves an empty message.
+    update_binary(tag, 0, &[0, 0]).await?;
+    for (i, chunk) in msg.chunks(cc.mlc).enumerate() {
+        update_binary(tag, 2 + i * cc.mlc, chunk).await?;
+    }
+    update_binary(tag, 0, &(msg.len() as u16).to_be_bytes()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn cc() {
+        let cc = Cc::parse(&[
+            0x00, 0x0F, 0x20, 0x00, 0x3B, 0x00, 0x34, 0x04, 0x06, 0xE1, 0x04, 0x00, 0x32, 0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(
+            cc,
+            Cc {
+                mle: 0x3B,
+                mlc: 0x34,
+                file_id: [0xE1, 0x04],
+                max_len: 0x32,
+                writable: true,
+            }
+        );
+        assert!(Cc::parse(&[0x00, 0x0F, 0x20]).is_err());
+    }
+}
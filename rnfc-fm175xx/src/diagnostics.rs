@@ -0,0 +1,46 @@
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use rnfc_traits::diagnostics::{ChipId, Diagnostics, Rssi};
+
+use crate::{Error, Fm175xx, Interface};
+
+impl<I, NpdPin, IrqPin> Diagnostics for Fm175xx<I, NpdPin, IrqPin>
+where
+    I: Interface,
+    NpdPin: OutputPin,
+    IrqPin: InputPin + Wait,
+{
+    type Error = Error<I::Error>;
+
+    /// Reads the `version` register. The high nibble is the chip type, the low nibble the revision.
+    async fn chip_id(&mut self) -> Result<ChipId, Self::Error> {
+        self.on().await?;
+        let ver = self.regs().version().read()?;
+        self.off()?;
+
+        Ok(ChipId {
+            chip_type: ver >> 4,
+            revision: ver & 0x0F,
+        })
+    }
+
+    async fn vdd_mv(&mut self) -> Result<Option<u32>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn regulator(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn amplitude(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn phase(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    fn last_rssi(&self) -> Option<Rssi> {
+        None
+    }
+}
@@ -0,0 +1,218 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use rnfc_traits::nfcf_ll as ll;
+use rnfc_traits::nfcf_ll::{SensfRes, TimeSlots};
+
+use crate::fmt::Bytes;
+pub use crate::iso14443a::Error;
+use crate::{regs, Fm175xx, Interface, TrxOpts};
+
+/// Max FeliCa frame length, including the length byte.
+const MAX_FRAME_LEN: usize = 255;
+
+/// Max SENSF_RES frame length, including the length byte and the error status byte.
+const MAX_SENSF_RES_LEN: usize = 21;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FelicaBitRate {
+    Kbps212,
+    Kbps424,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FelicaConfig {
+    pub bit_rate: FelicaBitRate,
+}
+
+impl Default for FelicaConfig {
+    fn default() -> Self {
+        Self {
+            bit_rate: FelicaBitRate::Kbps212,
+        }
+    }
+}
+
+/// An FM175xx chip enabled in FeliCa mode.
+pub struct Felica<'d, I: Interface, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    inner: &'d mut Fm175xx<I, NpdPin, IrqPin>,
+}
+
+impl<I: Interface, NpdPin, IrqPin> Fm175xx<I, NpdPin, IrqPin>
+where
+    NpdPin: OutputPin,
+    IrqPin: InputPin + Wait,
+{
+    /// Turn on the field in FeliCa mode.
+    ///
+    /// Manchester coding, preamble, sync code and CRC are handled by the chip.
+    pub async fn start_felica(&mut self, config: FelicaConfig) -> Result<Felica<I, NpdPin, IrqPin>, Error<I::Error>> {
+        self.on().await?;
+
+        let speed = match config.bit_rate {
+            FelicaBitRate::Kbps212 => regs::Speed::_212KBPS,
+            FelicaBitRate::Kbps424 => regs::Speed::_424KBPS,
+        };
+        self.regs().txmode().write(|w| {
+            w.set_framing(regs::Framing::FELICA);
+            w.set_speed(speed);
+        })?;
+        self.regs().rxmode().write(|w| {
+            w.set_framing(regs::Framing::FELICA);
+            w.set_speed(speed);
+        })?;
+        self.regs().mode().modify(|w| w.set_crcpreset(0b00))?; // 0x0000
+        self.regs().control().write(|w| {
+            w.set_initiator(true);
+        })?;
+        let rf_config = self.config;
+        self.regs().rfcfg().write(|w| {
+            w.set_rxgain(rf_config.rx_gain);
+        })?;
+        self.regs().rxtreshold().write(|w| {
+            w.set_collevel(rf_config.colllevel);
+            w.set_minlevel(rf_config.minlevel);
+        })?;
+        // 10% ASK
+        self.regs().txauto().write(|w| {
+            w.set_force100ask(false);
+        })?;
+
+        self.rf_on()?;
+        self.regs().modgsp().write(|w| {
+            w.set_modgsp(rf_config.p_drive_mod_ask10);
+        })?;
+
+        // Field on guard time. NFC Forum requires at least 20ms for NFC-F.
+        Timer::after(Duration::from_millis(20)).await;
+
+        Ok(Felica { inner: self })
+    }
+}
+
+impl<'d, I, NpdPin, IrqPin> Drop for Felica<'d, I, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    fn drop(&mut self) {
+        if self.inner.off().is_err() {
+            warn!("Failed to set field off on Felica drop");
+        }
+    }
+}
+
+/// Check the length byte of a received frame.
+fn check_len<T>(frame: &[u8]) -> Result<(), Error<T>> {
+    match frame.first() {
+        None => Err(Error::Protocol),
+        Some(&len) if len as usize != frame.len() => {
+            debug!("bad length byte: {} got {}", len, frame.len());
+            Err(Error::Protocol)
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+impl<'d, I, NpdPin, IrqPin> ll::Reader for Felica<'d, I, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        debug!("TX: {:02x}", Bytes(tx));
+
+        if tx.len() >= MAX_FRAME_LEN {
+            return Err(Error::Other);
+        }
+
+        let mut tx_buf = [0; MAX_FRAME_LEN];
+        tx_buf[0] = tx.len() as u8 + 1;
+        tx_buf[1..][..tx.len()].copy_from_slice(tx);
+        let mut rx_buf = [0; MAX_FRAME_LEN];
+
+        let (n, collision) = self
+            .inner
+            .pcd
+            .transceive_raw(&tx_buf[..tx.len() + 1], &mut rx_buf, TrxOpts::standard(timeout_1fc))
+            .await?;
+        if collision {
+            return Err(Error::Collision);
+        }
+        check_len(&rx_buf[..n])?;
+
+        let n = n - 1;
+        if rx.len() < n {
+            warn!("rx overflow! received {} but buffer is only {}", n, rx.len());
+            return Err(Error::Other);
+        }
+        rx[..n].copy_from_slice(&rx_buf[1..][..n]);
+        debug!("RX: {:02x}", Bytes(&rx[..n]));
+        Ok(n)
+    }
+
+    async fn sensf_req(
+        &mut self,
+        system_code: u16,
+        request_code: u8,
+        slots: TimeSlots,
+        res: &mut [SensfRes],
+    ) -> Result<usize, Self::Error> {
+        let sc = system_code.to_be_bytes();
+        let tx = [6, 0x00, sc[0], sc[1], request_code, slots as u8];
+        debug!("TX: {:02x}", Bytes(&tx));
+
+        // Responses start 512*64/fc after the request, each slot is 256*64/fc long.
+        // Keep the receiver on for the whole window, collecting all frames.
+        let window_1fc = (512 + 256 * slots.count() as u32) * 64;
+        let opts = TrxOpts {
+            rx_multiple: true,
+            ..TrxOpts::standard(window_1fc)
+        };
+        let mut buf = [0; MAX_SENSF_RES_LEN * 16];
+        let (n, _) = self.inner.pcd.transceive_raw(&tx, &mut buf, opts).await?;
+
+        // Each frame is followed by a copy of the error register.
+        let mut count = 0;
+        let mut pos = 0;
+        while pos < n && count < res.len() {
+            let len = buf[pos] as usize;
+            if len == 0 || pos + len + 1 > n {
+                debug!("bad SENSF_RES framing: {:02x}", Bytes(&buf[pos..n]));
+                break;
+            }
+            let frame = &buf[pos..][..len];
+            let errs = regs::Error(buf[pos + len]);
+            pos += len + 1;
+
+            if errs.crcerr() || errs.proterr() || errs.parityerr() || errs.collerr() {
+                // Probably two cards answering in the same slot, keep going.
+                debug!("SENSF_RES rx error: {:02x}", errs.0);
+                continue;
+            }
+            match SensfRes::parse(&frame[1..]) {
+                Some(r) => {
+                    debug!("SENSF_RES: nfcid2={:02x}", Bytes(&r.nfcid2));
+                    if !res[..count].iter().any(|x| x.nfcid2 == r.nfcid2) {
+                        res[count] = r;
+                        count += 1;
+                    }
+                }
+                None => debug!("bad SENSF_RES: {:02x}", Bytes(frame)),
+            }
+        }
+
+        Ok(count)
+    }
+}
@@ -14,34 +14,36 @@ impl<T: I2c> I2cInterface<T> {
         Self { i2c, address }
     }
 
-    fn read_reg_raw(&mut self, reg: u8) -> u8 {
+    fn read_reg_raw(&mut self, reg: u8) -> Result<u8, T::Error> {
         let mut buf = [0; 1];
-        self.i2c.write_read(self.address, &[reg], &mut buf).unwrap();
-        buf[0]
+        self.i2c.write_read(self.address, &[reg], &mut buf)?;
+        Ok(buf[0])
     }
 
-    fn write_reg_raw(&mut self, reg: u8, val: u8) {
-        self.i2c.write(self.address, &[reg as u8, val]).unwrap();
+    fn write_reg_raw(&mut self, reg: u8, val: u8) -> Result<(), T::Error> {
+        self.i2c.write(self.address, &[reg as u8, val])
     }
 }
 
 impl<T: I2c> Interface for I2cInterface<T> {
-    fn read_reg(&mut self, reg: usize) -> u8 {
+    type Error = T::Error;
+
+    fn read_reg(&mut self, reg: usize) -> Result<u8, Self::Error> {
         let reg = reg as u8;
         let res = if reg < 0x40 {
             // Main register
-            self.read_reg_raw(reg)
+            self.read_reg_raw(reg)?
         } else {
             // Extended register
             let reg = reg - 0x40;
-            self.write_reg_raw(0x0f, reg | 0x80);
-            self.read_reg_raw(0x0f) & 0x3F
+            self.write_reg_raw(0x0f, reg | 0x80)?;
+            self.read_reg_raw(0x0f)? & 0x3F
         };
         trace!("     read {:02x} = {:02x}", reg, res);
-        res
+        Ok(res)
     }
 
-    fn write_reg(&mut self, reg: usize, val: u8) {
+    fn write_reg(&mut self, reg: usize, val: u8) -> Result<(), Self::Error> {
         let reg = reg as u8;
         trace!("     write {:02x} = {:02x}", reg, val);
 
@@ -51,31 +53,31 @@ impl<T: I2c> Interface for I2cInterface<T> {
         } else {
             // Extended register
             let reg = reg - 0x40;
-            self.write_reg_raw(0x0F, reg | 0x40);
-            self.write_reg_raw(0x0F, (val & 0x3F) | 0xC0);
+            self.write_reg_raw(0x0F, reg | 0x40)?;
+            self.write_reg_raw(0x0F, (val & 0x3F) | 0xC0)
         }
     }
 
-    fn read_fifo(&mut self, data: &mut [u8]) {
+    fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
         if data.len() == 0 {
-            return;
+            return Ok(());
         }
 
-        self.i2c.write_read(self.address, &[0x09], data).unwrap();
+        self.i2c.write_read(self.address, &[0x09], data)?;
         trace!("     read_fifo {:02x}", Bytes(data));
+        Ok(())
     }
 
-    fn write_fifo(&mut self, data: &[u8]) {
-        if data.len() == 0 {
-            return;
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        // The FIFO is never written with more than it can hold, but split anyway
+        // so the buffer below can't overflow.
+        for chunk in data.chunks(FIFO_SIZE) {
+            let mut buf = [0; FIFO_SIZE + 1];
+            buf[0] = 0x09;
+            buf[1..1 + chunk.len()].copy_from_slice(chunk);
+            self.i2c.write(self.address, &buf[..1 + chunk.len()])?;
+            trace!("     write_fifo {:02x}", Bytes(chunk));
         }
-
-        assert!(data.len() <= FIFO_SIZE);
-
-        let mut buf = [0; FIFO_SIZE + 1];
-        buf[0] = 0x09;
-        buf[1..1 + data.len()].copy_from_slice(data);
-        self.i2c.write(self.address, &buf[..1 + data.len()]).unwrap();
-        trace!("     write_fifo {:02x}", Bytes(data));
+        Ok(())
     }
 }
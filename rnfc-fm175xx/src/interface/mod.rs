@@ -1,13 +1,19 @@
 mod i2c;
 mod spi;
+mod uart;
+
+use core::fmt::Debug;
 
 pub use i2c::I2cInterface;
 pub use spi::SpiInterface;
+pub use uart::UartInterface;
 
 pub trait Interface {
-    fn read_reg(&mut self, reg: usize) -> u8;
-    fn write_reg(&mut self, reg: usize, val: u8);
+    type Error: Debug;
+
+    fn read_reg(&mut self, reg: usize) -> Result<u8, Self::Error>;
+    fn write_reg(&mut self, reg: usize, val: u8) -> Result<(), Self::Error>;
 
-    fn read_fifo(&mut self, data: &mut [u8]);
-    fn write_fifo(&mut self, data: &[u8]);
+    fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error>;
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error>;
 }
@@ -13,44 +13,46 @@ impl<T: SpiDevice> SpiInterface<T> {
         Self { spi }
     }
 
-    fn read_reg_raw(&mut self, reg: u8) -> u8 {
+    fn read_reg_raw(&mut self, reg: u8) -> Result<u8, T::Error> {
         delay(10_000);
 
         let mut buf = [0x80 | (reg << 1), 0x00];
-        self.spi.transfer_in_place(&mut buf).unwrap();
+        self.spi.transfer_in_place(&mut buf)?;
         let res = buf[1];
 
         //trace!("         read_raw {:02x} = {:02x}", reg, res);
-        res
+        Ok(res)
     }
 
-    fn write_reg_raw(&mut self, reg: u8, val: u8) {
+    fn write_reg_raw(&mut self, reg: u8, val: u8) -> Result<(), T::Error> {
         //trace!("         write_raw {:02x} = {:02x}", reg, val);
         delay(10_000);
 
         let buf = [(reg << 1), val];
-        self.spi.write(&buf).unwrap();
+        self.spi.write(&buf)
     }
 }
 
 impl<T: SpiDevice> Interface for SpiInterface<T> {
-    fn read_reg(&mut self, reg: usize) -> u8 {
+    type Error = T::Error;
+
+    fn read_reg(&mut self, reg: usize) -> Result<u8, Self::Error> {
         let reg = reg as u8;
         let res = if reg < 0x40 {
             // Main register
-            self.read_reg_raw(reg)
+            self.read_reg_raw(reg)?
         } else {
             // Extended register
             let reg = reg - 0x40;
-            self.write_reg_raw(0x0f, reg | 0x80);
-            self.read_reg_raw(0x0f) & 0x3F
+            self.write_reg_raw(0x0f, reg | 0x80)?;
+            self.read_reg_raw(0x0f)? & 0x3F
         };
 
         trace!("     read {:02x} = {:02x}", reg, res);
-        res
+        Ok(res)
     }
 
-    fn write_reg(&mut self, reg: usize, val: u8) {
+    fn write_reg(&mut self, reg: usize, val: u8) -> Result<(), Self::Error> {
         let reg = reg as u8;
         trace!("     write {:02x} = {:02x}", reg, val);
 
@@ -60,14 +62,14 @@ impl<T: SpiDevice> Interface for SpiInterface<T> {
         } else {
             // Extended register
             let reg = reg - 0x40;
-            self.write_reg_raw(0x0F, reg | 0x40);
-            self.write_reg_raw(0x0F, (val & 0x3F) | 0xC0);
+            self.write_reg_raw(0x0F, reg | 0x40)?;
+            self.write_reg_raw(0x0F, (val & 0x3F) | 0xC0)
         }
     }
 
-    fn read_fifo(&mut self, data: &mut [u8]) {
+    fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
         if data.len() == 0 {
-            return;
+            return Ok(());
         }
 
         delay(10_000);
@@ -76,22 +78,20 @@ impl<T: SpiDevice> Interface for SpiInterface<T> {
         data[data.len() - 1] = 0x80;
 
         self.spi
-            .transaction(&mut [Operation::Write(&[0x92]), Operation::TransferInPlace(data)])
-            .unwrap();
+            .transaction(&mut [Operation::Write(&[0x92]), Operation::TransferInPlace(data)])?;
 
         trace!("     read_fifo {:02x}", Bytes(data));
+        Ok(())
     }
 
-    fn write_fifo(&mut self, data: &[u8]) {
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
         if data.len() == 0 {
-            return;
+            return Ok(());
         }
 
         trace!("     write_fifo {:02x}", Bytes(data));
         delay(10_000);
 
-        self.spi
-            .transaction(&mut [Operation::Write(&[0x12]), Operation::Write(data)])
-            .unwrap();
+        self.spi.transaction(&mut [Operation::Write(&[0x12]), Operation::Write(data)])
     }
 }
@@ -0,0 +1,88 @@
+use embedded_io::{Read, ReadExactError, Write};
+
+use super::Interface;
+use crate::fmt::Bytes;
+
+/// UART interface. The chip must be strapped for UART, the baudrate defaults to 9600.
+pub struct UartInterface<T: Read + Write> {
+    uart: T,
+}
+
+impl<T: Read + Write> UartInterface<T> {
+    pub fn new(uart: T) -> Self {
+        Self { uart }
+    }
+
+    fn read_reg_raw(&mut self, reg: u8) -> Result<u8, ReadExactError<T::Error>> {
+        self.uart.write_all(&[0x80 | reg]).map_err(ReadExactError::Other)?;
+        self.uart.flush().map_err(ReadExactError::Other)?;
+
+        let mut buf = [0; 1];
+        self.uart.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn write_reg_raw(&mut self, reg: u8, val: u8) -> Result<(), ReadExactError<T::Error>> {
+        self.uart.write_all(&[reg, val]).map_err(ReadExactError::Other)?;
+        self.uart.flush().map_err(ReadExactError::Other)?;
+
+        // The chip echoes the address byte once the write is done.
+        let mut buf = [0; 1];
+        self.uart.read_exact(&mut buf)?;
+        if buf[0] != reg {
+            warn!("uart: bad write echo {:02x}, expected {:02x}", buf[0], reg);
+        }
+        Ok(())
+    }
+}
+
+impl<T: Read + Write> Interface for UartInterface<T> {
+    type Error = ReadExactError<T::Error>;
+
+    fn read_reg(&mut self, reg: usize) -> Result<u8, Self::Error> {
+        let reg = reg as u8;
+        let res = if reg < 0x40 {
+            // Main register
+            self.read_reg_raw(reg)?
+        } else {
+            // Extended register
+            let reg = reg - 0x40;
+            self.write_reg_raw(0x0f, reg | 0x80)?;
+            self.read_reg_raw(0x0f)? & 0x3F
+        };
+        trace!("     read {:02x} = {:02x}", reg, res);
+        Ok(res)
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8) -> Result<(), Self::Error> {
+        let reg = reg as u8;
+        trace!("     write {:02x} = {:02x}", reg, val);
+
+        if reg < 0x40 {
+            // Main register
+            self.write_reg_raw(reg, val)
+        } else {
+            // Extended register
+            let reg = reg - 0x40;
+            self.write_reg_raw(0x0F, reg | 0x40)?;
+            self.write_reg_raw(0x0F, (val & 0x3F) | 0xC0)
+        }
+    }
+
+    fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        // No burst access over UART, one FIFO register read per byte.
+        for b in data.iter_mut() {
+            *b = self.read_reg_raw(0x09)?;
+        }
+        trace!("     read_fifo {:02x}", Bytes(data));
+        Ok(())
+    }
+
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        trace!("     write_fifo {:02x}", Bytes(data));
+        for &b in data {
+            self.write_reg_raw(0x09, b)?;
+        }
+        Ok(())
+    }
+}
@@ -1,15 +1,18 @@
-use embassy_futures::yield_now;
-use embassy_time::{Duration, Instant, Timer};
-use embedded_hal::digital::{InputPin, OutputPin};
+use core::fmt::Debug;
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{ErrorKind as PinErrorKind, InputPin, OutputPin};
 use embedded_hal_async::digital::Wait;
 use rnfc_traits::iso14443a_ll as ll;
 
 use crate::fmt::Bytes;
-use crate::{regs, Fm175xx, Interface, FIFO_SIZE};
+use crate::{regs, Fm175xx, Interface, Pcd, RfConfig, TrxOpts};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub enum Error {
+pub enum Error<T> {
+    Interface(T),
+    Pin(PinErrorKind),
     Other,
     Timeout,
     Crc,
@@ -17,9 +20,22 @@ pub enum Error {
     Collision,
 }
 
-impl ll::Error for Error {
+impl<T> From<crate::Error<T>> for Error<T> {
+    fn from(val: crate::Error<T>) -> Self {
+        match val {
+            crate::Error::Interface(e) => Error::Interface(e),
+            crate::Error::Pin(e) => Error::Pin(e),
+            crate::Error::Timeout => Error::Timeout,
+            crate::Error::Config(_) => Error::Other,
+        }
+    }
+}
+
+impl<T: Debug> ll::Error for Error<T> {
     fn kind(&self) -> ll::ErrorKind {
         match self {
+            Error::Interface(_) => ll::ErrorKind::Other,
+            Error::Pin(_) => ll::ErrorKind::Other,
             Error::Other => ll::ErrorKind::Other,
             Error::Timeout => ll::ErrorKind::Timeout,
             Error::Protocol => ll::ErrorKind::Corruption,
@@ -29,6 +45,40 @@ impl ll::Error for Error {
     }
 }
 
+/// Modulation width for each bit rate, in units of 1/fc minus one.
+/// The pause length scales with the bit duration.
+fn modwidth(bit_rate: ll::BitRate) -> u8 {
+    match bit_rate {
+        ll::BitRate::Kbps106 => 0x27,
+        ll::BitRate::Kbps212 => 0x15,
+        ll::BitRate::Kbps424 => 0x0A,
+        ll::BitRate::Kbps848 => 0x05,
+    }
+}
+
+/// Receive thresholds (minlevel, collevel) for each bit rate.
+///
+/// Above 106kbps the card uses BPSK on the subcarrier, which comes out weaker than the
+/// manchester coding at 106kbps, so a lower min level is needed.
+/// Collisions are only detected during anticollision, at 106kbps.
+fn rx_thresholds(bit_rate: ll::BitRate, config: &RfConfig) -> (u8, u8) {
+    match bit_rate {
+        ll::BitRate::Kbps106 => (config.minlevel, config.colllevel),
+        ll::BitRate::Kbps212 => (6, 2),
+        ll::BitRate::Kbps424 => (5, 2),
+        ll::BitRate::Kbps848 => (4, 2),
+    }
+}
+
+fn speed(bit_rate: ll::BitRate) -> regs::Speed {
+    match bit_rate {
+        ll::BitRate::Kbps106 => regs::Speed::_106KBPS,
+        ll::BitRate::Kbps212 => regs::Speed::_212KBPS,
+        ll::BitRate::Kbps424 => regs::Speed::_424KBPS,
+        ll::BitRate::Kbps848 => regs::Speed::_848KBPS,
+    }
+}
+
 pub struct Iso14443a<'d, I: Interface, NpdPin, IrqPin>
 where
     I: Interface + 'd,
@@ -43,34 +93,35 @@ where
     NpdPin: OutputPin,
     IrqPin: InputPin + Wait,
 {
-    pub async fn start_iso14443a(&mut self) -> Result<Iso14443a<I, NpdPin, IrqPin>, Error> {
-        self.on().await;
+    pub async fn start_iso14443a(&mut self) -> Result<Iso14443a<I, NpdPin, IrqPin>, Error<I::Error>> {
+        self.on().await?;
 
         self.regs().txmode().write(|w| {
             w.set_framing(regs::Framing::ISO14443A);
             w.set_speed(regs::Speed::_106KBPS);
-        });
+        })?;
         self.regs().rxmode().write(|w| {
             w.set_framing(regs::Framing::ISO14443A);
             w.set_speed(regs::Speed::_106KBPS);
-        });
-        self.regs().modwidth().write_value(0x27);
+        })?;
+        self.regs().mode().modify(|w| w.set_crcpreset(0b01))?; // 0x6363
+        self.regs().modwidth().write_value(modwidth(ll::BitRate::Kbps106))?;
         self.regs().control().write(|w| {
             w.set_initiator(true);
-        });
+        })?;
         let config = self.config.clone();
         self.regs().rfcfg().write(|w| {
             w.set_rxgain(config.rx_gain);
-        });
+        })?;
         self.regs().rxtreshold().write(|w| {
             w.set_collevel(config.colllevel);
             w.set_minlevel(config.minlevel);
-        });
+        })?;
         self.regs().txauto().write(|w| {
             w.set_force100ask(true);
-        });
+        })?;
 
-        self.rf_on();
+        self.rf_on()?;
 
         // Field on guard time
         Timer::after(Duration::from_millis(5)).await;
@@ -86,7 +137,9 @@ where
     IrqPin: InputPin + Wait + 'd,
 {
     fn drop(&mut self) {
-        self.inner.off();
+        if self.inner.off().is_err() {
+            warn!("Failed to set field off on Iso14443a drop");
+        }
     }
 }
 
@@ -96,12 +149,52 @@ where
     NpdPin: OutputPin + 'd,
     IrqPin: InputPin + Wait + 'd,
 {
-    type Error = Error;
+    type Error = Error<I::Error>;
+
+    fn max_bit_rate(&self) -> ll::BitRate {
+        ll::BitRate::Kbps848
+    }
+
+    async fn set_bit_rate(&mut self, tx: ll::BitRate, rx: ll::BitRate) -> Result<(), Self::Error> {
+        debug!("set bit rate: tx={:?} rx={:?}", tx, rx);
+        let config = self.inner.config;
+        self.inner.pcd.iso14443a_set_bit_rate(tx, rx, &config)?;
+        Ok(())
+    }
 
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Self::Error> {
-        debug!("TX: {:?} {:02x}", opts, Bytes(tx));
+        self.inner.pcd.iso14443a_transceive(tx, rx, opts).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        Timer::after(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
+}
+
+impl<I: Interface> Pcd<I> {
+    /// Set the speed, modulation width and receive thresholds for the given bit rates.
+    pub fn iso14443a_set_bit_rate(
+        &mut self,
+        tx: ll::BitRate,
+        rx: ll::BitRate,
+        config: &RfConfig,
+    ) -> Result<(), crate::Error<I::Error>> {
+        self.regs().txmode().modify(|w| w.set_speed(speed(tx)))?;
+        self.regs().modwidth().write_value(modwidth(tx))?;
+
+        self.regs().rxmode().modify(|w| w.set_speed(speed(rx)))?;
+        let (minlevel, collevel) = rx_thresholds(rx, config);
+        self.regs().rxtreshold().write(|w| {
+            w.set_collevel(collevel);
+            w.set_minlevel(minlevel);
+        })?;
+        Ok(())
+    }
 
-        let r = &mut *self.inner;
+    /// Transceive an ISO14443A frame, with the framing set up by [`iso14443a_set_bit_rate`](Self::iso14443a_set_bit_rate).
+    pub async fn iso14443a_transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Error<I::Error>> {
+        debug!("TX: {:?} {:02x}", opts, Bytes(tx));
 
         let (tx, crc, timeout_1fc, lastbits, rxalign) = match opts {
             ll::Frame::Anticoll { bits } => (&tx[..(bits + 7) / 8], false, 65536, (bits % 8) as u8, (bits % 8) as u8),
@@ -110,151 +203,15 @@ where
             ll::Frame::Standard { timeout_1fc } => (tx, true, timeout_1fc, 0, 0),
         };
 
-        // Set CRC
-        r.regs().txmode().modify(|w| w.set_crcen(crc));
-        r.regs().rxmode().modify(|w| w.set_crcen(crc));
-
-        // Set timeout
-        r.set_timer(timeout_1fc);
-
-        // Halt whatever currently running command.
-        r.regs().command().write(|w| {
-            w.set_command(regs::CommandVal::IDLE);
-        });
-
-        // Clear all IRQs
-        r.regs().divirq().write_value(0x7f.into());
-        r.regs().commirq().write_value(0x7f.into());
-
-        r.clear_fifo();
-
-        r.regs().coll().write(|w| {
-            w.set_valuesaftercoll(!matches!(opts, ll::Frame::Anticoll { .. }));
-        });
-
-        let mut collision = false;
-
-        let mut tx_pos = 0;
-        let mut write_fifo = |r: &mut Fm175xx<I, NpdPin, IrqPin>| {
-            if tx_pos >= tx.len() {
-                return Ok::<(), Error>(());
-            }
-
-            let used = r.regs().fifolevel().read().level() as usize;
-            let free = FIFO_SIZE - used;
-            let n = free.min(tx.len() - tx_pos);
-            r.iface.write_fifo(&tx[tx_pos..][..n]);
-            tx_pos += n;
-            Ok(())
-        };
-
-        let mut rx_pos = 0;
-        let mut read_fifo = |r: &mut Fm175xx<I, NpdPin, IrqPin>| {
-            let bytes = r.regs().fifolevel().read().level() as usize;
-            if rx_pos + bytes > rx.len() {
-                warn!("rx overflow! received {} but buffer is only {}", rx_pos + bytes, rx.len());
-                return Err(Error::Other);
-            }
-            r.iface.read_fifo(&mut rx[rx_pos..][..bytes]);
-            rx_pos += bytes;
-            Ok(())
+        let trx_opts = TrxOpts {
+            crc,
+            timeout_1fc,
+            tx_last_bits: lastbits,
+            rx_align: rxalign,
+            rx_multiple: false,
+            anticoll: matches!(opts, ll::Frame::Anticoll { .. }),
         };
-
-        // Fill FIFO as much as we can, to begin with.
-        write_fifo(r)?;
-
-        // Start trx
-        r.regs().command().write(|w| {
-            w.set_command(regs::CommandVal::TRANSCEIVE);
-        });
-
-        r.regs().bitframing().write(|w| {
-            w.set_startsend(true);
-            w.set_rxalign(rxalign);
-            w.set_txlastbits(lastbits);
-        });
-
-        let mut tx_done = false;
-        let deadline = Instant::now() + Duration::from_secs(1);
-        loop {
-            // make sure to not loop forever if timeri never fires for whatever reason.
-            if Instant::now() > deadline {
-                warn!("emergency timeout");
-                return Err(Error::Other);
-            }
-
-            let mut irqs = r.regs().commirq().read();
-
-            if irqs.timeri() {
-                trace!("irq: timeri");
-                return Err(Error::Timeout);
-            }
-
-            if irqs.erri() {
-                trace!("irq: ERR");
-                let errs = r.regs().error().read();
-                if errs.collerr() {
-                    debug!("err: collision");
-                    collision = true;
-                    //break;
-                }
-                if errs.bufferovfl() {
-                    warn!("err: buffer overflow");
-                    return Err(Error::Other);
-                }
-                if errs.crcerr() {
-                    warn!("err: bad CRC");
-                    return Err(Error::Crc);
-                }
-                if errs.parityerr() && !collision {
-                    warn!("err: parity");
-                    return Err(Error::Crc);
-                }
-                if errs.proterr() {
-                    warn!("err: protocol");
-                    return Err(Error::Protocol);
-                }
-                if errs.rferr() {
-                    warn!("err: rf");
-                    return Err(Error::Protocol);
-                }
-                if errs.temperr() {
-                    warn!("err: temperature");
-                    return Err(Error::Other);
-                }
-                if errs.wrerr() {
-                    warn!("err: write access error??");
-                    return Err(Error::Other);
-                }
-            }
-
-            if irqs.txi() {
-                trace!("irq: tx done");
-                tx_done = true;
-            }
-            if irqs.rxi() {
-                trace!("irq: rx done");
-                break;
-            }
-
-            irqs.set_set(false);
-            r.regs().commirq().write_value(irqs);
-
-            if tx_done {
-                read_fifo(r)?;
-            } else {
-                write_fifo(r)?;
-            }
-
-            yield_now().await;
-        }
-
-        if tx_pos != tx.len() {
-            warn!("TX fifo underflow (tx done fired before we wrote the bytes)");
-            return Err(Error::Other);
-        }
-
-        read_fifo(r)?;
+        let (rx_pos, collision) = self.transceive_raw(tx, rx, trx_opts).await?;
 
         if let ll::Frame::Anticoll { bits } = opts {
             let shift = bits / 8;
@@ -275,7 +232,7 @@ where
             // Collision at bit `i` means that bit is not valid, only `0..i-1` are.
             // substract 1 because collpos is 1-based, not 0-based (why??)
             let total_bits = if collision {
-                let coll = r.regs().coll().read();
+                let coll = self.regs().coll().read()?;
                 if coll.collposnotvalid() {
                     warn!("collision position out of range");
                     return Err(Error::Protocol);
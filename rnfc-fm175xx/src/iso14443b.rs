@@ -0,0 +1,100 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use rnfc_traits::iso14443b_ll as ll;
+
+use crate::fmt::Bytes;
+pub use crate::iso14443a::Error;
+use crate::{regs, Fm175xx, Interface, TrxOpts};
+
+/// An FM175xx chip enabled in ISO14443B mode.
+pub struct Iso14443b<'d, I: Interface, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    inner: &'d mut Fm175xx<I, NpdPin, IrqPin>,
+}
+
+impl<I: Interface, NpdPin, IrqPin> Fm175xx<I, NpdPin, IrqPin>
+where
+    NpdPin: OutputPin,
+    IrqPin: InputPin + Wait,
+{
+    /// Turn on the field in ISO14443B mode, 106kbps.
+    ///
+    /// SOF, EOF and CRC_B are handled by the chip.
+    pub async fn start_iso14443b(&mut self) -> Result<Iso14443b<I, NpdPin, IrqPin>, Error<I::Error>> {
+        self.on().await?;
+
+        self.regs().txmode().write(|w| {
+            w.set_framing(regs::Framing::ISO14443B);
+            w.set_speed(regs::Speed::_106KBPS);
+        })?;
+        self.regs().rxmode().write(|w| {
+            w.set_framing(regs::Framing::ISO14443B);
+            w.set_speed(regs::Speed::_106KBPS);
+        })?;
+        self.regs().mode().modify(|w| w.set_crcpreset(0b11))?; // 0xFFFF
+        self.regs().control().write(|w| {
+            w.set_initiator(true);
+        })?;
+        let config = self.config;
+        self.regs().rfcfg().write(|w| {
+            w.set_rxgain(config.rx_gain);
+        })?;
+        self.regs().rxtreshold().write(|w| {
+            w.set_collevel(config.colllevel);
+            w.set_minlevel(config.minlevel);
+        })?;
+        // 10% ASK
+        self.regs().txauto().write(|w| {
+            w.set_force100ask(false);
+        })?;
+
+        self.rf_on()?;
+        self.regs().modgsp().write(|w| {
+            w.set_modgsp(config.p_drive_mod_ask10);
+        })?;
+
+        // Field on guard time
+        Timer::after(Duration::from_millis(5)).await;
+
+        Ok(Iso14443b { inner: self })
+    }
+}
+
+impl<'d, I, NpdPin, IrqPin> Drop for Iso14443b<'d, I, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    fn drop(&mut self) {
+        if self.inner.off().is_err() {
+            warn!("Failed to set field off on Iso14443b drop");
+        }
+    }
+}
+
+impl<'d, I, NpdPin, IrqPin> ll::Reader for Iso14443b<'d, I, NpdPin, IrqPin>
+where
+    I: Interface + 'd,
+    NpdPin: OutputPin + 'd,
+    IrqPin: InputPin + Wait + 'd,
+{
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        debug!("TX: {:02x}", Bytes(tx));
+
+        let (n, collision) = self.inner.pcd.transceive_raw(tx, rx, TrxOpts::standard(timeout_1fc)).await?;
+        if collision {
+            return Err(Error::Collision);
+        }
+
+        debug!("RX: {:02x}", Bytes(&rx[..n]));
+        Ok(n)
+    }
+}
@@ -4,18 +4,64 @@
 // Must go FIRST so that other mods see its macros.
 mod fmt;
 
+mod diagnostics;
+pub mod felica;
 mod interface;
 pub mod iso14443a;
-mod regs;
-
-use core::convert::Infallible;
+pub mod iso14443b;
+mod pcd;
+pub mod regs;
+mod tuning;
 
 use embassy_time::{with_timeout, Duration, Instant, TimeoutError, Timer};
-use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::digital::{Error as _, ErrorKind as PinErrorKind, InputPin, OutputPin};
 use embedded_hal_async::digital::Wait;
 pub use interface::*;
+pub use pcd::{Pcd, TrxOpts};
 use regs::Regs;
 pub use regs::Rxgain as RxGain;
+pub use tuning::{TuningResult, TuningScore, TuningSweep};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    /// Error talking to the chip.
+    Interface(T),
+    /// Error driving the NPD pin, or waiting on the IRQ pin.
+    Pin(PinErrorKind),
+    /// The chip didn't finish an operation in time.
+    Timeout,
+    /// Invalid configuration.
+    Config(ConfigError),
+}
+
+/// A configuration field out of its valid range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConfigError {
+    /// `RfConfig::n_drive_cw` or `RfConfig::n_drive_mod` is over 15.
+    NDrive,
+    /// `RfConfig::p_drive_cw`, `RfConfig::p_drive_mod` or `RfConfig::p_drive_mod_ask10` is over 63.
+    PDrive,
+    /// `RfConfig::minlevel` or `RfConfig::colllevel` is over 15.
+    RxLevel,
+    /// `WakeupConfig::sleep_time` is not in 1..=15.
+    SleepTime,
+    /// `WakeupConfig::prepare_time` is not in 2..=31.
+    PrepareTime,
+    /// `WakeupConfig::measure_time` is not in 2..=31.
+    MeasureTime,
+    /// `WakeupConfig::n_drive` is over 1.
+    WakeupNDrive,
+    /// `WakeupConfig::p_drive` is over 7.
+    WakeupPDrive,
+    /// `DriftConfig::check_interval` is zero.
+    DriftCheckInterval,
+    /// `TuningSweep::attempts` is zero.
+    TuningAttempts,
+    /// The tuning score table is smaller than the sweep.
+    TuningTable,
+}
 
 #[derive(Clone, Copy)]
 pub struct RfConfig {
@@ -27,6 +73,10 @@ pub struct RfConfig {
     pub p_drive_cw: u8,
     /// PMOS carrier wave drive strength when modulating. 0..=63
     pub p_drive_mod: u8,
+    /// PMOS carrier wave drive strength when modulating with 10% ASK (ISO14443B, FeliCa). 0..=63
+    ///
+    /// This sets the modulation index, tune it for your antenna.
+    pub p_drive_mod_ask10: u8,
     /// RX gain.
     pub rx_gain: RxGain,
     /// Min rx level. 0..=15
@@ -35,6 +85,22 @@ pub struct RfConfig {
     pub colllevel: u8,
 }
 
+impl RfConfig {
+    /// Check all fields are in their valid range.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.n_drive_cw > 15 || self.n_drive_mod > 15 {
+            return Err(ConfigError::NDrive);
+        }
+        if self.p_drive_cw > 63 || self.p_drive_mod > 63 || self.p_drive_mod_ask10 > 63 {
+            return Err(ConfigError::PDrive);
+        }
+        if self.minlevel > 15 || self.colllevel > 15 {
+            return Err(ConfigError::RxLevel);
+        }
+        Ok(())
+    }
+}
+
 impl Default for RfConfig {
     fn default() -> Self {
         Self {
@@ -42,6 +108,7 @@ impl Default for RfConfig {
             n_drive_mod: 8,
             p_drive_cw: 32,
             p_drive_mod: 32,
+            p_drive_mod_ask10: 12,
             rx_gain: RxGain::_33DB,
             minlevel: 8,
             colllevel: 4,
@@ -78,6 +145,93 @@ pub struct WakeupConfig {
     pub p_drive: u8,
 
     pub recalibrate_interval: Option<Duration>,
+
+    /// Check for drift of the baseline reading, and recalibrate if needed. If `None`,
+    /// only recalibrate every `recalibrate_interval`.
+    pub drift: Option<DriftConfig>,
+}
+
+impl WakeupConfig {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if !(1..=15).contains(&self.sleep_time) {
+            return Err(ConfigError::SleepTime);
+        }
+        if !(2..=31).contains(&self.prepare_time) {
+            return Err(ConfigError::PrepareTime);
+        }
+        if !(2..=31).contains(&self.measure_time) {
+            return Err(ConfigError::MeasureTime);
+        }
+        if self.n_drive > 1 {
+            return Err(ConfigError::WakeupNDrive);
+        }
+        if self.p_drive > 7 {
+            return Err(ConfigError::WakeupPDrive);
+        }
+        if let Some(drift) = &self.drift {
+            if drift.check_interval == Duration::from_ticks(0) {
+                return Err(ConfigError::DriftCheckInterval);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wakeup check of the baseline ADC reading, to follow slow changes in the environment.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DriftConfig {
+    /// How often to wake up and take a reading.
+    pub check_interval: Duration,
+    /// Recalibrate if the reading differs by more than `threshold` from the baseline.
+    ///
+    /// Same units as [`WakeupConfig::threshold`], so it should be lower than it.
+    pub threshold: u8,
+}
+
+/// Result of an LPCD calibration.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LpcdCalibration {
+    /// Chosen gain level, 0..32. Higher levels are more gain.
+    pub gain_level: u8,
+    /// Chosen ADC reference voltage, 0..=0x7F.
+    pub reference: u8,
+    /// ADC reading right after calibration.
+    pub baseline: u8,
+    /// Wakeup if the ADC reading goes below this.
+    pub threshold_min: u8,
+    /// Wakeup if the ADC reading goes above this.
+    pub threshold_max: u8,
+    /// Max ADC reading for the configured measure time.
+    pub adc_range: u8,
+    /// The gain or reference search didn't converge, and the max was used.
+    pub failed: bool,
+}
+
+/// What woke the chip up from LPCD mode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupCause {
+    /// The ADC reading went out of the thresholds.
+    CardDetected,
+    /// The chip's automatic wakeup timer fired.
+    AutoWakeup,
+    /// The IRQ pin went low but no LPCD IRQ flag was set.
+    Unknown,
+}
+
+/// Result of [`Fm175xx::wait_for_card`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Wakeup {
+    pub cause: WakeupCause,
+    /// ADC reading at wakeup. Compare with the calibration thresholds to diagnose false wakeups.
+    pub reading: u8,
+    /// Calibration in use when the wakeup happened.
+    pub calibration: LpcdCalibration,
+    /// How many times it recalibrated while waiting, due to the timer or to drift.
+    pub recalibrations: u32,
 }
 
 const FIFO_SIZE: usize = 64;
@@ -85,8 +239,13 @@ const FIFO_SIZE: usize = 64;
 const ADC_REFERENCE_MIN: u8 = 0;
 const ADC_REFERENCE_MAX: u8 = 0x7F;
 
+/// `lpcd_ctrl4` values, sorted by increasing gain.
+const LPCD_GAIN_LEVELS: [u8; 32] = [
+    0, 4, 2, 8, 6, 1, 10, 5, 12, 16, 9, 3, 14, 20, 18, 7, 24, 22, 13, 11, 17, 26, 28, 21, 15, 30, 25, 19, 23, 29, 27, 31,
+];
+
 pub struct Fm175xx<I, NpdPin, IrqPin> {
-    iface: I,
+    pcd: Pcd<I>,
     npd: NpdPin,
     irq: IrqPin,
     config: RfConfig,
@@ -98,85 +257,55 @@ where
     NpdPin: OutputPin,
     IrqPin: InputPin + Wait,
 {
-    pub async fn new(iface: I, mut npd: NpdPin, irq: IrqPin) -> Self {
-        npd.set_low().unwrap();
+    pub async fn new(iface: I, mut npd: NpdPin, irq: IrqPin) -> Result<Self, Error<I::Error>> {
+        npd.set_low().map_err(|e| Error::Pin(e.kind()))?;
 
-        Self {
-            iface,
+        Ok(Self {
+            pcd: Pcd::new(iface),
             npd,
             irq,
             config: Default::default(),
-        }
+        })
     }
 
     fn regs(&mut self) -> Regs<I> {
-        Regs {
-            iface: &mut self.iface,
-            addr: 0,
-        }
+        self.pcd.regs()
     }
 
-    fn off(&mut self) {
-        self.npd.set_low().unwrap();
+    fn set_npd(&mut self, high: bool) -> Result<(), Error<I::Error>> {
+        let res = match high {
+            true => self.npd.set_high(),
+            false => self.npd.set_low(),
+        };
+        res.map_err(|e| Error::Pin(e.kind()))
     }
 
-    async fn on(&mut self) {
-        self.npd.set_low().unwrap();
+    fn off(&mut self) -> Result<(), Error<I::Error>> {
+        self.set_npd(false)
+    }
+
+    async fn on(&mut self) -> Result<(), Error<I::Error>> {
+        self.set_npd(false)?;
 
         // datasheet says reset takes 5ms.
         Timer::after(Duration::from_millis(5)).await;
 
-        self.npd.set_high().unwrap();
+        self.set_npd(true)?;
 
         // datasheet doesn't say anything about time after reset, wait a bit just in case.
         Timer::after(Duration::from_millis(1)).await;
 
-        debug!("softreset");
-        self.regs().command().write(|w| w.set_command(regs::CommandVal::SOFTRESET));
-
-        let deadline = Instant::now() + Duration::from_secs(1);
-        while self.regs().command().read().command() != regs::CommandVal::IDLE {
-            if Instant::now() > deadline {
-                warn!("timeout waiting for softreset.");
-                break;
-            }
-        }
-
-        // again, just in case
-        Timer::after(Duration::from_millis(1)).await;
-
-        //let ver = self.regs().version().read();
-        //debug!("IC version: {:02x}", ver);
+        self.pcd.soft_reset().await?;
+        Ok(())
     }
 
-    fn rf_on(&mut self) {
+    fn rf_on(&mut self) -> Result<(), Error<I::Error>> {
         let config = self.config;
-
-        self.regs().gsn().write(|w| {
-            w.set_cwgsn(config.n_drive_cw); // reset value: 8
-            w.set_modgsn(config.n_drive_mod); // reset value: 8
-        });
-        self.regs().cwgsp().write(|w| {
-            w.set_cwgsp(config.p_drive_cw); // reset value: 32
-        });
-        self.regs().modgsp().write(|w| {
-            w.set_modgsp(config.p_drive_mod); // reset value: 32
-        });
-
-        self.regs().command().write(|w| {
-            w.set_powerdown(false);
-            w.set_rcvoff(false);
-        });
-
-        self.regs().txcontrol().write(|w| {
-            w.set_tx1rfen(true);
-            w.set_tx2rfen(true);
-            w.set_invtx2on(true);
-        });
+        self.pcd.rf_on(&config)
     }
 
-    pub async fn sleep(&mut self) {
-        self.on().await;
+    pub async fn sleep(&mut self) -> Result<(), Error<I::Error>> {
+        self.on().await?;
 
         // lpcd reset
         self.regs().lpcd_ctrl1().write(|w| {
@@ -185,11 +314,11 @@ where
             w.set_en(true); // EN=0
             w.set_ie(true); // IE=0
             w.set_calibra_en(true); // CALIBRA_EN=0
-        });
+        })?;
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(true); // set bits written with 1
             w.set_rstn(true); // nRST=1
-        });
+        })?;
 
         // lpcd disable
         self.regs().lpcd_ctrl1().write(|w| {
@@ -198,276 +327,373 @@ where
             w.set_en(true); // EN=0
             w.set_ie(true); // IE=0
             w.set_calibra_en(true); // CALIBRA_EN=0
-        });
+        })?;
 
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(false); // clear bits written with 1
             w.set_en(true); // EN=0
-        });
+        })?;
 
         self.regs().lpcd_ctrl3().write(|w| {
             w.set_hpden(false);
-        });
+        })?;
 
         Timer::after(Duration::from_millis(1)).await; // give it some time
 
-        self.off();
+        self.off()
     }
 
-    pub async fn wait_for_card(&mut self, config: WakeupConfig) -> Result<(), Infallible> {
-        assert!((1..=15).contains(&config.sleep_time));
-        assert!((2..=31).contains(&config.prepare_time));
-        assert!((2..=31).contains(&config.measure_time));
-        assert!((0..=1).contains(&config.n_drive));
-        assert!((0..=7).contains(&config.p_drive));
+    /// Sleep in low power card detection mode until a card is detected.
+    ///
+    /// Returns what woke the chip up, and the calibration that was in use.
+    pub async fn wait_for_card(&mut self, config: WakeupConfig) -> Result<Wakeup, Error<I::Error>> {
+        config.validate().map_err(Error::Config)?;
+
+        let mut recalibrations = 0;
+        let mut calibration = self.lpcd_calibrate(&config).await?;
+        let mut calibrated_at = Instant::now();
 
         loop {
-            self.on().await;
-
-            //self.regs().command().write(|_| {});
-            self.regs().commien().write(|w| w.set_irqinv(true));
-            self.regs().divien().write(|w| w.set_irqpushpull(true));
-
-            // lpcd reset + enable
-            self.regs().lpcd_ctrl1().write(|w| {
-                w.set_bit_ctrl_set(false); // clear bits written with 1
-                w.set_rstn(true); // nRST=0
-                w.set_calibra_en(true); // CALIBRA_EN=0
-            });
-            self.regs().lpcd_ctrl1().write(|w| {
-                w.set_bit_ctrl_set(true); // set bits written with 1
-                w.set_rstn(true); // nRST=1
-                w.set_en(true); // EN=1
-                w.set_ie(true); // IE=1
-                w.set_sense_1(true); // SENSE1 = 1
-            });
-
-            self.regs().lpcd_ctrl2().write(|w| {
-                w.set_tx2en(true);
-                w.set_cwn(config.n_drive == 1);
-                w.set_cwp(config.p_drive);
-            });
-
-            self.regs().lpcd_ctrl3().write(|w| w.set_hpden(false));
-
-            let (t3clkdiv, adc_shift) = match config.measure_time {
-                16.. => (regs::LpcdT3clkdivk::DIV16, 3),
-                8.. => (regs::LpcdT3clkdivk::DIV8, 4),
-                0.. => (regs::LpcdT3clkdivk::DIV4, 5),
+            let recalibrate_interval = if calibration.failed {
+                // if calibration failed, force recalibrate very soon.
+                Duration::from_secs(10)
+            } else {
+                config.recalibrate_interval.unwrap_or(Duration::from_secs(3 * 60 * 60))
+            };
+            let recalibrate_at = calibrated_at + recalibrate_interval;
+            let wake_at = match config.drift {
+                Some(drift) => recalibrate_at.min(Instant::now() + drift.check_interval),
+                None => recalibrate_at,
             };
 
-            let adc_range = (config.measure_time - 1) << adc_shift;
-            let adc_center = adc_range / 2;
-
-            debug!("adc: range={} center={}", adc_range, adc_center);
-
-            self.regs().lpcd_t1cfg().write(|w| {
-                w.set_t1cfg(config.sleep_time);
-                w.set_t3clkdivk(t3clkdiv);
-            });
-            self.regs().lpcd_t2cfg().write(|w| w.set_t2cfg(config.prepare_time));
-            self.regs().lpcd_t3cfg().write(|w| w.set_t3cfg(config.measure_time));
-            self.regs().lpcd_vmid_bd_cfg().write(|w| w.set_vmid_bd_cfg(8));
-            self.regs().lpcd_auto_wup_cfg().write(|w| w.set_en(false));
-
-            self.regs().lpcd_misc().write(|w| w.set_calib_vmid_en(true));
-
-            // Calibrate! Note that:
-            // - Higher gain -> lower ADC reading
-            // - Higher reference voltage -> lower ADC reading
-
-            // First, find lowest gain (multiplier/divider) that satisfies "reading < center".
-            self.lpcd_set_adc_config(ADC_REFERENCE_MAX, 0);
-            let levels: [u8; 32] = [
-                0, 4, 2, 8, 6, 1, 10, 5, 12, 16, 9, 3, 14, 20, 18, 7, 24, 22, 13, 11, 17, 26, 28, 21, 15, 30, 25, 19, 23, 29,
-                27, 31,
-            ];
-
-            /*
-            for level in 0..levels.len() {
-                let mut vals = [0; ADC_REFERENCE_MAX as usize + 1];
-                for reference in ADC_REFERENCE_MIN..=ADC_REFERENCE_MAX {
-                    self.regs().lpcd_ctrl4().write_value(levels[level].into());
-                    self.lpcd_set_adc_config(reference as _, 0);
-
-                    vals[reference as usize] = self.lpcd_read_adc();
-                }
-                info!("level={} {}", level, vals);
-                embassy_futures::yield_now().await;
-            }
+            self.lpcd_arm(&calibration)?;
+
+            info!("Waiting for irq...");
+            match with_timeout(wake_at.saturating_duration_since(Instant::now()), self.irq.wait_for_low()).await {
+                Ok(Ok(())) => {
+                    info!("Got irq!");
 
-            return Ok(());
-            */
-
-            let mut failed = false;
-
-            let level = binary_search(0, levels.len() as _, |val| {
-                self.regs().lpcd_ctrl4().write_value(levels[val as usize].into());
-                let meas = self.lpcd_read_adc();
-                let res = meas < adc_center;
-                debug!("adc search level: {} => {} {}", val, meas, res);
-                res
-            });
-            let level = match level {
-                Some(x) => x as usize,
-                None => {
-                    warn!("Gain calibration failed.");
-                    failed = true;
-                    levels.len() - 1
+                    // Wake up without reset, so the LPCD registers are kept.
+                    self.set_npd(true)?;
+                    Timer::after(Duration::from_millis(1)).await;
+                    let irqs = self.regs().lpcd_irq().read()?;
+                    let reading = self.lpcd_get_adc_value()?;
+
+                    let cause = if irqs.card_in_irq() {
+                        WakeupCause::CardDetected
+                    } else if irqs.auto_wup_irq() {
+                        WakeupCause::AutoWakeup
+                    } else {
+                        WakeupCause::Unknown
+                    };
+                    debug!(
+                        "wakeup: cause={:?} reading={} baseline={}",
+                        cause, reading, calibration.baseline
+                    );
+
+                    return Ok(Wakeup {
+                        cause,
+                        reading,
+                        calibration,
+                        recalibrations,
+                    });
                 }
-            };
-            debug!("adc level {}", level);
-            self.regs().lpcd_ctrl4().write_value(levels[level].into());
-
-            // Second, find lowest reference voltage that satisfies "reading < center".
-            let reference = binary_search(ADC_REFERENCE_MIN as _, ADC_REFERENCE_MAX as _, |val| {
-                self.lpcd_set_adc_config(val as _, 0);
-                let meas = self.lpcd_read_adc();
-                let res = meas < adc_center;
-                debug!("adc search refer: {} => {} {}", val, meas, res);
-                res
-            });
-            let reference = match reference {
-                Some(x) => x as u8,
-                None => {
-                    warn!("Reference voltage calibration failed.");
-                    failed = true;
-                    ADC_REFERENCE_MAX
+                Ok(Err(e)) => {
+                    warn!("irq.wait_for_low() error");
+                    return Err(Error::Pin(e.kind()));
                 }
-            };
-            debug!("adc refer {}", reference);
-            self.lpcd_set_adc_config(reference, 0);
-
-            // Configure threshold based on current reading.
-            let curr = self.lpcd_read_adc();
-            let threshold_offs = ((adc_range as u32) * (config.threshold as u32) / 256) as u8;
-            let threshold_min = curr.saturating_sub(threshold_offs);
-            let threshold_max = curr.saturating_add(threshold_offs);
-            debug!(
-                "adc: curr={} threshold_offs={} threshold_min={} threshold_max={}",
-                curr, threshold_offs, threshold_min, threshold_max
-            );
-            self.regs().lpcd_threshold_min_l().write_value(threshold_min & 0x3F);
-            self.regs().lpcd_threshold_min_h().write_value(threshold_min >> 6);
-            self.regs().lpcd_threshold_max_l().write_value(threshold_max & 0x3F);
-            self.regs().lpcd_threshold_max_h().write_value(threshold_max >> 6);
-
-            /*
-            loop {
-                let r = self.lpcd_read_adc();
-                if r < threshold_min || r > threshold_max {
-                    info!(" res: {=u8} ====== CARD DETECTED", r);
-                } else {
-                    info!(" res: {=u8}", r);
+                Err(TimeoutError) => {}
+            }
+
+            if Instant::now() >= recalibrate_at {
+                info!("timed out, recalibrating...");
+            } else if let Some(drift) = config.drift {
+                let reading = self.lpcd_measure(&config, &calibration).await?;
+                match drift_check(&calibration, &drift, reading) {
+                    DriftCheck::Stable => {
+                        debug!("drift check: reading={} baseline={}", reading, calibration.baseline);
+                        continue;
+                    }
+                    DriftCheck::Drifted => {
+                        info!(
+                            "baseline drifted: reading={} baseline={}, recalibrating...",
+                            reading, calibration.baseline
+                        );
+                    }
+                    DriftCheck::CardDetected => {
+                        // A card showed up while the chip was awake for the check. Recalibrating
+                        // now would make it the new baseline, and it'd never be detected.
+                        info!("drift check: reading={} out of thresholds, card detected", reading);
+                        return Ok(Wakeup {
+                            cause: WakeupCause::CardDetected,
+                            reading,
+                            calibration,
+                            recalibrations,
+                        });
+                    }
                 }
-                Timer::after(Duration::from_millis(30)).await;
             }
-            */
 
-            self.regs().lpcd_misc().write(|w| w.set_calib_vmid_en(false));
-            self.regs().lpcd_auto_wup_cfg().write(|w| {
-                w.set_en(false);
-                w.set_time(regs::LpcdAutoWupTime::_1HOUR);
-            });
+            calibration = self.lpcd_calibrate(&config).await?;
+            calibrated_at = Instant::now();
+            recalibrations += 1;
+        }
+    }
 
-            self.regs().lpcd_ctrl1().write(|w| {
-                w.set_bit_ctrl_set(false);
-                w.set_rstn(true); // nRST = 0
-            });
-            self.regs().lpcd_ctrl1().write(|w| {
-                w.set_bit_ctrl_set(true);
-                w.set_rstn(true); // nRST = 1
-            });
+    /// Power on and configure the LPCD block, leaving the ADC ready for measurements.
+    /// Returns the ADC range.
+    async fn lpcd_setup(&mut self, config: &WakeupConfig) -> Result<u8, Error<I::Error>> {
+        self.on().await?;
 
-            //self.dump();
+        //self.regs().command().write(|_| {});
+        self.regs().commien().write(|w| w.set_irqinv(true))?;
+        self.regs().divien().write(|w| w.set_irqpushpull(true))?;
 
-            self.npd.set_low().unwrap();
+        // lpcd reset + enable
+        self.regs().lpcd_ctrl1().write(|w| {
+            w.set_bit_ctrl_set(false); // clear bits written with 1
+            w.set_rstn(true); // nRST=0
+            w.set_calibra_en(true); // CALIBRA_EN=0
+        })?;
+        self.regs().lpcd_ctrl1().write(|w| {
+            w.set_bit_ctrl_set(true); // set bits written with 1
+            w.set_rstn(true); // nRST=1
+            w.set_en(true); // EN=1
+            w.set_ie(true); // IE=1
+            w.set_sense_1(true); // SENSE1 = 1
+        })?;
+
+        self.regs().lpcd_ctrl2().write(|w| {
+            w.set_tx2en(true);
+            w.set_cwn(config.n_drive == 1);
+            w.set_cwp(config.p_drive);
+        })?;
+
+        self.regs().lpcd_ctrl3().write(|w| w.set_hpden(false))?;
+
+        let (t3clkdiv, adc_shift) = match config.measure_time {
+            16.. => (regs::LpcdT3clkdivk::DIV16, 3),
+            8.. => (regs::LpcdT3clkdivk::DIV8, 4),
+            0.. => (regs::LpcdT3clkdivk::DIV4, 5),
+        };
+
+        let adc_range = (config.measure_time - 1) << adc_shift;
+
+        self.regs().lpcd_t1cfg().write(|w| {
+            w.set_t1cfg(config.sleep_time);
+            w.set_t3clkdivk(t3clkdiv);
+        })?;
+        self.regs().lpcd_t2cfg().write(|w| w.set_t2cfg(config.prepare_time))?;
+        self.regs().lpcd_t3cfg().write(|w| w.set_t3cfg(config.measure_time))?;
+        self.regs().lpcd_vmid_bd_cfg().write(|w| w.set_vmid_bd_cfg(8))?;
+        self.regs().lpcd_auto_wup_cfg().write(|w| w.set_en(false))?;
+
+        self.regs().lpcd_misc().write(|w| w.set_calib_vmid_en(true))?;
+
+        Ok(adc_range)
+    }
 
-            let dur = if failed {
-                // if calibration failed, force recalibrate very soon.
-                Duration::from_secs(10)
-            } else {
-                config.recalibrate_interval.unwrap_or(Duration::from_secs(3 * 60 * 60))
-            };
+    /// Find the gain and reference for the current environment, and set the thresholds around the resulting reading.
+    async fn lpcd_calibrate(&mut self, config: &WakeupConfig) -> Result<LpcdCalibration, Error<I::Error>> {
+        let adc_range = self.lpcd_setup(config).await?;
+        let adc_center = adc_range / 2;
 
-            info!("Waiting for irq...");
-            match with_timeout(dur, self.irq.wait_for_low()).await {
-                Ok(Ok(())) => {
-                    info!("Got irq!");
+        debug!("adc: range={} center={}", adc_range, adc_center);
 
-                    //self.npd.set_high().unwrap();
-                    //Timer::after(Duration::from_millis(1)).await;
-                    //self.dump();
-                    //self.regs().lpcd_misc().write(|w| w.set_calib_vmid_en(true));
-                    //info!(" NOW READ: {=u8}", self.lpcd_read_adc());
+        // Calibrate! Note that:
+        // - Higher gain -> lower ADC reading
+        // - Higher reference voltage -> lower ADC reading
 
-                    return Ok(());
-                }
-                Ok(Err(_)) => warn!("irq.wait_for_low() error"),
-                Err(TimeoutError) => info!("timed out, recalibrating..."),
+        // First, find lowest gain (multiplier/divider) that satisfies "reading < center".
+        self.lpcd_set_adc_config(ADC_REFERENCE_MAX, 0)?;
+
+        /*
+        for level in 0..LPCD_GAIN_LEVELS.len() {
+            let mut vals = [0; ADC_REFERENCE_MAX as usize + 1];
+            for reference in ADC_REFERENCE_MIN..=ADC_REFERENCE_MAX {
+                self.regs().lpcd_ctrl4().write_value(LPCD_GAIN_LEVELS[level].into())?;
+                self.lpcd_set_adc_config(reference as _, 0)?;
+
+                vals[reference as usize] = self.lpcd_read_adc()?;
+            }
+            info!("level={} {}", level, vals);
+            embassy_futures::yield_now().await;
+        }
+        */
+
+        let mut failed = false;
+
+        let level = binary_search(0, LPCD_GAIN_LEVELS.len() as _, |val| {
+            self.regs().lpcd_ctrl4().write_value(LPCD_GAIN_LEVELS[val as usize].into())?;
+            let meas = self.lpcd_read_adc()?;
+            let res = meas < adc_center;
+            debug!("adc search level: {} => {} {}", val, meas, res);
+            Ok(res)
+        })?;
+        let level = match level {
+            Some(x) => x as u8,
+            None => {
+                warn!("Gain calibration failed.");
+                failed = true;
+                LPCD_GAIN_LEVELS.len() as u8 - 1
+            }
+        };
+        debug!("adc level {}", level);
+        self.regs()
+            .lpcd_ctrl4()
+            .write_value(LPCD_GAIN_LEVELS[level as usize].into())?;
+
+        // Second, find lowest reference voltage that satisfies "reading < center".
+        let reference = binary_search(ADC_REFERENCE_MIN as _, ADC_REFERENCE_MAX as _, |val| {
+            self.lpcd_set_adc_config(val as _, 0)?;
+            let meas = self.lpcd_read_adc()?;
+            let res = meas < adc_center;
+            debug!("adc search refer: {} => {} {}", val, meas, res);
+            Ok(res)
+        })?;
+        let reference = match reference {
+            Some(x) => x as u8,
+            None => {
+                warn!("Reference voltage calibration failed.");
+                failed = true;
+                ADC_REFERENCE_MAX
             }
+        };
+        debug!("adc refer {}", reference);
+        self.lpcd_set_adc_config(reference, 0)?;
+
+        // Configure threshold based on current reading.
+        let baseline = self.lpcd_read_adc()?;
+        let threshold_offs = threshold_offset(adc_range, config.threshold);
+        let threshold_min = baseline.saturating_sub(threshold_offs);
+        let threshold_max = baseline.saturating_add(threshold_offs);
+        debug!(
+            "adc: curr={} threshold_offs={} threshold_min={} threshold_max={}",
+            baseline, threshold_offs, threshold_min, threshold_max
+        );
+
+        /*
+        loop {
+            let r = self.lpcd_read_adc()?;
+            if r < threshold_min || r > threshold_max {
+                info!(" res: {=u8} ====== CARD DETECTED", r);
+            } else {
+                info!(" res: {=u8}", r);
+            }
+            Timer::after(Duration::from_millis(30)).await;
         }
+        */
+
+        Ok(LpcdCalibration {
+            gain_level: level,
+            reference,
+            baseline,
+            threshold_min,
+            threshold_max,
+            adc_range,
+            failed,
+        })
     }
 
-    fn _dump(&mut self) {
+    /// Take a single ADC reading with an existing calibration.
+    async fn lpcd_measure(&mut self, config: &WakeupConfig, calibration: &LpcdCalibration) -> Result<u8, Error<I::Error>> {
+        self.lpcd_setup(config).await?;
+        self.regs()
+            .lpcd_ctrl4()
+            .write_value(LPCD_GAIN_LEVELS[calibration.gain_level as usize].into())?;
+        self.lpcd_set_adc_config(calibration.reference, 0)?;
+        self.lpcd_read_adc()
+    }
+
+    /// Program the thresholds of the current calibration, and power down in LPCD mode.
+    fn lpcd_arm(&mut self, calibration: &LpcdCalibration) -> Result<(), Error<I::Error>> {
+        let (min, max) = (calibration.threshold_min, calibration.threshold_max);
+        self.regs().lpcd_threshold_min_l().write_value(min & 0x3F)?;
+        self.regs().lpcd_threshold_min_h().write_value(min >> 6)?;
+        self.regs().lpcd_threshold_max_l().write_value(max & 0x3F)?;
+        self.regs().lpcd_threshold_max_h().write_value(max >> 6)?;
+
+        self.regs().lpcd_misc().write(|w| w.set_calib_vmid_en(false))?;
+        self.regs().lpcd_auto_wup_cfg().write(|w| {
+            w.set_en(false);
+            w.set_time(regs::LpcdAutoWupTime::_1HOUR);
+        })?;
+
+        self.regs().lpcd_ctrl1().write(|w| {
+            w.set_bit_ctrl_set(false);
+            w.set_rstn(true); // nRST = 0
+        })?;
+        self.regs().lpcd_ctrl1().write(|w| {
+            w.set_bit_ctrl_set(true);
+            w.set_rstn(true); // nRST = 1
+        })?;
+
+        //self.dump();
+
+        self.set_npd(false)
+    }
+
+    fn _dump(&mut self) -> Result<(), Error<I::Error>> {
         info!("==============");
         info!(
             "comirq {:02x} divirq {:02x} lpcdirq {:02x}",
-            self.regs().commirq().read().0,
-            self.regs().divirq().read().0,
-            self.regs().lpcd_irq().read().0
+            self.regs().commirq().read()?.0,
+            self.regs().divirq().read()?.0,
+            self.regs().lpcd_irq().read()?.0
         );
         info!(
             "ctrl1={:02x} ctrl2={:02x} ctrl3={:02x} ctrl4={:02x} misc={:02x}",
-            self.regs().lpcd_ctrl1().read().0,
-            self.regs().lpcd_ctrl2().read().0,
-            self.regs().lpcd_ctrl3().read().0,
-            self.regs().lpcd_ctrl4().read().0,
-            self.regs().lpcd_misc().read().0,
+            self.regs().lpcd_ctrl1().read()?.0,
+            self.regs().lpcd_ctrl2().read()?.0,
+            self.regs().lpcd_ctrl3().read()?.0,
+            self.regs().lpcd_ctrl4().read()?.0,
+            self.regs().lpcd_misc().read()?.0,
         );
         info!(
             "t1cfg={:02x} t2cfg={:02x} t3cfg={:02x} adcref={:02x} adcbcu={:02x}",
-            self.regs().lpcd_t1cfg().read().0,
-            self.regs().lpcd_t2cfg().read().0,
-            self.regs().lpcd_t3cfg().read().0,
-            self.regs().lpcd_adc_referece().read(),
-            self.regs().lpcd_bias_current().read().0,
+            self.regs().lpcd_t1cfg().read()?.0,
+            self.regs().lpcd_t2cfg().read()?.0,
+            self.regs().lpcd_t3cfg().read()?.0,
+            self.regs().lpcd_adc_referece().read()?,
+            self.regs().lpcd_bias_current().read()?.0,
         );
 
-        info!("adc val {}", self.lpcd_get_adc_value());
+        info!("adc val {}", self.lpcd_get_adc_value()?);
+        Ok(())
     }
 
-    fn lpcd_set_adc_config(&mut self, reference: u8, bias_current: u8) {
-        self.regs().lpcd_adc_referece().write_value(reference & 0x3F);
+    fn lpcd_set_adc_config(&mut self, reference: u8, bias_current: u8) -> Result<(), Error<I::Error>> {
+        self.regs().lpcd_adc_referece().write_value(reference & 0x3F)?;
         self.regs().lpcd_bias_current().write(|w| {
             w.set_adc_referece_h((reference >> 6) != 0);
             w.set_bias_current(bias_current);
-        });
+        })?;
+        Ok(())
     }
 
-    fn lpcd_read_adc(&mut self) -> u8 {
+    fn lpcd_read_adc(&mut self) -> Result<u8, Error<I::Error>> {
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(false);
             w.set_rstn(true); // nRST = 0
             w.set_calibra_en(true); // calibra_en = 0
-        });
+        })?;
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(true);
             w.set_rstn(true); // nRST = 1
-        });
+        })?;
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(true);
             w.set_calibra_en(true); // calibra_en = 1
-        });
+        })?;
 
         //cortex_m::asm::delay(640_000); // 100ms
 
         //info!("calib: waiting for irq..");
         let deadline = Instant::now() + Duration::from_secs(1);
-        while !self.regs().lpcd_irq().read().calib_irq() {
+        while !self.regs().lpcd_irq().read()?.calib_irq() {
             if Instant::now() > deadline {
                 warn!("timeout waiting for adc calibration.");
-                break;
+                return Err(Error::Timeout);
             }
         }
 
@@ -475,40 +701,15 @@ where
         self.regs().lpcd_ctrl1().write(|w| {
             w.set_bit_ctrl_set(false);
             w.set_calibra_en(true);
-        });
+        })?;
 
         self.lpcd_get_adc_value()
     }
 
-    fn lpcd_get_adc_value(&mut self) -> u8 {
-        let h = self.regs().lpcd_adc_result_h().read();
-        let l = self.regs().lpcd_adc_result_l().read();
-        ((h & 0x3) << 6) | (l & 0x3f)
-    }
-
-    fn clear_fifo(&mut self) {
-        self.regs().fifolevel().write(|w| w.set_flushfifo(true));
-    }
-
-    fn set_timer(&mut self, onefc: u32) {
-        let mut prescaler: u32 = 0;
-        let mut timereload: u32 = 0;
-        while prescaler < 0xfff {
-            timereload = (onefc - 1).div_ceil(prescaler * 2 + 1);
-
-            if timereload < 0xffff {
-                break;
-            }
-            prescaler += 1;
-        }
-        timereload = timereload & 0xFFFF;
-        self.regs().tmode().write(|w| {
-            w.set_tauto(true);
-            w.set_tprescaler_hi((prescaler >> 8) as u8);
-        });
-        self.regs().tprescaler().write_value(prescaler as u8);
-        self.regs().treloadhi().write_value((timereload >> 8) as u8);
-        self.regs().treloadlo().write_value(timereload as u8);
+    fn lpcd_get_adc_value(&mut self) -> Result<u8, Error<I::Error>> {
+        let h = self.regs().lpcd_adc_result_h().read()?;
+        let l = self.regs().lpcd_adc_result_l().read()?;
+        Ok(((h & 0x3) << 6) | (l & 0x3f))
     }
 
     /*
@@ -526,32 +727,60 @@ where
         Raw { inner: self }
     }
 
-    pub fn set_config(&mut self, config: RfConfig) {
+    pub fn set_config(&mut self, config: RfConfig) -> Result<(), ConfigError> {
+        config.validate()?;
         self.config = config;
+        Ok(())
+    }
+}
+
+/// Wakeup threshold, as an offset from the baseline ADC reading.
+fn threshold_offset(adc_range: u8, threshold: u8) -> u8 {
+    ((adc_range as u32) * (threshold as u32) / 256) as u8
+}
+
+/// Outcome of a drift check reading.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum DriftCheck {
+    /// Close enough to the baseline, keep the calibration.
+    Stable,
+    /// Within the wakeup thresholds but away from the baseline, recalibrate.
+    Drifted,
+    /// Out of the wakeup thresholds, same as if the chip had woken up.
+    CardDetected,
+}
+
+fn drift_check(calibration: &LpcdCalibration, drift: &DriftConfig, reading: u8) -> DriftCheck {
+    if reading < calibration.threshold_min || reading > calibration.threshold_max {
+        DriftCheck::CardDetected
+    } else if reading.abs_diff(calibration.baseline) <= threshold_offset(calibration.adc_range, drift.threshold) {
+        DriftCheck::Stable
+    } else {
+        DriftCheck::Drifted
     }
 }
 
 /// Find lowest value in min..max (min included, max excluded)
 /// satisfying `f(val) = true`.
 ///
-/// If `f` returns `false` for all values, returns `None`.
+/// If `f` returns `false` for all values, returns `None`. Errors from `f` are returned immediately.
 ///
 /// `f` is assumed to be monotonically increasing.
-fn binary_search(mut min: i32, mut max: i32, mut f: impl FnMut(i32) -> bool) -> Option<i32> {
+fn binary_search<E>(mut min: i32, mut max: i32, mut f: impl FnMut(i32) -> Result<bool, E>) -> Result<Option<i32>, E> {
     let orig_max = max;
     min -= 1;
     while min + 1 < max {
         let m = (min + max) / 2;
-        if f(m) {
+        if f(m)? {
             max = m
         } else {
             min = m
         }
     }
     if max == orig_max {
-        None
+        Ok(None)
     } else {
-        Some(max)
+        Ok(Some(max))
     }
 }
 
@@ -570,18 +799,58 @@ where
     NpdPin: OutputPin,
     IrqPin: InputPin + Wait,
 {
-    pub async fn field_on(&mut self) -> Result<(), Infallible> {
-        self.inner.on().await;
-        self.inner.rf_on();
-
-        Ok(())
+    pub async fn field_on(&mut self) -> Result<(), Error<I::Error>> {
+        self.inner.on().await?;
+        self.inner.rf_on()
     }
-    pub async fn field_off(&mut self) -> Result<(), Infallible> {
-        self.inner.off();
-        Ok(())
+    pub async fn field_off(&mut self) -> Result<(), Error<I::Error>> {
+        self.inner.off()
     }
-    pub async fn driver_hi_z(&mut self) -> Result<(), Infallible> {
-        self.inner.off();
-        Ok(())
+    pub async fn driver_hi_z(&mut self) -> Result<(), Error<I::Error>> {
+        self.inner.off()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drift_check_out_of_thresholds() {
+        let calibration = LpcdCalibration {
+            gain_level: 10,
+            reference: 0x40,
+            baseline: 100,
+            threshold_min: 90,
+            threshold_max: 110,
+            adc_range: 240,
+            failed: false,
+        };
+        // threshold_offset(240, 16) = 15
+        let drift = DriftConfig {
+            check_interval: Duration::from_secs(1),
+            threshold: 16,
+        };
+
+        assert_eq!(drift_check(&calibration, &drift, 100), DriftCheck::Stable);
+        assert_eq!(drift_check(&calibration, &drift, 94), DriftCheck::Stable);
+        assert_eq!(drift_check(&calibration, &drift, 106), DriftCheck::Stable);
+        // Within the drift threshold, but out of the wakeup ones.
+        assert_eq!(drift_check(&calibration, &drift, 112), DriftCheck::CardDetected);
+        // Past the drift threshold, but a card rather than drift: don't recalibrate onto it.
+        assert_eq!(drift_check(&calibration, &drift, 120), DriftCheck::CardDetected);
+
+        // With a drift threshold below the wakeup one, there's a window to recalibrate in.
+        let drift = DriftConfig { threshold: 4, ..drift };
+        assert_eq!(drift_check(&calibration, &drift, 103), DriftCheck::Stable);
+        assert_eq!(drift_check(&calibration, &drift, 104), DriftCheck::Drifted);
+        assert_eq!(drift_check(&calibration, &drift, 90), DriftCheck::Drifted);
+        assert_eq!(drift_check(&calibration, &drift, 110), DriftCheck::Drifted);
+
+        // Out of the wakeup thresholds it's a card, not drift, whatever the drift threshold.
+        assert_eq!(drift_check(&calibration, &drift, 89), DriftCheck::CardDetected);
+        assert_eq!(drift_check(&calibration, &drift, 111), DriftCheck::CardDetected);
+        assert_eq!(drift_check(&calibration, &drift, 0), DriftCheck::CardDetected);
+        assert_eq!(drift_check(&calibration, &drift, 255), DriftCheck::CardDetected);
     }
 }
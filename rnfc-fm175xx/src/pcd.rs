@@ -0,0 +1,306 @@
+//! Register-level core shared with the MFRC522 family.
+//!
+//! The FM175xx main register set (0x00..0x40), command set and FIFO are compatible with
+//! the MFRC522, so everything here works on both. The FM175xx extras (LPCD, extended
+//! registers) are not used.
+
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Instant, Timer};
+
+use crate::regs::{self, Regs};
+use crate::{iso14443a, Error, Interface, RfConfig, FIFO_SIZE};
+
+/// Options for [`Pcd::transceive_raw`].
+#[derive(Clone, Copy)]
+pub struct TrxOpts {
+    pub crc: bool,
+    pub timeout_1fc: u32,
+    /// Number of bits to send of the last TX byte. 0 means all 8.
+    pub tx_last_bits: u8,
+    /// Bit position of the first received bit in the first RX byte.
+    pub rx_align: u8,
+    /// Keep the receiver on after the first frame, until the timer expires.
+    /// Each received frame is followed by an error status byte in `rx`.
+    pub rx_multiple: bool,
+    /// Bits received after a collision are cleared, instead of being kept.
+    pub anticoll: bool,
+}
+
+impl TrxOpts {
+    pub fn standard(timeout_1fc: u32) -> Self {
+        Self {
+            crc: true,
+            timeout_1fc,
+            tx_last_bits: 0,
+            rx_align: 0,
+            rx_multiple: false,
+            anticoll: false,
+        }
+    }
+}
+
+/// Register access and transceive logic over an [`Interface`].
+pub struct Pcd<I> {
+    iface: I,
+}
+
+impl<I: Interface> Pcd<I> {
+    pub fn new(iface: I) -> Self {
+        Self { iface }
+    }
+
+    pub fn regs(&mut self) -> Regs<I> {
+        Regs {
+            iface: &mut self.iface,
+            addr: 0,
+        }
+    }
+
+    /// Run a SOFTRESET command and wait for it to finish. Returns the `version` register.
+    pub async fn soft_reset(&mut self) -> Result<u8, Error<I::Error>> {
+        debug!("softreset");
+        self.regs().command().write(|w| w.set_command(regs::CommandVal::SOFTRESET))?;
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while self.regs().command().read()?.command() != regs::CommandVal::IDLE {
+            if Instant::now() > deadline {
+                warn!("timeout waiting for softreset.");
+                return Err(Error::Timeout);
+            }
+        }
+
+        // datasheet doesn't say anything about time after reset, wait a bit just in case.
+        Timer::after(Duration::from_millis(1)).await;
+
+        let ver = self.regs().version().read()?;
+        debug!("IC version: {:02x}", ver);
+        Ok(ver)
+    }
+
+    /// Set the driver strengths, and turn on the receiver and the field.
+    pub fn rf_on(&mut self, config: &RfConfig) -> Result<(), Error<I::Error>> {
+        self.regs().gsn().write(|w| {
+            w.set_cwgsn(config.n_drive_cw); // reset value: 8
+            w.set_modgsn(config.n_drive_mod); // reset value: 8
+        })?;
+        self.regs().cwgsp().write(|w| {
+            w.set_cwgsp(config.p_drive_cw); // reset value: 32
+        })?;
+        self.regs().modgsp().write(|w| {
+            w.set_modgsp(config.p_drive_mod); // reset value: 32
+        })?;
+
+        self.regs().command().write(|w| {
+            w.set_powerdown(false);
+            w.set_rcvoff(false);
+        })?;
+
+        self.regs().txcontrol().write(|w| {
+            w.set_tx1rfen(true);
+            w.set_tx2rfen(true);
+            w.set_invtx2on(true);
+        })?;
+        Ok(())
+    }
+
+    pub fn clear_fifo(&mut self) -> Result<(), Error<I::Error>> {
+        self.regs().fifolevel().write(|w| w.set_flushfifo(true))
+    }
+
+    pub fn set_timer(&mut self, onefc: u32) -> Result<(), Error<I::Error>> {
+        let mut prescaler: u32 = 0;
+        let mut timereload: u32 = 0;
+        while prescaler < 0xfff {
+            timereload = onefc.saturating_sub(1).div_ceil(prescaler * 2 + 1);
+
+            if timereload < 0xffff {
+                break;
+            }
+            prescaler += 1;
+        }
+        timereload &= 0xFFFF;
+        self.regs().tmode().write(|w| {
+            w.set_tauto(true);
+            w.set_tprescaler_hi((prescaler >> 8) as u8);
+        })?;
+        self.regs().tprescaler().write_value(prescaler as u8)?;
+        self.regs().treloadhi().write_value((timereload >> 8) as u8)?;
+        self.regs().treloadlo().write_value(timereload as u8)?;
+        Ok(())
+    }
+
+    /// Run a TRANSCEIVE command. Returns the number of bytes received into `rx`,
+    /// and whether a collision was detected.
+    ///
+    /// Framing, speed and CRC preset must be already set up by the session.
+    pub async fn transceive_raw(
+        &mut self,
+        tx: &[u8],
+        rx: &mut [u8],
+        opts: TrxOpts,
+    ) -> Result<(usize, bool), iso14443a::Error<I::Error>> {
+        use iso14443a::Error;
+
+        // Set CRC
+        self.regs().txmode().modify(|w| w.set_crcen(opts.crc))?;
+        self.regs().rxmode().modify(|w| {
+            w.set_crcen(opts.crc);
+            w.set_rxmultiple(opts.rx_multiple);
+        })?;
+
+        // Set timeout
+        self.set_timer(opts.timeout_1fc)?;
+
+        // Halt whatever currently running command.
+        self.regs().command().write(|w| {
+            w.set_command(regs::CommandVal::IDLE);
+        })?;
+
+        // Clear all IRQs
+        self.regs().divirq().write_value(0x7f.into())?;
+        self.regs().commirq().write_value(0x7f.into())?;
+
+        self.clear_fifo()?;
+
+        self.regs().coll().write(|w| {
+            w.set_valuesaftercoll(!opts.anticoll);
+        })?;
+
+        let mut collision = false;
+
+        let mut tx_pos = 0;
+        let mut write_fifo = |r: &mut Self| {
+            if tx_pos >= tx.len() {
+                return Ok::<(), Error<I::Error>>(());
+            }
+
+            let used = r.regs().fifolevel().read()?.level() as usize;
+            let free = FIFO_SIZE.saturating_sub(used);
+            let n = free.min(tx.len() - tx_pos);
+            r.iface.write_fifo(&tx[tx_pos..][..n]).map_err(Error::Interface)?;
+            tx_pos += n;
+            Ok(())
+        };
+
+        let mut rx_pos = 0;
+        let mut read_fifo = |r: &mut Self| {
+            let bytes = r.regs().fifolevel().read()?.level() as usize;
+            if rx_pos + bytes > rx.len() {
+                warn!("rx overflow! received {} but buffer is only {}", rx_pos + bytes, rx.len());
+                return Err(Error::Other);
+            }
+            r.iface.read_fifo(&mut rx[rx_pos..][..bytes]).map_err(Error::Interface)?;
+            rx_pos += bytes;
+            Ok(())
+        };
+
+        // Fill FIFO as much as we can, to begin with.
+        write_fifo(self)?;
+
+        // Start trx
+        self.regs().command().write(|w| {
+            w.set_command(regs::CommandVal::TRANSCEIVE);
+        })?;
+
+        self.regs().bitframing().write(|w| {
+            w.set_startsend(true);
+            w.set_rxalign(opts.rx_align);
+            w.set_txlastbits(opts.tx_last_bits);
+        })?;
+
+        let mut tx_done = false;
+        let deadline = Instant::now() + Duration::from_secs(1);
+        loop {
+            // make sure to not loop forever if timeri never fires for whatever reason.
+            if Instant::now() > deadline {
+                warn!("emergency timeout");
+                return Err(Error::Other);
+            }
+
+            let mut irqs = self.regs().commirq().read()?;
+
+            if irqs.timeri() {
+                trace!("irq: timeri");
+                if opts.rx_multiple {
+                    // End of the receive window.
+                    self.regs().command().write(|w| {
+                        w.set_command(regs::CommandVal::IDLE);
+                    })?;
+                    break;
+                }
+                return Err(Error::Timeout);
+            }
+
+            if irqs.erri() {
+                trace!("irq: ERR");
+                let errs = self.regs().error().read()?;
+                if errs.bufferovfl() {
+                    warn!("err: buffer overflow");
+                    return Err(Error::Other);
+                }
+                if errs.temperr() {
+                    warn!("err: temperature");
+                    return Err(Error::Other);
+                }
+                if errs.wrerr() {
+                    warn!("err: write access error??");
+                    return Err(Error::Other);
+                }
+                // With rx_multiple, the other errors are reported per frame in the FIFO.
+                if !opts.rx_multiple {
+                    if errs.collerr() {
+                        debug!("err: collision");
+                        collision = true;
+                    }
+                    if errs.crcerr() {
+                        warn!("err: bad CRC");
+                        return Err(Error::Crc);
+                    }
+                    if errs.parityerr() && !collision {
+                        warn!("err: parity");
+                        return Err(Error::Crc);
+                    }
+                    if errs.proterr() {
+                        warn!("err: protocol");
+                        return Err(Error::Protocol);
+                    }
+                    if errs.rferr() {
+                        warn!("err: rf");
+                        return Err(Error::Protocol);
+                    }
+                }
+            }
+
+            if irqs.txi() {
+                trace!("irq: tx done");
+                tx_done = true;
+            }
+            if irqs.rxi() {
+                trace!("irq: rx done");
+                if !opts.rx_multiple {
+                    break;
+                }
+            }
+
+            irqs.set_set(false);
+            self.regs().commirq().write_value(irqs)?;
+
+            if tx_done {
+                read_fifo(self)?;
+            } else {
+                write_fifo(self)?;
+            }
+
+            yield_now().await;
+        }
+
+        if tx_pos != tx.len() {
+            warn!("TX fifo underflow (tx done fired before we wrote the bytes)");
+            return Err(Error::Other);
+        }
+
+        read_fifo(self)?;
+
+        Ok((rx_pos, collision))
+    }
+}
@@ -47,39 +47,39 @@ impl<'a, I: Interface, T: Copy + Into<u8> + From<u8>, A: Access> Reg<'a, I, T, A
         }
     }
 
-    pub fn read(&mut self) -> T
+    pub fn read(&mut self) -> Result<T, crate::Error<I::Error>>
     where
         A: Read,
     {
-        self.iface.read_reg(self.addr).into()
+        Ok(self.iface.read_reg(self.addr).map_err(crate::Error::Interface)?.into())
     }
 
-    pub fn write_value(&mut self, val: T)
+    pub fn write_value(&mut self, val: T) -> Result<(), crate::Error<I::Error>>
     where
         A: Write,
     {
-        self.iface.write_reg(self.addr, val.into())
+        self.iface.write_reg(self.addr, val.into()).map_err(crate::Error::Interface)
     }
 
-    pub fn modify<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R
+    pub fn modify<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Result<R, crate::Error<I::Error>>
     where
         A: Read + Write,
     {
-        let mut val = self.read();
+        let mut val = self.read()?;
         let res = f(&mut val);
-        self.write_value(val);
-        res
+        self.write_value(val)?;
+        Ok(res)
     }
 
-    pub fn write<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R
+    pub fn write<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> Result<R, crate::Error<I::Error>>
     where
         A: Write,
         T: Default,
     {
         let mut val = Default::default();
         let res = f(&mut val);
-        self.write_value(val);
-        res
+        self.write_value(val)?;
+        Ok(res)
     }
 }
 
@@ -0,0 +1,394 @@
+//! Automatic [`RfConfig`] tuning against a reference card.
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_async::digital::Wait;
+use rnfc_traits::iso14443a_ll::{Frame, Reader};
+
+use crate::{iso14443a, ConfigError, Error, Fm175xx, Interface, RfConfig, RxGain};
+
+/// Values to try for each [`RfConfig`] field. Every combination is tried.
+///
+/// Fields not swept are taken from the current config.
+#[derive(Clone, Copy)]
+pub struct TuningSweep<'a> {
+    /// Used for both `n_drive_cw` and `n_drive_mod`.
+    pub n_drive: &'a [u8],
+    /// Used for both `p_drive_cw` and `p_drive_mod`.
+    pub p_drive: &'a [u8],
+    pub rx_gain: &'a [RxGain],
+    pub minlevel: &'a [u8],
+    pub colllevel: &'a [u8],
+    /// Anticollision attempts for each setting.
+    pub attempts: u8,
+}
+
+impl TuningSweep<'_> {
+    /// Number of settings in the sweep.
+    pub fn len(&self) -> usize {
+        self.n_drive.len() * self.p_drive.len() * self.rx_gain.len() * self.minlevel.len() * self.colllevel.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, base: RfConfig, mut i: usize) -> RfConfig {
+        let mut pick = |vals: &[u8]| {
+            let v = vals[i % vals.len()];
+            i /= vals.len();
+            v
+        };
+        let n_drive = pick(self.n_drive);
+        let p_drive = pick(self.p_drive);
+        let minlevel = pick(self.minlevel);
+        let colllevel = pick(self.colllevel);
+        let rx_gain = self.rx_gain[i % self.rx_gain.len()];
+
+        RfConfig {
+            n_drive_cw: n_drive,
+            n_drive_mod: n_drive,
+            p_drive_cw: p_drive,
+            p_drive_mod: p_drive,
+            rx_gain,
+            minlevel,
+            colllevel,
+            ..base
+        }
+    }
+}
+
+/// Score of one setting of the sweep.
+#[derive(Clone, Copy)]
+pub struct TuningScore {
+    pub config: RfConfig,
+    /// Successful anticollisions, out of `TuningSweep::attempts`.
+    pub successes: u8,
+    /// How many steps `minlevel` could be raised over `config.minlevel` while still
+    /// reading the card. Only measured if all attempts succeeded.
+    pub margin: u8,
+}
+
+impl TuningScore {
+    fn rank(&self) -> (u8, u8) {
+        (self.successes, self.margin)
+    }
+}
+
+/// Result of [`Fm175xx::tune`].
+#[derive(Clone, Copy)]
+pub struct TuningResult {
+    /// Setting with the most successes, then the highest margin. `None` if the card was never read.
+    pub best: Option<RfConfig>,
+    /// Number of entries written to the score table.
+    pub len: usize,
+}
+
+impl<I, NpdPin, IrqPin> Fm175xx<I, NpdPin, IrqPin>
+where
+    I: Interface,
+    NpdPin: OutputPin,
+    IrqPin: InputPin + Wait,
+{
+    /// Sweep RF settings with a reference card placed in the field, and score each one.
+    ///
+    /// Each setting is scored on the rate of successful WUPA + cascade level 1 anticollisions,
+    /// then on signal margin. The scores are written to `table`, which must fit `sweep.len()` entries.
+    ///
+    /// The current config is kept, apply the best one with [`set_config`](Self::set_config).
+    pub async fn tune(&mut self, sweep: &TuningSweep<'_>, table: &mut [TuningScore]) -> Result<TuningResult, Error<I::Error>> {
+        let base = self.config;
+        let res = run_sweep(self, base, sweep, table).await;
+        self.config = base;
+        let res = res?;
+
+        self.off()?;
+        Ok(res)
+    }
+}
+
+/// Something that tries to read the reference card with a given config.
+trait TuningTarget {
+    type Error;
+
+    async fn attempt(&mut self, config: RfConfig) -> Result<bool, Error<Self::Error>>;
+}
+
+impl<I, NpdPin, IrqPin> TuningTarget for Fm175xx<I, NpdPin, IrqPin>
+where
+    I: Interface,
+    NpdPin: OutputPin,
+    IrqPin: InputPin + Wait,
+{
+    type Error = I::Error;
+
+    /// Try a WUPA and a cascade level 1 anticollision with the given config.
+    /// Returns whether a complete UID CL1 with a good BCC was received.
+    async fn attempt(&mut self, config: RfConfig) -> Result<bool, Error<I::Error>> {
+        self.config = config;
+        let mut reader = match self.start_iso14443a().await {
+            Ok(r) => r,
+            Err(e) => return soft_fail(e),
+        };
+
+        let mut atqa = [0; 2];
+        match reader.transceive(&[], &mut atqa, Frame::WupA).await {
+            Ok(16) => {}
+            Ok(_) => return Ok(false),
+            Err(e) => return soft_fail(e),
+        }
+
+        let tx = [0x93, 0x20];
+        let mut rx = [0; 7];
+        match reader.transceive(&tx, &mut rx, Frame::Anticoll { bits: 16 }).await {
+            Ok(56) => {}
+            Ok(_) => return Ok(false),
+            Err(e) => return soft_fail(e),
+        }
+
+        let bcc = rx[2] ^ rx[3] ^ rx[4] ^ rx[5];
+        Ok(bcc == rx[6])
+    }
+}
+
+/// Score each setting of `sweep` over `base` into `table`, and pick the best one.
+async fn run_sweep<T: TuningTarget>(
+    target: &mut T,
+    base: RfConfig,
+    sweep: &TuningSweep<'_>,
+    table: &mut [TuningScore],
+) -> Result<TuningResult, Error<T::Error>> {
+    if sweep.attempts == 0 {
+        return Err(Error::Config(ConfigError::TuningAttempts));
+    }
+    if table.len() < sweep.len() {
+        return Err(Error::Config(ConfigError::TuningTable));
+    }
+
+    let mut best: Option<usize> = None;
+
+    for i in 0..sweep.len() {
+        let config = sweep.get(base, i);
+        config.validate().map_err(Error::Config)?;
+
+        let mut successes = 0;
+        for _ in 0..sweep.attempts {
+            if target.attempt(config).await? {
+                successes += 1;
+            }
+        }
+
+        // Raise minlevel until the card is no longer read.
+        let mut margin = 0;
+        if successes == sweep.attempts {
+            for minlevel in config.minlevel + 1..=15 {
+                let config = RfConfig { minlevel, ..config };
+                if !target.attempt(config).await? {
+                    break;
+                }
+                margin += 1;
+            }
+        }
+
+        debug!(
+            "tuning: n={} p={} gain={} min={} coll={} => successes={} margin={}",
+            config.n_drive_cw, config.p_drive_cw, config.rx_gain.0, config.minlevel, config.colllevel, successes, margin
+        );
+
+        table[i] = TuningScore {
+            config,
+            successes,
+            margin,
+        };
+        let better = match best {
+            None => successes != 0,
+            Some(b) => table[i].rank() > table[b].rank(),
+        };
+        if better {
+            best = Some(i);
+        }
+    }
+
+    Ok(TuningResult {
+        best: best.map(|b| table[b].config),
+        len: sweep.len(),
+    })
+}
+
+/// RF errors count as a failed attempt, chip errors are returned.
+fn soft_fail<T>(e: iso14443a::Error<T>) -> Result<bool, Error<T>> {
+    match e {
+        iso14443a::Error::Interface(e) => Err(Error::Interface(e)),
+        iso14443a::Error::Pin(e) => Err(Error::Pin(e)),
+        _ => Ok(false),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+
+    /// Reference card that's read when `read` says so, counting the attempts.
+    struct FakeCard<F> {
+        read: F,
+        attempts: usize,
+    }
+
+    impl<F: FnMut(&RfConfig) -> bool> FakeCard<F> {
+        fn new(read: F) -> Self {
+            Self { read, attempts: 0 }
+        }
+    }
+
+    impl<F: FnMut(&RfConfig) -> bool> TuningTarget for FakeCard<F> {
+        type Error = Infallible;
+
+        async fn attempt(&mut self, config: RfConfig) -> Result<bool, Error<Infallible>> {
+            self.attempts += 1;
+            Ok((self.read)(&config))
+        }
+    }
+
+    fn sweep(n_drive: &[u8]) -> TuningSweep<'_> {
+        TuningSweep {
+            n_drive,
+            p_drive: &[32],
+            rx_gain: &[RxGain::_33DB],
+            minlevel: &[8],
+            colllevel: &[4],
+            attempts: 3,
+        }
+    }
+
+    fn empty_table() -> [TuningScore; 8] {
+        [TuningScore {
+            config: RfConfig::default(),
+            successes: 0,
+            margin: 0,
+        }; 8]
+    }
+
+    fn scores(table: &[TuningScore]) -> [(u8, u8, u8); 4] {
+        core::array::from_fn(|i| (table[i].config.n_drive_cw, table[i].successes, table[i].margin))
+    }
+
+    #[test]
+    fn scoring() {
+        let mut flaky = 0;
+        let mut card = FakeCard::new(|config: &RfConfig| match config.n_drive_cw {
+            // Never read.
+            4 => false,
+            // Read every other time.
+            6 => {
+                flaky += 1;
+                flaky % 2 == 1
+            }
+            // Read until minlevel gets too high.
+            8 => config.minlevel <= 11,
+            _ => true,
+        });
+
+        let mut table = empty_table();
+        let res = block_on(run_sweep(&mut card, RfConfig::default(), &sweep(&[4, 6, 8, 12]), &mut table)).unwrap();
+
+        assert_eq!(res.len, 4);
+        assert_eq!(scores(&table), [(4, 0, 0), (6, 2, 0), (8, 3, 3), (12, 3, 7)]);
+        // Margin is only measured when all attempts succeeded.
+        assert_eq!(card.attempts, 3 + 3 + (3 + 4) + (3 + 7));
+
+        let best = res.best.unwrap();
+        assert_eq!(best.n_drive_cw, 12);
+        assert_eq!(best.n_drive_mod, 12);
+        // The margin isn't applied, the swept minlevel is.
+        assert_eq!(best.minlevel, 8);
+    }
+
+    #[test]
+    fn best_ranks_successes_before_margin() {
+        let mut flaky = 0;
+        let mut card = FakeCard::new(|config: &RfConfig| match config.n_drive_cw {
+            8 => config.minlevel <= 8,
+            _ => {
+                flaky += 1;
+                flaky % 3 != 0
+            }
+        });
+
+        let mut table = empty_table();
+        let res = block_on(run_sweep(&mut card, RfConfig::default(), &sweep(&[12, 8, 4]), &mut table)).unwrap();
+        assert_eq!(scores(&table)[..3], [(12, 2, 0), (8, 3, 0), (4, 2, 0)]);
+        assert_eq!(res.best.unwrap().n_drive_cw, 8);
+    }
+
+    #[test]
+    fn best_keeps_first_of_ties() {
+        let mut card = FakeCard::new(|config: &RfConfig| config.minlevel <= 10);
+        let mut table = empty_table();
+        let res = block_on(run_sweep(&mut card, RfConfig::default(), &sweep(&[4, 6, 8]), &mut table)).unwrap();
+        assert_eq!(scores(&table)[..3], [(4, 3, 2), (6, 3, 2), (8, 3, 2)]);
+        assert_eq!(res.best.unwrap().n_drive_cw, 4);
+    }
+
+    #[test]
+    fn no_best_if_never_read() {
+        let mut card = FakeCard::new(|_: &RfConfig| false);
+        let mut table = empty_table();
+        let res = block_on(run_sweep(&mut card, RfConfig::default(), &sweep(&[4, 6]), &mut table)).unwrap();
+        assert_eq!(res.len, 2);
+        assert!(res.best.is_none());
+        assert_eq!(card.attempts, 6);
+    }
+
+    #[test]
+    fn sweep_combinations() {
+        let sweep = TuningSweep {
+            n_drive: &[4, 8],
+            p_drive: &[16, 32, 48],
+            rx_gain: &[RxGain::_33DB, RxGain::_38DB],
+            minlevel: &[8],
+            colllevel: &[4],
+            attempts: 1,
+        };
+        assert_eq!(sweep.len(), 12);
+
+        let mut card = FakeCard::new(|_: &RfConfig| true);
+        let mut table = [empty_table()[0]; 12];
+        block_on(run_sweep(&mut card, RfConfig::default(), &sweep, &mut table)).unwrap();
+
+        // Every combination is tried once, the other fields are kept.
+        for (i, a) in table.iter().enumerate() {
+            for b in &table[..i] {
+                let same = (a.config.n_drive_cw, a.config.p_drive_cw, a.config.rx_gain)
+                    == (b.config.n_drive_cw, b.config.p_drive_cw, b.config.rx_gain);
+                assert!(!same, "{} tried twice", i);
+            }
+            assert_eq!(a.config.p_drive_mod, a.config.p_drive_cw);
+            assert_eq!(a.config.p_drive_mod_ask10, RfConfig::default().p_drive_mod_ask10);
+        }
+    }
+
+    #[test]
+    fn sweep_errors() {
+        let mut card = FakeCard::new(|_: &RfConfig| true);
+        let mut table = empty_table();
+        let base = RfConfig::default();
+
+        let no_attempts = TuningSweep {
+            attempts: 0,
+            ..sweep(&[4])
+        };
+        let res = block_on(run_sweep(&mut card, base, &no_attempts, &mut table));
+        assert_eq!(res.err(), Some(Error::Config(ConfigError::TuningAttempts)));
+
+        let res = block_on(run_sweep(&mut card, base, &sweep(&[4, 6, 8]), &mut table[..2]));
+        assert_eq!(res.err(), Some(Error::Config(ConfigError::TuningTable)));
+
+        let res = block_on(run_sweep(&mut card, base, &sweep(&[16]), &mut table));
+        assert_eq!(res.err(), Some(Error::Config(ConfigError::NDrive)));
+
+        assert_eq!(card.attempts, 0);
+    }
+}
@@ -0,0 +1,42 @@
+use embedded_hal::digital::OutputPin;
+use rnfc_traits::diagnostics::{ChipId, Diagnostics, Rssi};
+
+use crate::{Error, Interface, Mfrc522};
+
+impl<I, RstPin> Diagnostics for Mfrc522<I, RstPin>
+where
+    I: Interface,
+    RstPin: OutputPin,
+{
+    type Error = Error<I::Error>;
+
+    /// Reads the `version` register. The high nibble is the chip type, the low nibble the revision.
+    async fn chip_id(&mut self) -> Result<ChipId, Self::Error> {
+        let ver = self.version().await?.to_reg();
+
+        Ok(ChipId {
+            chip_type: ver >> 4,
+            revision: ver & 0x0F,
+        })
+    }
+
+    async fn vdd_mv(&mut self) -> Result<Option<u32>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn regulator(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn amplitude(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    async fn phase(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(None)
+    }
+
+    fn last_rssi(&self) -> Option<Rssi> {
+        None
+    }
+}
@@ -0,0 +1,81 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::OutputPin;
+pub use rnfc_fm175xx::iso14443a::Error;
+use rnfc_fm175xx::regs;
+use rnfc_traits::iso14443a_ll as ll;
+
+use crate::{Interface, Mfrc522};
+
+pub struct Iso14443a<'d, I, RstPin>
+where
+    I: Interface + 'd,
+    RstPin: OutputPin + 'd,
+{
+    inner: &'d mut Mfrc522<I, RstPin>,
+}
+
+impl<I: Interface, RstPin: OutputPin> Mfrc522<I, RstPin> {
+    pub async fn start_iso14443a(&mut self) -> Result<Iso14443a<I, RstPin>, Error<I::Error>> {
+        self.on().await?;
+
+        let config = self.config;
+        let pcd = &mut self.pcd;
+        pcd.regs().txmode().write(|w| w.set_framing(regs::Framing::ISO14443A))?;
+        pcd.regs().rxmode().write(|w| w.set_framing(regs::Framing::ISO14443A))?;
+        pcd.regs().mode().modify(|w| w.set_crcpreset(0b01))?; // 0x6363
+        pcd.iso14443a_set_bit_rate(ll::BitRate::Kbps106, ll::BitRate::Kbps106, &config)?;
+        pcd.regs().rfcfg().write(|w| {
+            w.set_rxgain(config.rx_gain);
+        })?;
+        pcd.regs().txauto().write(|w| {
+            w.set_force100ask(true);
+        })?;
+
+        pcd.rf_on(&config)?;
+
+        // Field on guard time
+        Timer::after(Duration::from_millis(5)).await;
+
+        Ok(Iso14443a { inner: self })
+    }
+}
+
+impl<'d, I, RstPin> Drop for Iso14443a<'d, I, RstPin>
+where
+    I: Interface + 'd,
+    RstPin: OutputPin + 'd,
+{
+    fn drop(&mut self) {
+        if self.inner.off().is_err() {
+            warn!("Failed to set field off on Iso14443a drop");
+        }
+    }
+}
+
+impl<'d, I, RstPin> ll::Reader for Iso14443a<'d, I, RstPin>
+where
+    I: Interface + 'd,
+    RstPin: OutputPin + 'd,
+{
+    type Error = Error<I::Error>;
+
+    fn max_bit_rate(&self) -> ll::BitRate {
+        ll::BitRate::Kbps848
+    }
+
+    async fn set_bit_rate(&mut self, tx: ll::BitRate, rx: ll::BitRate) -> Result<(), Self::Error> {
+        debug!("set bit rate: tx={:?} rx={:?}", tx, rx);
+        let config = self.inner.config;
+        self.inner.pcd.iso14443a_set_bit_rate(tx, rx, &config)?;
+        Ok(())
+    }
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Self::Error> {
+        self.inner.pcd.iso14443a_transceive(tx, rx, opts).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        Timer::after(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+
+//! Driver for the NXP MFRC522 and compatible readers.
+//!
+//! The MFRC522 register set is a subset of the FM175xx one, so the register access,
+//! interfaces and transceive logic are shared with [`rnfc_fm175xx`].
+
+// Must go FIRST so that other mods see its macros.
+mod fmt;
+
+mod diagnostics;
+pub mod iso14443a;
+
+use embassy_time::{Duration, Timer};
+use embedded_hal::digital::{Error as _, OutputPin};
+use rnfc_fm175xx::Pcd;
+pub use rnfc_fm175xx::{ConfigError, Error, I2cInterface, Interface, RfConfig, RxGain, SpiInterface, UartInterface};
+
+/// Chip version, from the `version` register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChipVersion {
+    /// MFRC522 version 0.0 (0x90).
+    Mfrc522V0,
+    /// MFRC522 version 1.0 (0x91).
+    Mfrc522V1,
+    /// MFRC522 version 2.0 (0x92).
+    Mfrc522V2,
+    /// Fudan FM17522 (0x88), found on many cheap modules.
+    Fm17522,
+    /// Unknown version. Many clone chips report something else, and usually work anyway.
+    Unknown(u8),
+}
+
+impl ChipVersion {
+    pub fn from_reg(val: u8) -> Self {
+        match val {
+            0x90 => Self::Mfrc522V0,
+            0x91 => Self::Mfrc522V1,
+            0x92 => Self::Mfrc522V2,
+            0x88 => Self::Fm17522,
+            x => Self::Unknown(x),
+        }
+    }
+
+    /// Raw `version` register value.
+    pub fn to_reg(self) -> u8 {
+        match self {
+            Self::Mfrc522V0 => 0x90,
+            Self::Mfrc522V1 => 0x91,
+            Self::Mfrc522V2 => 0x92,
+            Self::Fm17522 => 0x88,
+            Self::Unknown(x) => x,
+        }
+    }
+}
+
+pub struct Mfrc522<I, RstPin> {
+    pcd: Pcd<I>,
+    rst: RstPin,
+    config: RfConfig,
+    version: Option<ChipVersion>,
+}
+
+impl<I, RstPin> Mfrc522<I, RstPin>
+where
+    I: Interface,
+    RstPin: OutputPin,
+{
+    /// Create a new driver. `rst` is the NRSTPD pin, the chip is kept in hard power-down while it's low.
+    pub async fn new(iface: I, mut rst: RstPin) -> Result<Self, Error<I::Error>> {
+        rst.set_low().map_err(|e| Error::Pin(e.kind()))?;
+
+        Ok(Self {
+            pcd: Pcd::new(iface),
+            rst,
+            config: Default::default(),
+            version: None,
+        })
+    }
+
+    fn set_rst(&mut self, high: bool) -> Result<(), Error<I::Error>> {
+        let res = match high {
+            true => self.rst.set_high(),
+            false => self.rst.set_low(),
+        };
+        res.map_err(|e| Error::Pin(e.kind()))
+    }
+
+    fn off(&mut self) -> Result<(), Error<I::Error>> {
+        self.set_rst(false)
+    }
+
+    async fn on(&mut self) -> Result<(), Error<I::Error>> {
+        self.set_rst(false)?;
+        Timer::after(Duration::from_millis(1)).await;
+        self.set_rst(true)?;
+
+        // Oscillator startup time, depends on the crystal. Datasheet example is 37.74us.
+        Timer::after(Duration::from_millis(1)).await;
+
+        let version = ChipVersion::from_reg(self.pcd.soft_reset().await?);
+        if self.version.is_none() {
+            if let ChipVersion::Unknown(x) = version {
+                warn!("Unknown chip version {:02x}, it might not be MFRC522-compatible.", x);
+            }
+        }
+        self.version = Some(version);
+        Ok(())
+    }
+
+    /// Detect the chip version. The chip is powered down afterwards.
+    pub async fn version(&mut self) -> Result<ChipVersion, Error<I::Error>> {
+        if let Some(version) = self.version {
+            return Ok(version);
+        }
+
+        self.on().await?;
+        self.off()?;
+        Ok(unwrap!(self.version))
+    }
+
+    pub fn set_config(&mut self, config: RfConfig) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.config = config;
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+//! PN532 native frames, used over I2C, SPI and HSU.
+//!
+//! Normal frame: `00 00 FF LEN LCS TFI PD.. DCS 00`
+//! Extended frame: `00 00 FF FF FF LENM LENL LCS TFI PD.. DCS 00`
+
+use crate::pn53x::MAX_DATA_LEN;
+
+pub const ACK: [u8; 6] = [0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+pub const NACK: [u8; 6] = [0x00, 0x00, 0xFF, 0xFF, 0x00, 0x00];
+
+/// Max length of a frame on the wire.
+pub const MAX_FRAME_LEN: usize = MAX_DATA_LEN + 10;
+
+const START: [u8; 2] = [0x00, 0xFF];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame<'a> {
+    Ack,
+    Nack,
+    /// Error frame: the PN532 found a syntax error in the last command.
+    Error,
+    /// Information frame, with the TFI and PD bytes.
+    Data(&'a [u8]),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DecodeError {
+    /// More bytes are needed.
+    Incomplete,
+    /// Bad length or data checksum.
+    Checksum,
+    /// Frame too long.
+    TooLong,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)).wrapping_neg()
+}
+
+/// Encode an information frame with the TFI and PD bytes in `data`. Returns the frame length.
+pub fn encode(data: &[u8], buf: &mut [u8]) -> Option<usize> {
+    let ext = data.len() > 255;
+    let header = if ext { 8 } else { 5 };
+    let len = header + data.len() + 2;
+    if data.len() > MAX_DATA_LEN || len > buf.len() {
+        return None;
+    }
+
+    buf[..3].copy_from_slice(&[0x00, 0x00, 0xFF]);
+    if ext {
+        let [hi, lo] = (data.len() as u16).to_be_bytes();
+        buf[3..8].copy_from_slice(&[0xFF, 0xFF, hi, lo, checksum(&[hi, lo])]);
+    } else {
+        let l = data.len() as u8;
+        buf[3..5].copy_from_slice(&[l, l.wrapping_neg()]);
+    }
+    buf[header..][..data.len()].copy_from_slice(data);
+    buf[header + data.len()] = checksum(data);
+    buf[header + data.len() + 1] = 0x00;
+    Some(len)
+}
+
+/// Decode the first frame in `buf`, skipping any leading zeros.
+/// Returns the frame and the number of bytes used up to its end, not including the postamble.
+pub fn decode(buf: &[u8]) -> Result<(Frame<'_>, usize), DecodeError> {
+    let start = match buf.windows(2).position(|w| w == START) {
+        Some(i) => i + 2,
+        None => return Err(DecodeError::Incomplete),
+    };
+    let b = &buf[start..];
+    let get = |i: usize| b.get(i).copied().ok_or(DecodeError::Incomplete);
+
+    let (len, lcs, header) = match (get(0)?, get(1)?) {
+        (0x00, 0xFF) => return Ok((Frame::Ack, start + 2)),
+        (0xFF, 0x00) => return Ok((Frame::Nack, start + 2)),
+        (0xFF, 0xFF) => {
+            let len = u16::from_be_bytes([get(2)?, get(3)?]);
+            (len as usize, get(2)?.wrapping_add(get(3)?).wrapping_add(get(4)?), 5)
+        }
+        (len, lcs) => (len as usize, len.wrapping_add(lcs), 2),
+    };
+    if lcs != 0 {
+        return Err(DecodeError::Checksum);
+    }
+    if len > MAX_DATA_LEN {
+        return Err(DecodeError::TooLong);
+    }
+
+    let end = header + len + 1;
+    if b.len() < end {
+        return Err(DecodeError::Incomplete);
+    }
+    let data = &b[header..][..len];
+    if checksum(data) != b[header + len] {
+        return Err(DecodeError::Checksum);
+    }
+
+    // The error frame is a 1-byte information frame with a 0x7F payload.
+    let frame = match data {
+        [0x7F] => Frame::Error,
+        _ => Frame::Data(data),
+    };
+    Ok((frame, start + end))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // GetFirmwareVersion, and its response, from the PN532 user manual.
+    const GET_FIRMWARE_VERSION: [u8; 9] = [0x00, 0x00, 0xFF, 0x02, 0xFE, 0xD4, 0x02, 0x2A, 0x00];
+    const FIRMWARE_VERSION: [u8; 13] = [0x00, 0x00, 0xFF, 0x06, 0xFA, 0xD5, 0x03, 0x32, 0x01, 0x06, 0x07, 0xE8, 0x00];
+
+    #[test]
+    fn encode_normal() {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let n = encode(&[0xD4, 0x02], &mut buf).unwrap();
+        assert_eq!(buf[..n], GET_FIRMWARE_VERSION);
+
+        // LEN wraps to a zero LCS, DCS of all zeros is zero.
+        let n = encode(&[0x00; 255], &mut buf).unwrap();
+        assert_eq!(n, 262);
+        assert_eq!(buf[..5], [0x00, 0x00, 0xFF, 0xFF, 0x01]);
+        assert_eq!(buf[260..262], [0x00, 0x00]);
+    }
+
+    #[test]
+    fn encode_extended() {
+        let data = [0xAB; 300];
+        let mut buf = [0; MAX_FRAME_LEN];
+        // Over MAX_DATA_LEN.
+        assert_eq!(encode(&data, &mut buf), None);
+
+        let data = &data[..MAX_DATA_LEN];
+        let n = encode(data, &mut buf).unwrap();
+        assert_eq!(n, MAX_FRAME_LEN);
+        // LEN = 0x0108, LCS = -(0x01 + 0x08)
+        assert_eq!(buf[..8], [0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x01, 0x08, 0xF7]);
+        assert_eq!(buf[8..n - 2], *data);
+        // DCS = -(264 * 0xAB) = -0xB058
+        assert_eq!(buf[n - 2..n], [0xA8, 0x00]);
+
+        assert_eq!(decode(&buf[..n]), Ok((Frame::Data(data), n - 1)));
+    }
+
+    #[test]
+    fn encode_buffer_too_small() {
+        let mut buf = [0; 8];
+        assert_eq!(encode(&[0xD4, 0x02], &mut buf), None);
+        let mut buf = [0; 9];
+        assert_eq!(encode(&[0xD4, 0x02], &mut buf), Some(9));
+    }
+
+    #[test]
+    fn decode_data() {
+        let data = [0xD5, 0x03, 0x32, 0x01, 0x06, 0x07];
+        assert_eq!(decode(&FIRMWARE_VERSION), Ok((Frame::Data(&data), 12)));
+
+        // Leading garbage before the preamble, and a following frame.
+        let mut buf = [0; 32];
+        buf[..3].copy_from_slice(&[0x00, 0x00, 0x00]);
+        buf[3..16].copy_from_slice(&FIRMWARE_VERSION);
+        buf[16..22].copy_from_slice(&ACK);
+        assert_eq!(decode(&buf[..22]), Ok((Frame::Data(&data), 15)));
+        // Starting at the postamble of the first one.
+        assert_eq!(decode(&buf[15..22]), Ok((Frame::Ack, 6)));
+
+        // Roundtrip.
+        let mut buf = [0; MAX_FRAME_LEN];
+        for len in [1, 2, 100, 255, 256] {
+            let data = [0x5A; 256];
+            let n = encode(&data[..len], &mut buf).unwrap();
+            assert_eq!(decode(&buf[..n]), Ok((Frame::Data(&data[..len]), n - 1)));
+        }
+    }
+
+    #[test]
+    fn decode_ack_nack_error() {
+        assert_eq!(decode(&ACK), Ok((Frame::Ack, 5)));
+        assert_eq!(decode(&NACK), Ok((Frame::Nack, 5)));
+        // The postamble isn't needed, nor counted.
+        assert_eq!(decode(&ACK[..5]), Ok((Frame::Ack, 5)));
+        assert_eq!(decode(&ACK[..4]), Err(DecodeError::Incomplete));
+
+        let error = [0x00, 0x00, 0xFF, 0x01, 0xFF, 0x7F, 0x81, 0x00];
+        assert_eq!(decode(&error), Ok((Frame::Error, 7)));
+    }
+
+    #[test]
+    fn decode_incomplete() {
+        assert_eq!(decode(&[]), Err(DecodeError::Incomplete));
+        assert_eq!(decode(&[0x00; 8]), Err(DecodeError::Incomplete));
+        // Every prefix short of the DCS.
+        for n in 0..FIRMWARE_VERSION.len() - 2 {
+            assert_eq!(decode(&FIRMWARE_VERSION[..n]), Err(DecodeError::Incomplete), "{}", n);
+        }
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let n = encode(&[0x11; 260], &mut buf).unwrap();
+        for i in 0..n - 1 {
+            assert_eq!(decode(&buf[..i]), Err(DecodeError::Incomplete), "{}", i);
+        }
+        assert!(decode(&buf[..n - 1]).is_ok());
+    }
+
+    #[test]
+    fn decode_corrupted() {
+        // Bad LCS.
+        let mut frame = FIRMWARE_VERSION;
+        frame[4] = 0xFB;
+        assert_eq!(decode(&frame), Err(DecodeError::Checksum));
+
+        // Bad DCS.
+        let mut frame = FIRMWARE_VERSION;
+        frame[11] = 0xE9;
+        assert_eq!(decode(&frame), Err(DecodeError::Checksum));
+
+        // Corrupted data byte.
+        let mut frame = FIRMWARE_VERSION;
+        frame[8] ^= 0x01;
+        assert_eq!(decode(&frame), Err(DecodeError::Checksum));
+
+        // Bad extended LCS.
+        let mut buf = [0; MAX_FRAME_LEN];
+        let n = encode(&[0x11; 260], &mut buf).unwrap();
+        buf[7] ^= 0x01;
+        assert_eq!(decode(&buf[..n]), Err(DecodeError::Checksum));
+
+        // Extended frame longer than MAX_DATA_LEN, with a good LCS.
+        let frame = [0x00, 0x00, 0xFF, 0xFF, 0xFF, 0x01, 0x09, 0xF6];
+        assert_eq!(decode(&frame), Err(DecodeError::TooLong));
+    }
+}
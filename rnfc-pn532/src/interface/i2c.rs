@@ -0,0 +1,49 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::i2c::I2c;
+
+use super::Interface;
+use crate::fmt::Bytes;
+use crate::frame::MAX_FRAME_LEN;
+
+/// Default 7-bit I2C address.
+pub const DEFAULT_I2C_ADDRESS: u8 = 0x24;
+
+pub struct I2cInterface<T: I2c> {
+    i2c: T,
+    address: u8,
+}
+
+impl<T: I2c> I2cInterface<T> {
+    pub fn new(i2c: T, address: u8) -> Self {
+        Self { i2c, address }
+    }
+}
+
+impl<T: I2c> Interface for I2cInterface<T> {
+    type Error = T::Error;
+
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        trace!("     write {:02x}", Bytes(frame));
+        self.i2c.write(self.address, frame).await
+    }
+
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // Every read starts with the status byte, bit 0 is set when a frame is ready.
+        // The frame must be read in the same transaction.
+        loop {
+            let mut status = [0; 1];
+            self.i2c.read(self.address, &mut status).await?;
+            if status[0] & 0x01 != 0 {
+                break;
+            }
+            Timer::after(Duration::from_millis(1)).await;
+        }
+
+        let n = buf.len().min(MAX_FRAME_LEN);
+        let mut rx = [0; MAX_FRAME_LEN + 1];
+        self.i2c.read(self.address, &mut rx[..n + 1]).await?;
+        buf[..n].copy_from_slice(&rx[1..][..n]);
+        trace!("     read {:02x}", Bytes(&buf[..n]));
+        Ok(n)
+    }
+}
@@ -0,0 +1,26 @@
+mod i2c;
+mod spi;
+mod uart;
+
+use core::fmt::Debug;
+
+pub use i2c::{I2cInterface, DEFAULT_I2C_ADDRESS};
+pub use spi::SpiInterface;
+pub use uart::UartInterface;
+
+pub trait Interface {
+    type Error: Debug;
+
+    /// Wake up the chip before the first command.
+    async fn wakeup(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Send a complete frame.
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error>;
+
+    /// Wait until the chip has a frame ready, and read it into `buf`.
+    ///
+    /// Returns the number of bytes read. It may stop at the end of the frame, or read the whole `buf`.
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
@@ -0,0 +1,52 @@
+use embassy_time::{Duration, Timer};
+use embedded_hal_async::spi::{Operation, SpiDevice};
+
+use super::Interface;
+use crate::fmt::Bytes;
+
+const DATA_WRITE: u8 = 0x01;
+const STATUS_READ: u8 = 0x02;
+const DATA_READ: u8 = 0x03;
+
+/// SPI interface.
+///
+/// The PN532 sends and expects bytes LSB first, in SPI mode 0. The SPI bus must be configured for that.
+pub struct SpiInterface<T: SpiDevice> {
+    spi: T,
+}
+
+impl<T: SpiDevice> SpiInterface<T> {
+    pub fn new(spi: T) -> Self {
+        Self { spi }
+    }
+}
+
+impl<T: SpiDevice> Interface for SpiInterface<T> {
+    type Error = T::Error;
+
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        trace!("     write {:02x}", Bytes(frame));
+        self.spi
+            .transaction(&mut [Operation::Write(&[DATA_WRITE]), Operation::Write(frame)])
+            .await
+    }
+
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        loop {
+            let mut status = [0; 1];
+            self.spi
+                .transaction(&mut [Operation::Write(&[STATUS_READ]), Operation::Read(&mut status)])
+                .await?;
+            if status[0] & 0x01 != 0 {
+                break;
+            }
+            Timer::after(Duration::from_millis(1)).await;
+        }
+
+        self.spi
+            .transaction(&mut [Operation::Write(&[DATA_READ]), Operation::Read(buf)])
+            .await?;
+        trace!("     read {:02x}", Bytes(buf));
+        Ok(buf.len())
+    }
+}
@@ -0,0 +1,48 @@
+use embedded_io_async::{Read, ReadExactError, Write};
+
+use super::Interface;
+use crate::fmt::Bytes;
+use crate::frame::{self, DecodeError};
+
+/// HSU (UART) interface, at 115200 baud by default.
+pub struct UartInterface<T: Read + Write> {
+    uart: T,
+}
+
+impl<T: Read + Write> UartInterface<T> {
+    pub fn new(uart: T) -> Self {
+        Self { uart }
+    }
+}
+
+impl<T: Read + Write> Interface for UartInterface<T> {
+    type Error = ReadExactError<T::Error>;
+
+    async fn wakeup(&mut self) -> Result<(), Self::Error> {
+        // Out of power down, the chip needs a long preamble to sync to the next frame.
+        let mut buf = [0; 16];
+        buf[..2].copy_from_slice(&[0x55, 0x55]);
+        self.uart.write_all(&buf).await.map_err(ReadExactError::Other)
+    }
+
+    async fn write_frame(&mut self, frame: &[u8]) -> Result<(), Self::Error> {
+        trace!("     write {:02x}", Bytes(frame));
+        self.uart.write_all(frame).await.map_err(ReadExactError::Other)?;
+        self.uart.flush().await.map_err(ReadExactError::Other)
+    }
+
+    async fn read_frame(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        // No framing on the wire, read until a complete frame has been received.
+        let mut n = 0;
+        while n < buf.len() {
+            self.uart.read_exact(&mut buf[n..][..1]).await?;
+            n += 1;
+            match frame::decode(&buf[..n]) {
+                Err(DecodeError::Incomplete) => {}
+                _ => break,
+            }
+        }
+        trace!("     read {:02x}", Bytes(&buf[..n]));
+        Ok(n)
+    }
+}
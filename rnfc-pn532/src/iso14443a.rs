@@ -0,0 +1,309 @@
+//! Raw ISO14443A frames through `InCommunicateThru`.
+//!
+//! The PN53x firmware doesn't touch the framing for `InCommunicateThru`, so CRC and
+//! bit framing are set in the CIU registers with `WriteRegister`, and the timeout with
+//! `RFConfiguration`. Works with anything that runs PN53x commands, see [`Pn53x`].
+//!
+//! Collisions can't be resolved bit by bit: the firmware reports an error and drops
+//! the received bits, so anticollision only works with a single card in the field.
+
+use core::fmt::Debug;
+
+use embassy_time::{Duration, Timer};
+use rnfc_traits::iso14443a_ll as ll;
+
+use crate::fmt::Bytes;
+use crate::pn53x::{self, cmd, reg, rf_item, Pn53x, Status, StatusError, MAX_DATA_LEN};
+
+// CIU register bits.
+const MODE_CRC_PRESET_MASK: u8 = 0x03;
+const MODE_CRC_PRESET_6363: u8 = 0x01;
+const TXRX_MODE_CRC_EN: u8 = 0x80;
+const TXRX_MODE_SPEED_MASK: u8 = 0x70;
+const TXRX_MODE_FRAMING_MASK: u8 = 0x03;
+const TX_AUTO_FORCE_100_ASK: u8 = 0x40;
+const MANUAL_RCV_PARITY_DISABLE: u8 = 0x10;
+const CONTROL_INITIATOR: u8 = 0x10;
+
+/// Default ATR_RES timeout for `RFConfiguration`, 102.4ms.
+const ATR_RES_TIMEOUT: u8 = 0x0B;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    /// Error running the command.
+    Interface(T),
+    Other,
+    Timeout,
+    Crc,
+    Protocol,
+    Collision,
+}
+
+impl<T> From<pn53x::Error> for Error<T> {
+    fn from(val: pn53x::Error) -> Self {
+        match val {
+            pn53x::Error::UnexpectedResponse => Error::Protocol,
+            pn53x::Error::BufferTooSmall | pn53x::Error::InvalidArgument => Error::Other,
+            pn53x::Error::Status(e) => match e {
+                StatusError::Timeout => Error::Timeout,
+                StatusError::Crc | StatusError::Parity => Error::Crc,
+                StatusError::Collision | StatusError::BitCount => Error::Collision,
+                StatusError::RfProtocol | StatusError::MifareFraming | StatusError::InvalidFormat => Error::Protocol,
+                _ => Error::Other,
+            },
+        }
+    }
+}
+
+impl<T: Debug> ll::Error for Error<T> {
+    fn kind(&self) -> ll::ErrorKind {
+        match self {
+            Error::Interface(_) => ll::ErrorKind::Other,
+            Error::Other => ll::ErrorKind::Other,
+            Error::Timeout => ll::ErrorKind::Timeout,
+            Error::Protocol => ll::ErrorKind::Corruption,
+            Error::Crc => ll::ErrorKind::Corruption,
+            Error::Collision => ll::ErrorKind::Corruption,
+        }
+    }
+}
+
+/// CIU setup for raw ISO14443A frames at 106 kbps through `InCommunicateThru`.
+///
+/// Doesn't own the PN53x, so it can be kept next to something that also sends other commands,
+/// like a card selected with `InListPassiveTarget`. See [`Iso14443a`] for one that does.
+pub struct Thru {
+    tx_mode: u8,
+    rx_mode: u8,
+    manual_rcv: u8,
+    // Last values written, to skip writing them again.
+    crc: Option<bool>,
+    parity: Option<bool>,
+    bit_framing: Option<u8>,
+    timeout: Option<u8>,
+}
+
+impl Thru {
+    /// Set up the CIU for ISO14443A. Doesn't touch the field.
+    pub async fn new<T: Pn53x>(pn: &mut T) -> Result<Self, Error<T::Error>> {
+        // Read the registers we only change some bits of.
+        let addrs = [
+            reg::CIU_MODE,
+            reg::CIU_TX_MODE,
+            reg::CIU_RX_MODE,
+            reg::CIU_TX_AUTO,
+            reg::CIU_MANUAL_RCV,
+            reg::CIU_CONTROL,
+        ];
+        let mut data = [0; 12];
+        for (i, addr) in addrs.iter().enumerate() {
+            data[i * 2..][..2].copy_from_slice(&addr.to_be_bytes());
+        }
+        let mut vals = [0; 6];
+        let n = pn
+            .command(cmd::READ_REGISTER, &data, &mut vals)
+            .await
+            .map_err(Error::Interface)?;
+        if n != vals.len() {
+            return Err(Error::Protocol);
+        }
+        let [mode, tx_mode, rx_mode, tx_auto, manual_rcv, control] = vals;
+
+        // 106 kbps, ISO14443A framing.
+        let tx_mode = tx_mode & !(TXRX_MODE_CRC_EN | TXRX_MODE_SPEED_MASK | TXRX_MODE_FRAMING_MASK);
+        let rx_mode = rx_mode & !(TXRX_MODE_CRC_EN | TXRX_MODE_SPEED_MASK | TXRX_MODE_FRAMING_MASK);
+        let manual_rcv = manual_rcv & !MANUAL_RCV_PARITY_DISABLE;
+
+        write_regs(
+            pn,
+            &[
+                (reg::CIU_MODE, (mode & !MODE_CRC_PRESET_MASK) | MODE_CRC_PRESET_6363),
+                (reg::CIU_TX_MODE, tx_mode),
+                (reg::CIU_RX_MODE, rx_mode),
+                (reg::CIU_TX_AUTO, tx_auto | TX_AUTO_FORCE_100_ASK),
+                (reg::CIU_MANUAL_RCV, manual_rcv),
+                (reg::CIU_CONTROL, control | CONTROL_INITIATOR),
+            ],
+        )
+        .await?;
+
+        Ok(Self {
+            tx_mode,
+            rx_mode,
+            manual_rcv,
+            crc: Some(false),
+            parity: Some(true),
+            bit_framing: None,
+            timeout: None,
+        })
+    }
+
+    /// Enable or disable the parity bits, for both sending and receiving.
+    ///
+    /// MIFARE Classic encrypts the parity bits after authentication, so they have to be
+    /// disabled and sent in the data instead. Enabled by default.
+    pub async fn set_parity<T: Pn53x>(&mut self, pn: &mut T, enabled: bool) -> Result<(), Error<T::Error>> {
+        if self.parity != Some(enabled) {
+            let bit = if enabled { 0 } else { MANUAL_RCV_PARITY_DISABLE };
+            write_regs(pn, &[(reg::CIU_MANUAL_RCV, self.manual_rcv | bit)]).await?;
+            self.parity = Some(enabled);
+        }
+        Ok(())
+    }
+
+    /// Set CRC, bit framing and timeout for the next frame, writing only what changed.
+    async fn setup<T: Pn53x>(
+        &mut self,
+        pn: &mut T,
+        crc: bool,
+        bit_framing: u8,
+        timeout_1fc: u32,
+    ) -> Result<(), Error<T::Error>> {
+        let mut regs = [(0, 0); 3];
+        let mut n = 0;
+        if self.crc != Some(crc) {
+            let bit = if crc { TXRX_MODE_CRC_EN } else { 0 };
+            regs[n] = (reg::CIU_TX_MODE, self.tx_mode | bit);
+            regs[n + 1] = (reg::CIU_RX_MODE, self.rx_mode | bit);
+            n += 2;
+        }
+        if self.bit_framing != Some(bit_framing) {
+            regs[n] = (reg::CIU_BIT_FRAMING, bit_framing);
+            n += 1;
+        }
+        if n != 0 {
+            write_regs(pn, &regs[..n]).await?;
+            self.crc = Some(crc);
+            self.bit_framing = Some(bit_framing);
+        }
+
+        let timeout_us = (timeout_1fc as u64 * 1_000_000 / 13_560_000) as u32;
+        let timeout = pn53x::timeout_code(timeout_us);
+        if self.timeout != Some(timeout) {
+            pn.command(
+                cmd::RF_CONFIGURATION,
+                &[rf_item::TIMINGS, 0x00, ATR_RES_TIMEOUT, timeout],
+                &mut [],
+            )
+            .await
+            .map_err(Error::Interface)?;
+            self.timeout = Some(timeout);
+        }
+        Ok(())
+    }
+
+    /// Send a frame and receive the response, see [`ll::Reader::transceive`].
+    pub async fn transceive<T: Pn53x>(
+        &mut self,
+        pn: &mut T,
+        tx: &[u8],
+        rx: &mut [u8],
+        opts: ll::Frame,
+    ) -> Result<usize, Error<T::Error>> {
+        debug!("TX: {:?} {:02x}", opts, Bytes(tx));
+
+        let (tx, crc, timeout_1fc, lastbits, rxalign) = match opts {
+            ll::Frame::Anticoll { bits } => (&tx[..(bits + 7) / 8], false, 65536, (bits % 8) as u8, (bits % 8) as u8),
+            ll::Frame::ReqA => (&[0x26][..], false, 16384, 7, 0),
+            ll::Frame::WupA => (&[0x52][..], false, 16384, 7, 0),
+            ll::Frame::Standard { timeout_1fc } => (tx, true, timeout_1fc, 0, 0),
+        };
+        self.setup(pn, crc, (rxalign << 4) | lastbits, timeout_1fc).await?;
+
+        let mut resp = [0; MAX_DATA_LEN];
+        let n = pn
+            .command(cmd::IN_COMMUNICATE_THRU, tx, &mut resp)
+            .await
+            .map_err(Error::Interface)?;
+        if n == 0 {
+            return Err(Error::Protocol);
+        }
+        Status(resp[0]).check()?;
+        let data = &resp[1..n];
+
+        if let ll::Frame::Anticoll { bits } = opts {
+            // The response completes the last partial byte sent, put it after the sent bytes.
+            let shift = bits / 8;
+            if shift + data.len() > rx.len() {
+                return Err(Error::Other);
+            }
+            rx[..shift].copy_from_slice(&tx[..shift]);
+            rx[shift..][..data.len()].copy_from_slice(data);
+            if bits % 8 != 0 && !data.is_empty() {
+                let mask = (1u8 << (bits % 8)) - 1;
+                rx[shift] = (rx[shift] & !mask) | (tx[shift] & mask);
+            }
+
+            let total_bits = (shift + data.len()) * 8;
+            debug!("RX: {:02x} bits: {}", Bytes(&rx[..shift + data.len()]), total_bits);
+            Ok(total_bits)
+        } else {
+            if data.len() > rx.len() {
+                return Err(Error::Other);
+            }
+            rx[..data.len()].copy_from_slice(data);
+            debug!("RX: {:02x}", Bytes(data));
+            Ok(data.len() * 8)
+        }
+    }
+}
+
+async fn write_regs<T: Pn53x>(pn: &mut T, regs: &[(u16, u8)]) -> Result<(), Error<T::Error>> {
+    let mut data = [0; 3 * 8];
+    let n = pn53x::write_register(regs, &mut data)?;
+    pn.command(cmd::WRITE_REGISTER, &data[..n], &mut [])
+        .await
+        .map_err(Error::Interface)?;
+    Ok(())
+}
+
+/// A PN53x with the field on, sending raw ISO14443A frames at 106 kbps.
+///
+/// The field stays on when this is dropped, turn it off with [`field_off`](Self::field_off).
+pub struct Iso14443a<T> {
+    pn: T,
+    thru: Thru,
+}
+
+impl<T: Pn53x> Iso14443a<T> {
+    /// Set up the CIU for ISO14443A and turn on the field.
+    pub async fn new(mut pn: T) -> Result<Self, Error<T::Error>> {
+        let thru = Thru::new(&mut pn).await?;
+
+        pn.command(cmd::RF_CONFIGURATION, &[rf_item::RF_FIELD, 0x01], &mut [])
+            .await
+            .map_err(Error::Interface)?;
+
+        // Field on guard time
+        Timer::after(Duration::from_millis(5)).await;
+
+        Ok(Self { pn, thru })
+    }
+
+    pub async fn field_off(&mut self) -> Result<(), Error<T::Error>> {
+        self.pn
+            .command(cmd::RF_CONFIGURATION, &[rf_item::RF_FIELD, 0x00], &mut [])
+            .await
+            .map_err(Error::Interface)?;
+        Ok(())
+    }
+
+    /// Enable or disable the parity bits, see [`Thru::set_parity`].
+    pub async fn set_parity(&mut self, enabled: bool) -> Result<(), Error<T::Error>> {
+        self.thru.set_parity(&mut self.pn, enabled).await
+    }
+}
+
+impl<T: Pn53x> ll::Reader for Iso14443a<T> {
+    type Error = Error<T::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Self::Error> {
+        self.thru.transceive(&mut self.pn, tx, rx, opts).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        Timer::after(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,305 @@
+#![no_std]
+#![allow(async_fn_in_trait)]
+
+// Must go FIRST so that other mods see its macros.
+mod fmt;
+
+pub mod frame;
+mod interface;
+pub mod iso14443a;
+pub mod pn53x;
+
+use embassy_time::{with_timeout, Duration};
+pub use interface::*;
+use pn53x::{cmd, param, rf_item, FirmwareVersion, Pn53x, Status, TargetA, MAX_DATA_LEN};
+use rnfc_traits::{iso14443a_ll, iso_dep};
+
+use crate::fmt::Bytes;
+use crate::frame::{Frame, ACK, MAX_FRAME_LEN};
+use crate::iso14443a::Thru;
+
+/// Time for the chip to acknowledge a command.
+const ACK_TIMEOUT: Duration = Duration::from_millis(50);
+/// Time for the chip to respond to a command. Longer than the longest timeout the chip
+/// applies on its own while talking to a card (3.28s).
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(4);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error<T> {
+    /// Error talking to the chip.
+    Interface(T),
+    /// The chip didn't acknowledge or respond in time.
+    Timeout,
+    /// Bad frame checksum, or unexpected frame type.
+    Framing,
+    /// The chip returned an error frame, it found a syntax error in the command.
+    Syntax,
+    /// Error in the command or response payload.
+    Pn53x(pn53x::Error),
+}
+
+impl<T> From<pn53x::Error> for Error<T> {
+    fn from(val: pn53x::Error) -> Self {
+        Error::Pn53x(val)
+    }
+}
+
+pub struct Pn532<I> {
+    iface: I,
+    firmware: FirmwareVersion,
+}
+
+impl<I: Interface> Pn532<I> {
+    /// Wake up and configure the chip.
+    ///
+    /// Automatic RATS is enabled, so cards found by [`poll`](Self::poll) are ready for ISO-DEP.
+    pub async fn new(iface: I) -> Result<Self, Error<I::Error>> {
+        let mut this = Self {
+            iface,
+            firmware: FirmwareVersion {
+                ic: 0,
+                version: 0,
+                revision: 0,
+                support: 0,
+            },
+        };
+
+        this.iface.wakeup().await.map_err(Error::Interface)?;
+
+        // SAMConfiguration: normal mode, no SAM, use the IRQ pin.
+        this.command(cmd::SAM_CONFIGURATION, &[0x01, 0x14, 0x01], &mut []).await?;
+
+        let mut resp = [0; 4];
+        let n = this.command(cmd::GET_FIRMWARE_VERSION, &[], &mut resp).await?;
+        this.firmware = FirmwareVersion::parse(&resp[..n])?;
+        debug!("firmware: {:?}", this.firmware);
+        if this.firmware.ic != 0x32 {
+            warn!("Unexpected IC {:02x}, expected a PN532", this.firmware.ic);
+        }
+
+        this.set_auto_rats(true).await?;
+
+        // Retry the passive activation only twice, instead of forever.
+        this.command(cmd::RF_CONFIGURATION, &[rf_item::MAX_RETRIES, 0xFF, 0x01, 0x02], &mut [])
+            .await?;
+
+        Ok(this)
+    }
+
+    pub fn firmware_version(&self) -> FirmwareVersion {
+        self.firmware
+    }
+
+    /// Enable or disable sending RATS when a card supporting ISO-DEP is found by [`poll`](Self::poll).
+    ///
+    /// With it disabled, cards are only selected, and can be talked to with raw frames
+    /// through [`rnfc_traits::iso14443a::Reader`], same as with other readers.
+    pub async fn set_auto_rats(&mut self, enabled: bool) -> Result<(), Error<I::Error>> {
+        let rats = if enabled { param::AUTOMATIC_RATS } else { 0 };
+        self.command(cmd::SET_PARAMETERS, &[param::AUTOMATIC_ATR_RES | rats], &mut [])
+            .await?;
+        Ok(())
+    }
+
+    /// Run a command and wait for its response. `resp` gets the response data,
+    /// without the `D5 cmd+1` header. Returns the response data length.
+    pub async fn command(&mut self, cmd: u8, data: &[u8], resp: &mut [u8]) -> Result<usize, Error<I::Error>> {
+        let mut payload = [0; MAX_DATA_LEN];
+        let len = pn53x::encode_command(cmd, data, &mut payload)?;
+        let mut buf = [0; MAX_FRAME_LEN];
+        let n = unwrap!(frame::encode(&payload[..len], &mut buf));
+
+        debug!("TX: {:02x}", Bytes(&payload[1..len]));
+        self.iface.write_frame(&buf[..n]).await.map_err(Error::Interface)?;
+
+        // Wait for the ACK.
+        let n = match with_timeout(ACK_TIMEOUT, self.iface.read_frame(&mut buf[..ACK.len()])).await {
+            Ok(r) => r.map_err(Error::Interface)?,
+            Err(_) => {
+                warn!("timeout waiting for ACK");
+                return Err(Error::Timeout);
+            }
+        };
+        match frame::decode(&buf[..n]) {
+            Ok((Frame::Ack, _)) => {}
+            r => {
+                warn!("expected ACK, got {:?}", r);
+                return Err(Error::Framing);
+            }
+        }
+
+        // Wait for the response.
+        let n = match with_timeout(RESPONSE_TIMEOUT, self.iface.read_frame(&mut buf)).await {
+            Ok(r) => r.map_err(Error::Interface)?,
+            Err(_) => {
+                warn!("timeout waiting for response, aborting command");
+                // An ACK from the host aborts the running command.
+                self.iface.write_frame(&ACK).await.map_err(Error::Interface)?;
+                return Err(Error::Timeout);
+            }
+        };
+        let payload = match frame::decode(&buf[..n]) {
+            Ok((Frame::Data(d), _)) => d,
+            Ok((Frame::Error, _)) => {
+                warn!("syntax error frame");
+                return Err(Error::Syntax);
+            }
+            r => {
+                warn!("expected response, got {:?}", r);
+                return Err(Error::Framing);
+            }
+        };
+
+        let data = pn53x::decode_response(cmd, payload)?;
+        debug!("RX: {:02x}", Bytes(data));
+        if data.len() > resp.len() {
+            return Err(pn53x::Error::BufferTooSmall.into());
+        }
+        resp[..data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    /// Look for an ISO14443A card, and select it. Returns `None` if there's no card.
+    pub async fn poll(&mut self) -> Result<Option<Card<'_, I>>, Error<I::Error>> {
+        let mut resp = [0; MAX_DATA_LEN];
+        let n = self
+            .command(cmd::IN_LIST_PASSIVE_TARGET, &pn53x::list_passive_target_a(), &mut resp)
+            .await?;
+
+        match pn53x::parse_list_passive_target_a(&resp[..n])? {
+            None => Ok(None),
+            Some(target) => {
+                debug!("found card: uid={:02x} ats={:02x}", Bytes(&target.uid), Bytes(&target.ats));
+                Ok(Some(Card {
+                    dev: self,
+                    target,
+                    thru: None,
+                }))
+            }
+        }
+    }
+
+    /// Turn on the field, for raw ISO14443A frames through `InCommunicateThru`.
+    pub async fn start_iso14443a(&mut self) -> Result<iso14443a::Iso14443a<&mut Self>, iso14443a::Error<Error<I::Error>>> {
+        iso14443a::Iso14443a::new(self).await
+    }
+}
+
+impl<I: Interface> Pn53x for Pn532<I> {
+    type Error = Error<I::Error>;
+
+    async fn command(&mut self, cmd: u8, data: &[u8], resp: &mut [u8]) -> Result<usize, Self::Error> {
+        Pn532::command(self, cmd, data, resp).await
+    }
+}
+
+/// A card selected by [`Pn532::poll`].
+///
+/// Talked to with `InDataExchange` through [`iso_dep::Reader`], or with raw frames
+/// through `InCommunicateThru` with [`rnfc_traits::iso14443a::Reader`].
+pub struct Card<'a, I> {
+    dev: &'a mut Pn532<I>,
+    pub target: TargetA,
+    thru: Option<Thru>,
+}
+
+impl<I: Interface> Card<'_, I> {
+    /// Run one `InDataExchange`, check the status byte and return the response length.
+    async fn exchange(&mut self, data: &[u8], resp: &mut [u8]) -> Result<usize, Error<I::Error>> {
+        let n = self.dev.command(cmd::IN_DATA_EXCHANGE, data, resp).await?;
+        if n == 0 {
+            return Err(pn53x::Error::UnexpectedResponse.into());
+        }
+        Status(resp[0]).check()?;
+        Ok(n)
+    }
+
+    /// Set up the CIU for raw frames, the first time they're used.
+    async fn init_thru(&mut self) -> Result<(), iso14443a::Error<Error<I::Error>>> {
+        if self.thru.is_none() {
+            self.thru = Some(Thru::new(&mut *self.dev).await?);
+        }
+        Ok(())
+    }
+
+    /// Enable or disable the parity bits for raw frames, see [`Thru::set_parity`].
+    pub async fn set_parity(&mut self, enabled: bool) -> Result<(), iso14443a::Error<Error<I::Error>>> {
+        self.init_thru().await?;
+        let thru = unwrap!(self.thru.as_mut());
+        thru.set_parity(&mut *self.dev, enabled).await
+    }
+}
+
+impl<I: Interface> rnfc_traits::iso14443a::Reader for Card<'_, I> {
+    type Error = iso14443a::Error<Error<I::Error>>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        self.init_thru().await?;
+        let thru = unwrap!(self.thru.as_mut());
+        let bits = thru
+            .transceive(&mut *self.dev, tx, rx, iso14443a_ll::Frame::Standard { timeout_1fc })
+            .await?;
+        Ok(bits / 8)
+    }
+
+    fn uid(&self) -> &[u8] {
+        &self.target.uid
+    }
+
+    fn atqa(&self) -> [u8; 2] {
+        self.target.atqa
+    }
+
+    fn sak(&self) -> u8 {
+        self.target.sak
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        embassy_time::Timer::after(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
+}
+
+impl<I: Interface> iso_dep::Reader for Card<'_, I> {
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        // Data per InDataExchange, after the TFI, command code and Tg.
+        const MAX_CHUNK: usize = MAX_DATA_LEN - 3;
+
+        let mut data = [0; MAX_DATA_LEN];
+        let mut resp = [0; MAX_DATA_LEN];
+
+        // Send in chunks, with the MI bit set in all but the last one.
+        let mut pos = 0;
+        let mut n = loop {
+            let chunk = &tx[pos..][..(tx.len() - pos).min(MAX_CHUNK)];
+            pos += chunk.len();
+            let more = pos < tx.len();
+
+            data[0] = self.target.tg | if more { pn53x::TG_MORE_INFO } else { 0 };
+            data[1..][..chunk.len()].copy_from_slice(chunk);
+            let n = self.exchange(&data[..1 + chunk.len()], &mut resp).await?;
+            if !more {
+                break n;
+            }
+        };
+
+        // Receive, asking for the rest while the MI bit is set.
+        let mut len = 0;
+        loop {
+            let res = &resp[1..n];
+            if len + res.len() > rx.len() {
+                return Err(pn53x::Error::BufferTooSmall.into());
+            }
+            rx[len..][..res.len()].copy_from_slice(res);
+            len += res.len();
+
+            if !Status(resp[0]).more_info() {
+                return Ok(len);
+            }
+            n = self.exchange(&[self.target.tg], &mut resp).await?;
+        }
+    }
+}
@@ -0,0 +1,910 @@
+//! PN53x command encoding, shared by all transports.
+//!
+//! This only deals with the command and response payloads (`D4 cmd data..` / `D5 cmd+1 data..`).
+//! Wrapping them for the wire is the transport's job: native frames for the PN532,
+//! CCID pseudo-APDUs for the ACR122U.
+
+use core::fmt::Debug;
+
+use heapless::Vec;
+use rnfc_traits::card_emulation::Iso14443aConfig;
+use rnfc_traits::nfcf_ll::SensfRes;
+
+/// Frame identifier for host to PN53x.
+pub const TFI_HOST: u8 = 0xD4;
+/// Frame identifier for PN53x to host.
+pub const TFI_PN53X: u8 = 0xD5;
+
+/// Max length of a command or response payload, including the TFI and command code.
+pub const MAX_DATA_LEN: usize = 264;
+
+/// More information bit in the `Tg` byte of `InDataExchange`: the data is chained,
+/// the rest follows in the next `InDataExchange`.
+pub const TG_MORE_INFO: u8 = 0x40;
+
+pub const UID_MAX_LEN: usize = 10;
+pub const ATS_MAX_LEN: usize = 64;
+pub const ATTRIB_RES_MAX_LEN: usize = 64;
+/// Max targets the PN53x handles at once.
+pub const MAX_TARGETS: usize = 2;
+
+/// Command codes.
+pub mod cmd {
+    pub const DIAGNOSE: u8 = 0x00;
+    pub const GET_FIRMWARE_VERSION: u8 = 0x02;
+    pub const GET_GENERAL_STATUS: u8 = 0x04;
+    pub const READ_REGISTER: u8 = 0x06;
+    pub const WRITE_REGISTER: u8 = 0x08;
+    pub const SET_PARAMETERS: u8 = 0x12;
+    pub const SAM_CONFIGURATION: u8 = 0x14;
+    pub const POWER_DOWN: u8 = 0x16;
+    pub const RF_CONFIGURATION: u8 = 0x32;
+    pub const IN_DATA_EXCHANGE: u8 = 0x40;
+    pub const IN_COMMUNICATE_THRU: u8 = 0x42;
+    pub const IN_LIST_PASSIVE_TARGET: u8 = 0x4A;
+    pub const IN_RELEASE: u8 = 0x52;
+    pub const IN_SELECT: u8 = 0x54;
+    pub const IN_AUTO_POLL: u8 = 0x60;
+    pub const TG_GET_DATA: u8 = 0x86;
+    pub const TG_INIT_AS_TARGET: u8 = 0x8C;
+    pub const TG_SET_DATA: u8 = 0x8E;
+    pub const TG_SET_META_DATA: u8 = 0x94;
+}
+
+/// CIU register addresses, for `ReadRegister` and `WriteRegister`.
+pub mod reg {
+    pub const CIU_MODE: u16 = 0x6301;
+    pub const CIU_TX_MODE: u16 = 0x6302;
+    pub const CIU_RX_MODE: u16 = 0x6303;
+    pub const CIU_TX_AUTO: u16 = 0x6305;
+    pub const CIU_MANUAL_RCV: u16 = 0x630D;
+    pub const CIU_STATUS2: u16 = 0x6338;
+    pub const CIU_CONTROL: u16 = 0x633C;
+    pub const CIU_BIT_FRAMING: u16 = 0x633D;
+    pub const CIU_COLL: u16 = 0x633E;
+}
+
+/// `SetParameters` flags.
+pub mod param {
+    pub const NAD_USED: u8 = 0x01;
+    pub const DID_USED: u8 = 0x02;
+    pub const AUTOMATIC_ATR_RES: u8 = 0x04;
+    pub const AUTOMATIC_RATS: u8 = 0x10;
+    pub const ISO14443_4_PICC: u8 = 0x20;
+    pub const REMOVE_PRE_POST_AMBLE: u8 = 0x40;
+}
+
+/// `RFConfiguration` items.
+pub mod rf_item {
+    pub const RF_FIELD: u8 = 0x01;
+    pub const TIMINGS: u8 = 0x02;
+    pub const MAX_RTY_COM: u8 = 0x04;
+    pub const MAX_RETRIES: u8 = 0x05;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The response is too short, or for a different command.
+    UnexpectedResponse,
+    /// The command or response doesn't fit in the buffer.
+    BufferTooSmall,
+    /// A command parameter is out of range.
+    InvalidArgument,
+    /// The PN53x returned an error status.
+    Status(StatusError),
+}
+
+/// Error codes from the status byte of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StatusError {
+    /// The target didn't answer in time.
+    Timeout,
+    Crc,
+    Parity,
+    /// Erroneous bit count during anticollision or select.
+    BitCount,
+    /// Framing error during a MIFARE operation.
+    MifareFraming,
+    /// Bit collision during bitwise anticollision at 106 kbps.
+    Collision,
+    /// The communication buffer is too small.
+    BufferSize,
+    RfBufferOverflow,
+    /// The RF field wasn't switched on in time by the counterpart.
+    RfField,
+    RfProtocol,
+    Temperature,
+    InternalBufferOverflow,
+    InvalidParameter,
+    /// DEP: the command received in target mode is not supported.
+    DepUnsupportedCommand,
+    /// DEP, ISO14443-4 or MIFARE: the data format doesn't match the spec.
+    InvalidFormat,
+    MifareAuthentication,
+    /// Wrong UID check byte (BCC).
+    UidCheck,
+    DepInvalidState,
+    /// Operation not allowed in this configuration.
+    NotAllowed,
+    /// Command not acceptable in the current context.
+    NotAcceptable,
+    /// The target was released by the initiator.
+    Released,
+    /// The card ID doesn't match, the card was swapped.
+    CardIdMismatch,
+    /// The card disappeared from the field.
+    CardDisappeared,
+    Nfcid3Mismatch,
+    OverCurrent,
+    /// NAD missing in DEP frame.
+    NadMissing,
+    Unknown(u8),
+}
+
+impl StatusError {
+    fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => Self::Timeout,
+            0x02 => Self::Crc,
+            0x03 => Self::Parity,
+            0x04 => Self::BitCount,
+            0x05 => Self::MifareFraming,
+            0x06 => Self::Collision,
+            0x07 => Self::BufferSize,
+            0x09 => Self::RfBufferOverflow,
+            0x0A => Self::RfField,
+            0x0B => Self::RfProtocol,
+            0x0D => Self::Temperature,
+            0x0E => Self::InternalBufferOverflow,
+            0x10 => Self::InvalidParameter,
+            0x12 => Self::DepUnsupportedCommand,
+            0x13 => Self::InvalidFormat,
+            0x14 => Self::MifareAuthentication,
+            0x23 => Self::UidCheck,
+            0x25 => Self::DepInvalidState,
+            0x26 => Self::NotAllowed,
+            0x27 => Self::NotAcceptable,
+            0x29 => Self::Released,
+            0x2A => Self::CardIdMismatch,
+            0x2B => Self::CardDisappeared,
+            0x2C => Self::Nfcid3Mismatch,
+            0x2D => Self::OverCurrent,
+            0x2E => Self::NadMissing,
+            x => Self::Unknown(x),
+        }
+    }
+}
+
+/// Status byte, the first byte of the response to data exchange commands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Status(pub u8);
+
+impl Status {
+    pub fn error(&self) -> Option<StatusError> {
+        match self.0 & 0x3F {
+            0 => None,
+            x => Some(StatusError::from_code(x)),
+        }
+    }
+
+    /// More information: the response is chained, more data follows.
+    pub fn more_info(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    /// The response has a NAD.
+    pub fn nad(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+
+    /// Check for an error.
+    pub fn check(&self) -> Result<(), Error> {
+        match self.error() {
+            None => Ok(()),
+            Some(e) => Err(Error::Status(e)),
+        }
+    }
+}
+
+/// Something that can run PN53x commands.
+pub trait Pn53x {
+    type Error: Debug;
+
+    /// Run a command. `resp` gets the response data, without the `D5 cmd+1` header.
+    /// Returns the response data length.
+    async fn command(&mut self, cmd: u8, data: &[u8], resp: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl<T: Pn53x> Pn53x for &mut T {
+    type Error = T::Error;
+
+    async fn command(&mut self, cmd: u8, data: &[u8], resp: &mut [u8]) -> Result<usize, Self::Error> {
+        T::command(self, cmd, data, resp).await
+    }
+}
+
+/// Write a command payload, `D4 cmd data..`, into `buf`. Returns its length.
+pub fn encode_command(cmd: u8, data: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+    let len = 2 + data.len();
+    if len > MAX_DATA_LEN || len > buf.len() {
+        return Err(Error::BufferTooSmall);
+    }
+    buf[0] = TFI_HOST;
+    buf[1] = cmd;
+    buf[2..len].copy_from_slice(data);
+    Ok(len)
+}
+
+/// Check the `D5 cmd+1` header of a response payload to `cmd`, and return the response data.
+pub fn decode_response(cmd: u8, payload: &[u8]) -> Result<&[u8], Error> {
+    if payload.len() < 2 {
+        debug!("PN53x response too short: {}", payload.len());
+        return Err(Error::UnexpectedResponse);
+    }
+    if payload[0] != TFI_PN53X || payload[1] != cmd.wrapping_add(1) {
+        debug!(
+            "PN53x unexpected response: want {:02x} {:02x}, got {:02x} {:02x}",
+            TFI_PN53X,
+            cmd.wrapping_add(1),
+            payload[0],
+            payload[1]
+        );
+        return Err(Error::UnexpectedResponse);
+    }
+    Ok(&payload[2..])
+}
+
+/// Response to `GetFirmwareVersion`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FirmwareVersion {
+    /// 0x32 for the PN532, 0x33 for the PN533.
+    pub ic: u8,
+    pub version: u8,
+    pub revision: u8,
+    /// Supported technologies bitmask. Bit 0 ISO14443A, bit 1 ISO14443B, bit 2 ISO18092.
+    pub support: u8,
+}
+
+impl FirmwareVersion {
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        match *data {
+            [ic, version, revision, support, ..] => Ok(Self {
+                ic,
+                version,
+                revision,
+                support,
+            }),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+}
+
+/// Baud rate and modulation type, `BrTy` in `InListPassiveTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Modulation {
+    /// ISO14443A and MIFARE, 106 kbps.
+    TypeA106 = 0x00,
+    /// FeliCa, 212 kbps.
+    Felica212 = 0x01,
+    /// FeliCa, 424 kbps.
+    Felica424 = 0x02,
+    /// ISO14443B, 106 kbps.
+    TypeB106 = 0x03,
+    /// Innovision Jewel / Topaz, 106 kbps.
+    Jewel106 = 0x04,
+}
+
+impl Modulation {
+    /// Target type for `InAutoPoll`.
+    fn auto_poll_type(self) -> u8 {
+        match self {
+            // Generic passive, finds MIFARE, ISO-DEP and DEP targets.
+            Self::TypeA106 => 0x00,
+            Self::Felica212 => 0x11,
+            Self::Felica424 => 0x12,
+            Self::TypeB106 => 0x03,
+            Self::Jewel106 => 0x04,
+        }
+    }
+
+    fn from_auto_poll_type(typ: u8) -> Option<Self> {
+        match typ {
+            0x00 | 0x10 | 0x20 => Some(Self::TypeA106),
+            0x01 | 0x11 => Some(Self::Felica212),
+            0x02 | 0x12 => Some(Self::Felica424),
+            0x03 | 0x23 => Some(Self::TypeB106),
+            0x04 => Some(Self::Jewel106),
+            _ => None,
+        }
+    }
+}
+
+/// ISO14443A target found by `InListPassiveTarget`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TargetA {
+    /// Logical target number, used in the following commands.
+    pub tg: u8,
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    pub uid: Vec<u8, UID_MAX_LEN>,
+    /// ATS, including the TL byte. Empty if the card is not ISO-DEP, or automatic RATS is off.
+    pub ats: Vec<u8, ATS_MAX_LEN>,
+}
+
+impl TargetA {
+    /// Parse the target data, starting with `Tg`.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let err = Error::UnexpectedResponse;
+        let tg = *data.first().ok_or(err)?;
+        let atqa = [*data.get(1).ok_or(err)?, *data.get(2).ok_or(err)?];
+        let sak = *data.get(3).ok_or(err)?;
+        let uid_len = *data.get(4).ok_or(err)? as usize;
+        let uid = data.get(5..5 + uid_len).ok_or(err)?;
+        let uid = Vec::from_slice(uid).map_err(|_| err)?;
+
+        let ats = match data.get(5 + uid_len) {
+            None => Vec::new(),
+            Some(&ats_len) => {
+                let ats = data.get(5 + uid_len..5 + uid_len + ats_len as usize).ok_or(err)?;
+                Vec::from_slice(ats).map_err(|_| Error::BufferTooSmall)?
+            }
+        };
+
+        Ok(Self { tg, atqa, sak, uid, ats })
+    }
+}
+
+/// ISO14443B target found by `InListPassiveTarget`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TargetB {
+    /// Logical target number, used in the following commands.
+    pub tg: u8,
+    /// ATQB, starting with the 0x50 byte.
+    pub atqb: [u8; 12],
+    /// ATTRIB response. The PN53x sends ATTRIB itself, the card is ready for ISO-DEP.
+    pub attrib_res: Vec<u8, ATTRIB_RES_MAX_LEN>,
+}
+
+impl TargetB {
+    /// Parse the target data, starting with `Tg`.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let err = Error::UnexpectedResponse;
+        let tg = *data.first().ok_or(err)?;
+        let atqb = data.get(1..13).ok_or(err)?;
+        let attrib_res_len = *data.get(13).ok_or(err)? as usize;
+        let attrib_res = data.get(14..14 + attrib_res_len).ok_or(err)?;
+        let attrib_res = Vec::from_slice(attrib_res).map_err(|_| Error::BufferTooSmall)?;
+
+        let mut this = Self {
+            tg,
+            atqb: [0; 12],
+            attrib_res,
+        };
+        this.atqb.copy_from_slice(atqb);
+        Ok(this)
+    }
+
+    /// Pseudo-unique PICC identifier, from the ATQB.
+    pub fn pupi(&self) -> [u8; 4] {
+        [self.atqb[1], self.atqb[2], self.atqb[3], self.atqb[4]]
+    }
+}
+
+/// FeliCa target found by `InListPassiveTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TargetF {
+    /// Logical target number, used in the following commands.
+    pub tg: u8,
+    /// Polling response, with the system code as request data.
+    pub sensf_res: SensfRes,
+}
+
+impl TargetF {
+    /// Parse the target data, starting with `Tg`.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        let err = Error::UnexpectedResponse;
+        let tg = *data.first().ok_or(err)?;
+        // POL_RES, with its length byte.
+        let len = *data.get(1).ok_or(err)? as usize;
+        let pol_res = data.get(2..1 + len).ok_or(err)?;
+        let sensf_res = SensfRes::parse(pol_res).ok_or(err)?;
+        Ok(Self { tg, sensf_res })
+    }
+}
+
+/// Jewel / Topaz target found by `InListPassiveTarget`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TargetJewel {
+    /// Logical target number, used in the following commands.
+    pub tg: u8,
+    pub sens_res: [u8; 2],
+    pub id: [u8; 4],
+}
+
+impl TargetJewel {
+    /// Parse the target data, starting with `Tg`.
+    pub fn parse(data: &[u8]) -> Result<Self, Error> {
+        match *data {
+            [tg, s0, s1, i0, i1, i2, i3, ..] => Ok(Self {
+                tg,
+                sens_res: [s0, s1],
+                id: [i0, i1, i2, i3],
+            }),
+            _ => Err(Error::UnexpectedResponse),
+        }
+    }
+}
+
+/// A target of any technology.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Target {
+    A(TargetA),
+    B(TargetB),
+    F(TargetF),
+    Jewel(TargetJewel),
+}
+
+impl Target {
+    /// Parse the target data for `modulation`, starting with `Tg`.
+    pub fn parse(modulation: Modulation, data: &[u8]) -> Result<Self, Error> {
+        Ok(match modulation {
+            Modulation::TypeA106 => Self::A(TargetA::parse(data)?),
+            Modulation::Felica212 | Modulation::Felica424 => Self::F(TargetF::parse(data)?),
+            Modulation::TypeB106 => Self::B(TargetB::parse(data)?),
+            Modulation::Jewel106 => Self::Jewel(TargetJewel::parse(data)?),
+        })
+    }
+
+    /// Logical target number, used in the following commands.
+    pub fn tg(&self) -> u8 {
+        match self {
+            Self::A(t) => t.tg,
+            Self::B(t) => t.tg,
+            Self::F(t) => t.tg,
+            Self::Jewel(t) => t.tg,
+        }
+    }
+}
+
+/// `InListPassiveTarget` data for one target with `modulation`.
+pub fn list_passive_target(modulation: Modulation) -> Vec<u8, 7> {
+    let mut data = Vec::new();
+    let _ = data.extend_from_slice(&[0x01, modulation as u8]);
+    match modulation {
+        Modulation::TypeA106 | Modulation::Jewel106 => {}
+        // Polling for all system codes, asking for the system code, single time slot.
+        Modulation::Felica212 | Modulation::Felica424 => {
+            let _ = data.extend_from_slice(&[0x00, 0xFF, 0xFF, 0x01, 0x00]);
+        }
+        // AFI: all families.
+        Modulation::TypeB106 => {
+            let _ = data.push(0x00);
+        }
+    }
+    data
+}
+
+/// Parse the response to [`list_passive_target`]. Returns `None` if no card was found.
+pub fn parse_list_passive_target(modulation: Modulation, data: &[u8]) -> Result<Option<Target>, Error> {
+    match data.first() {
+        None => Err(Error::UnexpectedResponse),
+        Some(0) => Ok(None),
+        Some(_) => Target::parse(modulation, &data[1..]).map(Some),
+    }
+}
+
+/// `InListPassiveTarget` data for one ISO14443A target at 106 kbps.
+pub fn list_passive_target_a() -> [u8; 2] {
+    [0x01, Modulation::TypeA106 as u8]
+}
+
+/// Parse the response to [`list_passive_target_a`]. Returns `None` if no card was found.
+pub fn parse_list_passive_target_a(data: &[u8]) -> Result<Option<TargetA>, Error> {
+    match data.first() {
+        None => Err(Error::UnexpectedResponse),
+        Some(0) => Ok(None),
+        Some(_) => TargetA::parse(&data[1..]).map(Some),
+    }
+}
+
+/// `InAutoPoll` settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AutoPoll<'a> {
+    /// Number of polling rounds, 1 to 254, or 255 to poll until a target is found.
+    pub rounds: u8,
+    /// Time between rounds, in units of 150ms, 1 to 15.
+    pub period: u8,
+    /// Target types to look for, in order. 1 to 15.
+    pub types: &'a [Modulation],
+}
+
+impl AutoPoll<'_> {
+    /// Write the `InAutoPoll` data into `buf`. Returns its length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.rounds == 0 || !(1..=15).contains(&self.period) || !(1..=15).contains(&self.types.len()) {
+            return Err(Error::InvalidArgument);
+        }
+        let len = 2 + self.types.len();
+        if len > buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        buf[0] = self.rounds;
+        buf[1] = self.period;
+        for (b, t) in buf[2..len].iter_mut().zip(self.types) {
+            *b = t.auto_poll_type();
+        }
+        Ok(len)
+    }
+}
+
+/// Parse the response to `InAutoPoll`. Targets of types other than [`Modulation`] (DEP) are skipped.
+pub fn parse_auto_poll(data: &[u8]) -> Result<Vec<Target, MAX_TARGETS>, Error> {
+    let err = Error::UnexpectedResponse;
+    let count = *data.first().ok_or(err)?;
+    let mut targets = Vec::new();
+    let mut pos = 1;
+    for _ in 0..count {
+        let typ = *data.get(pos).ok_or(err)?;
+        let len = *data.get(pos + 1).ok_or(err)? as usize;
+        let target_data = data.get(pos + 2..pos + 2 + len).ok_or(err)?;
+        pos += 2 + len;
+
+        match Modulation::from_auto_poll_type(typ) {
+            Some(modulation) => {
+                let target = Target::parse(modulation, target_data)?;
+                targets.push(target).map_err(|_| Error::BufferTooSmall)?;
+            }
+            None => debug!("skipping InAutoPoll target type {:02x}", typ),
+        }
+    }
+    Ok(targets)
+}
+
+/// Write `WriteRegister` data for the `(address, value)` pairs into `buf`. Returns its length.
+pub fn write_register(regs: &[(u16, u8)], buf: &mut [u8]) -> Result<usize, Error> {
+    let len = regs.len() * 3;
+    if len > buf.len() {
+        return Err(Error::BufferTooSmall);
+    }
+    for (i, &(addr, val)) in regs.iter().enumerate() {
+        let [hi, lo] = addr.to_be_bytes();
+        buf[i * 3..][..3].copy_from_slice(&[hi, lo, val]);
+    }
+    Ok(len)
+}
+
+/// `RFConfiguration` timeout value for at least `us` microseconds.
+///
+/// Value `n` is 100us * 2^(n-1), up to 0x10 (3.28s).
+pub fn timeout_code(us: u32) -> u8 {
+    let mut n = 1;
+    while n < 0x10 && (100u32 << (n - 1)) < us {
+        n += 1;
+    }
+    n
+}
+
+/// Write `TgInitAsTarget` data into `buf`, to emulate a passive ISO14443A card. Returns its length.
+///
+/// The PN53x only lets the last 3 UID bytes be set: the UID is always 4 bytes, starting with 0x08.
+/// Likewise, only the historical bytes of the ATS are used, the PN53x builds the rest itself.
+pub fn init_as_target(config: &Iso14443aConfig<'_>, buf: &mut [u8]) -> Result<usize, Error> {
+    let &[uid0, ref nfcid1 @ ..] = config.uid else {
+        return Err(Error::InvalidArgument);
+    };
+    if nfcid1.len() != 3 {
+        return Err(Error::InvalidArgument);
+    }
+    if uid0 != 0x08 {
+        warn!("PN53x can't emulate UID byte 0 {:02x}, it'll be 08", uid0);
+    }
+    let historical = ats_historical_bytes(config.ats)?;
+
+    let len = 37 + historical.len();
+    if len > buf.len() {
+        return Err(Error::BufferTooSmall);
+    }
+    buf[..len].fill(0);
+    // Mode: passive only, PICC only.
+    buf[0] = 0x05;
+    buf[1..3].copy_from_slice(&config.atqa);
+    buf[3..6].copy_from_slice(nfcid1);
+    buf[6] = config.sak;
+    // FeliCa parameters and NFCID3t are left zeroed, LEN Gt is 0.
+    buf[36] = historical.len() as u8;
+    buf[37..len].copy_from_slice(historical);
+    Ok(len)
+}
+
+/// Find the historical bytes in an ATS, starting with the TL byte.
+fn ats_historical_bytes(ats: &[u8]) -> Result<&[u8], Error> {
+    let tl = *ats.first().ok_or(Error::InvalidArgument)? as usize;
+    if tl != ats.len() {
+        return Err(Error::InvalidArgument);
+    }
+    let Some(&t0) = ats.get(1) else {
+        return Ok(&[]);
+    };
+    // T0 is followed by TA, TB and TC, if present.
+    let start = 2 + ((t0 >> 4) & 0x07).count_ones() as usize;
+    ats.get(start..).ok_or(Error::InvalidArgument)
+}
+
+/// Mode byte, the first byte of the response to `TgInitAsTarget`: how the target was activated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TargetMode(pub u8);
+
+impl TargetMode {
+    /// Activated as an ISO14443-4 card, with RATS.
+    pub fn iso14443_4(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    /// Activated with DEP, by an NFCIP-1 initiator.
+    pub fn dep(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+}
+
+/// Parse the response to `TgInitAsTarget`. Returns the mode, and the first command from the initiator.
+pub fn parse_init_as_target(data: &[u8]) -> Result<(TargetMode, &[u8]), Error> {
+    match data {
+        [mode, command @ ..] => Ok((TargetMode(*mode), command)),
+        [] => Err(Error::UnexpectedResponse),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_response() {
+        let mut buf = [0; MAX_DATA_LEN];
+        let n = encode_command(cmd::IN_DATA_EXCHANGE, &[0x01, 0x30, 0x04], &mut buf).unwrap();
+        assert_eq!(buf[..n], [0xD4, 0x40, 0x01, 0x30, 0x04]);
+        assert_eq!(
+            encode_command(cmd::IN_DATA_EXCHANGE, &[0; MAX_DATA_LEN - 1], &mut buf),
+            Err(Error::BufferTooSmall)
+        );
+
+        assert_eq!(decode_response(cmd::IN_DATA_EXCHANGE, &[0xD5, 0x41, 0x00]), Ok(&[0x00][..]));
+        assert_eq!(
+            decode_response(cmd::IN_DATA_EXCHANGE, &[0xD5, 0x43, 0x00]),
+            Err(Error::UnexpectedResponse)
+        );
+        assert_eq!(
+            decode_response(cmd::IN_DATA_EXCHANGE, &[0xD5]),
+            Err(Error::UnexpectedResponse)
+        );
+    }
+
+    #[test]
+    fn status() {
+        assert_eq!(Status(0x00).check(), Ok(()));
+        assert_eq!(Status(0x01).check(), Err(Error::Status(StatusError::Timeout)));
+        assert_eq!(Status(0x41).error(), Some(StatusError::Timeout));
+        assert!(Status(0x40).more_info());
+        assert!(Status(0x80).nad());
+        assert_eq!(Status(0x3F).error(), Some(StatusError::Unknown(0x3F)));
+    }
+
+    #[test]
+    fn target_a() {
+        // MIFARE Classic, from the PN532 user manual.
+        let target = TargetA::parse(&[0x01, 0x00, 0x04, 0x08, 0x04, 0x92, 0x2E, 0x58, 0x32]).unwrap();
+        assert_eq!(target.tg, 1);
+        assert_eq!(target.atqa, [0x00, 0x04]);
+        assert_eq!(target.sak, 0x08);
+        assert_eq!(target.uid, [0x92, 0x2E, 0x58, 0x32]);
+        assert!(target.ats.is_empty());
+
+        // DESFire, 7 byte UID and ATS.
+        let data = [
+            0x01, 0x03, 0x44, 0x20, 0x07, 0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x06, 0x75, 0x77, 0x81, 0x02, 0x80,
+        ];
+        let target = TargetA::parse(&data).unwrap();
+        assert_eq!(target.atqa, [0x03, 0x44]);
+        assert_eq!(target.sak, 0x20);
+        assert_eq!(target.uid, [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        assert_eq!(target.ats, [0x06, 0x75, 0x77, 0x81, 0x02, 0x80]);
+
+        // ATS cut short, UID cut short, UID too long, no UID length.
+        assert_eq!(TargetA::parse(&data[..17]), Err(Error::UnexpectedResponse));
+        assert_eq!(TargetA::parse(&data[..10]), Err(Error::UnexpectedResponse));
+        let mut data = [0; 16];
+        data[4] = 11;
+        assert_eq!(TargetA::parse(&data), Err(Error::UnexpectedResponse));
+        assert_eq!(TargetA::parse(&data[..4]), Err(Error::UnexpectedResponse));
+
+        assert_eq!(parse_list_passive_target_a(&[0x00]), Ok(None));
+        assert_eq!(parse_list_passive_target_a(&[]), Err(Error::UnexpectedResponse));
+        let found = parse_list_passive_target_a(&[0x01, 0x01, 0x00, 0x04, 0x08, 0x04, 0x92, 0x2E, 0x58, 0x32]);
+        assert_eq!(found.unwrap().unwrap().uid, [0x92, 0x2E, 0x58, 0x32]);
+    }
+
+    #[test]
+    fn target_b() {
+        let data = [
+            0x01, 0x50, 0x12, 0x34, 0x56, 0x78, 0x00, 0x00, 0x00, 0x00, 0x00, 0x81, 0x81, 0x01, 0x00,
+        ];
+        let target = TargetB::parse(&data).unwrap();
+        assert_eq!(target.tg, 1);
+        assert_eq!(target.atqb, data[1..13]);
+        assert_eq!(target.pupi(), [0x12, 0x34, 0x56, 0x78]);
+        assert_eq!(target.attrib_res, [0x00]);
+
+        // ATTRIB response cut short, ATQB cut short.
+        assert_eq!(TargetB::parse(&data[..14]), Err(Error::UnexpectedResponse));
+        assert_eq!(TargetB::parse(&data[..12]), Err(Error::UnexpectedResponse));
+
+        let target = Target::parse(Modulation::TypeB106, &data).unwrap();
+        assert_eq!(target.tg(), 1);
+        assert!(matches!(target, Target::B(_)));
+    }
+
+    #[test]
+    fn target_f() {
+        let mut data = [0; 21];
+        // Tg, POL_RES length including itself, response code.
+        data[..3].copy_from_slice(&[0x01, 0x14, 0x01]);
+        data[3..11].copy_from_slice(&[0x01, 0x2E, 0x3D, 0x4C, 0x5B, 0x6A, 0x79, 0x88]);
+        data[11..19].copy_from_slice(&[0xA0; 8]);
+        data[19..].copy_from_slice(&[0x12, 0xFC]);
+
+        let target = TargetF::parse(&data).unwrap();
+        assert_eq!(target.tg, 1);
+        assert_eq!(target.sensf_res.nfcid2, data[3..11]);
+        assert_eq!(target.sensf_res.pad, [0xA0; 8]);
+        assert_eq!(target.sensf_res.request_data, Some([0x12, 0xFC]));
+
+        // Without the system code.
+        data[1] = 0x12;
+        assert_eq!(TargetF::parse(&data[..19]).unwrap().sensf_res.request_data, None);
+
+        // Cut short, bad response code.
+        data[1] = 0x14;
+        assert_eq!(TargetF::parse(&data[..20]), Err(Error::UnexpectedResponse));
+        data[2] = 0x02;
+        assert_eq!(TargetF::parse(&data), Err(Error::UnexpectedResponse));
+    }
+
+    #[test]
+    fn target_jewel() {
+        let data = [0x01, 0x0C, 0x00, 0x11, 0x22, 0x33, 0x44];
+        assert_eq!(
+            TargetJewel::parse(&data),
+            Ok(TargetJewel {
+                tg: 1,
+                sens_res: [0x0C, 0x00],
+                id: [0x11, 0x22, 0x33, 0x44],
+            })
+        );
+        assert_eq!(TargetJewel::parse(&data[..6]), Err(Error::UnexpectedResponse));
+
+        assert_eq!(parse_list_passive_target(Modulation::Jewel106, &[0x00]), Ok(None));
+        let mut resp = [0; 8];
+        resp[0] = 0x01;
+        resp[1..].copy_from_slice(&data);
+        let found = parse_list_passive_target(Modulation::Jewel106, &resp).unwrap();
+        assert_eq!(found.map(|t| t.tg()), Some(1));
+    }
+
+    #[test]
+    fn auto_poll() {
+        let mut buf = [0; 16];
+        let poll = AutoPoll {
+            rounds: 0xFF,
+            period: 2,
+            types: &[Modulation::TypeA106, Modulation::Felica212, Modulation::TypeB106],
+        };
+        let n = poll.encode(&mut buf).unwrap();
+        assert_eq!(buf[..n], [0xFF, 0x02, 0x00, 0x11, 0x03]);
+
+        assert_eq!(AutoPoll { rounds: 0, ..poll }.encode(&mut buf), Err(Error::InvalidArgument));
+        assert_eq!(AutoPoll { period: 16, ..poll }.encode(&mut buf), Err(Error::InvalidArgument));
+        assert_eq!(AutoPoll { types: &[], ..poll }.encode(&mut buf), Err(Error::InvalidArgument));
+        assert_eq!(poll.encode(&mut buf[..4]), Err(Error::BufferTooSmall));
+
+        // A MIFARE card and a DEP target, which is skipped.
+        let data = [
+            0x02, 0x10, 0x09, 0x01, 0x00, 0x04, 0x08, 0x04, 0x92, 0x2E, 0x58, 0x32, 0x40, 0x02, 0xAA, 0xBB,
+        ];
+        let targets = parse_auto_poll(&data).unwrap();
+        assert_eq!(targets.len(), 1);
+        let Target::A(target) = &targets[0] else {
+            panic!("expected a type A target, got {:?}", targets[0]);
+        };
+        assert_eq!(target.uid, [0x92, 0x2E, 0x58, 0x32]);
+
+        // A type B and a Jewel target.
+        let data = [
+            0x02, 0x23, 0x0E, 0x01, 0x50, 0x12, 0x34, 0x56, 0x78, 0x00, 0x00, 0x00, 0x00, 0x00, 0x81, 0x81, 0x00, 0x04, 0x07,
+            0x02, 0x0C, 0x00, 0x11, 0x22, 0x33, 0x44,
+        ];
+        let targets = parse_auto_poll(&data).unwrap();
+        assert_eq!(targets.len(), 2);
+        assert!(matches!(&targets[0], Target::B(t) if t.pupi() == [0x12, 0x34, 0x56, 0x78]));
+        assert!(matches!(&targets[1], Target::Jewel(t) if t.tg == 2));
+
+        assert_eq!(parse_auto_poll(&[0x00]), Ok(Vec::new()));
+        // Target data cut short, bad target data.
+        assert_eq!(parse_auto_poll(&data[..25]), Err(Error::UnexpectedResponse));
+        assert_eq!(parse_auto_poll(&[0x01, 0x04, 0x01, 0x01]), Err(Error::UnexpectedResponse));
+        assert_eq!(parse_auto_poll(&[]), Err(Error::UnexpectedResponse));
+    }
+
+    #[test]
+    fn historical_bytes() {
+        assert_eq!(ats_historical_bytes(&[0x06, 0x75, 0x77, 0x81, 0x02, 0x80]), Ok(&[0x80][..]));
+        // Only TB.
+        assert_eq!(ats_historical_bytes(&[0x05, 0x20, 0x81, 0x12, 0x34]), Ok(&[0x12, 0x34][..]));
+        assert_eq!(ats_historical_bytes(&[0x02, 0x00]), Ok(&[][..]));
+        assert_eq!(ats_historical_bytes(&[0x01]), Ok(&[][..]));
+
+        // TL doesn't match the length, T0 announces missing bytes.
+        assert_eq!(ats_historical_bytes(&[0x05, 0x75]), Err(Error::InvalidArgument));
+        assert_eq!(ats_historical_bytes(&[0x03, 0x70, 0x00]), Err(Error::InvalidArgument));
+        assert_eq!(ats_historical_bytes(&[]), Err(Error::InvalidArgument));
+    }
+
+    #[test]
+    fn init_as_target_data() {
+        let config = Iso14443aConfig {
+            atqa: [0x04, 0x00],
+            sak: 0x20,
+            uid: &[0x08, 0x11, 0x22, 0x33],
+            ats: &[0x07, 0x75, 0x77, 0x81, 0x02, 0x80, 0x81],
+        };
+        let mut buf = [0xFF; 64];
+        let n = init_as_target(&config, &mut buf).unwrap();
+        assert_eq!(n, 39);
+        assert_eq!(buf[..7], [0x05, 0x04, 0x00, 0x11, 0x22, 0x33, 0x20]);
+        assert!(buf[7..36].iter().all(|&b| b == 0));
+        assert_eq!(buf[36..39], [0x02, 0x80, 0x81]);
+
+        // The first UID byte can't be set, but it's not an error.
+        let uid = [0x04, 0x11, 0x22, 0x33];
+        assert_eq!(init_as_target(&Iso14443aConfig { uid: &uid, ..config }, &mut buf), Ok(39));
+
+        let uid = [0x04, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66];
+        assert_eq!(
+            init_as_target(&Iso14443aConfig { uid: &uid, ..config }, &mut buf),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(
+            init_as_target(&Iso14443aConfig { ats: &[0x02], ..config }, &mut buf),
+            Err(Error::InvalidArgument)
+        );
+        assert_eq!(init_as_target(&config, &mut buf[..38]), Err(Error::BufferTooSmall));
+
+        assert_eq!(
+            parse_init_as_target(&[0x08, 0xE0, 0x80]),
+            Ok((TargetMode(0x08), &[0xE0, 0x80][..]))
+        );
+        assert!(TargetMode(0x08).iso14443_4());
+        assert_eq!(parse_init_as_target(&[]), Err(Error::UnexpectedResponse));
+    }
+
+    #[test]
+    fn timeouts() {
+        assert_eq!(timeout_code(0), 0x01);
+        assert_eq!(timeout_code(100), 0x01);
+        assert_eq!(timeout_code(101), 0x02);
+        assert_eq!(timeout_code(1_000), 0x05);
+        assert_eq!(timeout_code(3_276_800), 0x10);
+        assert_eq!(timeout_code(u32::MAX), 0x10);
+    }
+}
@@ -0,0 +1,191 @@
+use log::debug;
+
+use crate::picc::{Application, Reply};
+
+const RATS: u8 = 0xE0;
+/// PPSS, with CID 0.
+const PPSS: u8 = 0xD0;
+const S_DESELECT: u8 = 0xC2;
+const S_WTX: u8 = 0xF2;
+const PCB_CHAINING: u8 = 0x10;
+const PCB_NAK: u8 = 0x10;
+const PCB_CID: u8 = 0x08;
+
+/// FSD for each FSDI, in the RATS parameter byte.
+const FSD_TABLE: [usize; 9] = [16, 24, 32, 40, 48, 64, 96, 128, 256];
+
+/// Computes the response to an APDU.
+type Handler = Box<dyn FnMut(&[u8]) -> Vec<u8>>;
+
+/// An ISO-DEP card: answers RATS with the ATS, then runs the block protocol and hands
+/// the APDUs to a handler.
+pub struct IsoDep {
+    ats: Vec<u8>,
+    handler: Handler,
+    /// Ask for a waiting time extension with this multiplier before answering each APDU.
+    pub wtx: Option<u8>,
+
+    /// RATS received.
+    active: bool,
+    /// PPS is only allowed right after the ATS.
+    pps_allowed: bool,
+    pps1: Option<u8>,
+    /// Max frame size the reader can receive, including the PCB and CRC.
+    fsd: usize,
+    block_num: u8,
+    /// Chained command received so far.
+    command: Vec<u8>,
+    /// Response left to send.
+    response: Vec<u8>,
+    /// Waiting for the S(WTX) response, before sending `response`.
+    waiting_wtx: bool,
+    /// Last block sent, for retransmission.
+    last: Vec<u8>,
+}
+
+impl IsoDep {
+    /// A card answering RATS with `ats`, starting with the TL byte, and APDUs with `handler`.
+    pub fn new(ats: &[u8], handler: impl FnMut(&[u8]) -> Vec<u8> + 'static) -> Self {
+        assert_eq!(ats.first().copied(), Some(ats.len() as u8), "TL must be the ATS length");
+        Self {
+            ats: ats.to_vec(),
+            handler: Box::new(handler),
+            wtx: None,
+            active: false,
+            pps_allowed: false,
+            pps1: None,
+            fsd: FSD_TABLE[2],
+            block_num: 1,
+            command: Vec::new(),
+            response: Vec::new(),
+            waiting_wtx: false,
+            last: Vec::new(),
+        }
+    }
+
+    /// PPS1 byte of the last PPS request accepted: DSI in bits 2-3, DRI in bits 0-1.
+    pub fn pps1(&self) -> Option<u8> {
+        self.pps1
+    }
+
+    fn send(&mut self, block: Vec<u8>) -> Reply {
+        self.last = block.clone();
+        Reply::Frame(block)
+    }
+
+    /// Send the next block of the response, chaining if it doesn't fit in the FSD.
+    fn send_response(&mut self) -> Reply {
+        let max = self.fsd - 3;
+        let n = self.response.len().min(max);
+        let chaining = n < self.response.len();
+
+        let mut block = vec![0x02 | self.block_num | if chaining { PCB_CHAINING } else { 0 }];
+        block.extend(self.response.drain(..n));
+        self.send(block)
+    }
+
+    fn i_block(&mut self, pcb: u8, inf: &[u8]) -> Reply {
+        self.block_num ^= 1;
+        self.command.extend_from_slice(inf);
+        if pcb & PCB_CHAINING != 0 {
+            return self.send(vec![0xA2 | self.block_num]);
+        }
+
+        let command = std::mem::take(&mut self.command);
+        self.response = (self.handler)(&command);
+        match self.wtx {
+            Some(mul) => {
+                self.waiting_wtx = true;
+                self.send(vec![S_WTX, mul])
+            }
+            None => self.send_response(),
+        }
+    }
+
+    fn r_block(&mut self, pcb: u8) -> Reply {
+        let num = pcb & 0x01;
+        if num == self.block_num {
+            // The reader didn't get our last block.
+            return Reply::Frame(self.last.clone());
+        }
+        if pcb & PCB_NAK != 0 {
+            return self.send(vec![0xA2 | self.block_num]);
+        }
+        // ACK of a chained block: send the next one.
+        self.block_num ^= 1;
+        if self.response.is_empty() {
+            debug!("iso-dep: ACK with nothing left to send");
+            return Reply::Ignore;
+        }
+        self.send_response()
+    }
+}
+
+impl Application for IsoDep {
+    fn sak(&self) -> u8 {
+        0x20
+    }
+
+    fn handle(&mut self, frame: &[u8]) -> Reply {
+        let Some(&pcb) = frame.first() else {
+            return Reply::Ignore;
+        };
+
+        if !self.active {
+            return match *frame {
+                [RATS, param] => {
+                    self.fsd = FSD_TABLE[((param >> 4) as usize).min(FSD_TABLE.len() - 1)];
+                    self.active = true;
+                    self.pps_allowed = true;
+                    Reply::Frame(self.ats.clone())
+                }
+                _ => Reply::Ignore,
+            };
+        }
+
+        if self.pps_allowed && pcb == PPSS {
+            self.pps_allowed = false;
+            return match *frame {
+                [PPSS, 0x11, pps1] => {
+                    self.pps1 = Some(pps1);
+                    Reply::Frame(vec![PPSS])
+                }
+                [PPSS, 0x01] => Reply::Frame(vec![PPSS]),
+                _ => Reply::Ignore,
+            };
+        }
+        self.pps_allowed = false;
+
+        if pcb & PCB_CID != 0 {
+            debug!("iso-dep: CID not supported");
+            return Reply::Ignore;
+        }
+        match pcb {
+            0x02 | 0x03 | 0x12 | 0x13 => self.i_block(pcb, &frame[1..]),
+            0xA2 | 0xA3 | 0xB2 | 0xB3 => self.r_block(pcb),
+            S_DESELECT => Reply::Deselect(vec![S_DESELECT]),
+            S_WTX if self.waiting_wtx => {
+                self.waiting_wtx = false;
+                self.send_response()
+            }
+            _ => {
+                debug!("iso-dep: unexpected block {:02x?}", frame);
+                Reply::Ignore
+            }
+        }
+    }
+
+    fn reset(&mut self) {
+        self.active = false;
+        self.pps_allowed = false;
+        self.block_num = 1;
+        self.command.clear();
+        self.response.clear();
+        self.waiting_wtx = false;
+        self.last.clear();
+    }
+
+    fn in_protocol(&self) -> bool {
+        self.active
+    }
+}
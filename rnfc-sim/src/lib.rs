@@ -0,0 +1,252 @@
+//! A simulated NFC field, with virtual ISO14443A cards in it.
+//!
+//! [`Field`] implements [`iso14443a_ll::Reader`], so the pollers and protocol layers
+//! can be run on the host against any number of [`Picc`]s at once. The cards run the
+//! ISO14443-3 state machine, with bit-level anticollision: when several cards answer,
+//! their bits collide like they would on the air.
+
+#![allow(async_fn_in_trait)]
+
+mod iso_dep;
+mod picc;
+mod type2;
+
+use log::{debug, trace};
+use rnfc_traits::iso14443a_ll::{self as ll, BitRate, Frame};
+
+pub use crate::iso_dep::IsoDep;
+pub use crate::picc::{Application, Faults, Picc, Reply, State};
+pub use crate::type2::Type2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// No card answered.
+    Timeout,
+    /// Several cards gave different answers to a frame other than anticollision.
+    Collision,
+    /// The answer was corrupted, see [`Faults::corrupt`].
+    Crc,
+}
+
+impl ll::Error for Error {
+    fn kind(&self) -> ll::ErrorKind {
+        match self {
+            Error::Timeout => ll::ErrorKind::Timeout,
+            Error::Collision | Error::Crc => ll::ErrorKind::Corruption,
+        }
+    }
+}
+
+/// The field of a virtual reader, with the cards in it.
+pub struct Field {
+    piccs: Vec<Picc>,
+    max_bit_rate: BitRate,
+    bit_rate: (BitRate, BitRate),
+    frames: usize,
+}
+
+impl Default for Field {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Field {
+    /// An empty field, with a reader supporting 106 kbps only.
+    pub fn new() -> Self {
+        Self {
+            piccs: Vec::new(),
+            max_bit_rate: BitRate::Kbps106,
+            bit_rate: (BitRate::Kbps106, BitRate::Kbps106),
+            frames: 0,
+        }
+    }
+
+    /// Bring a card into the field. Returns its index.
+    pub fn add(&mut self, picc: Picc) -> usize {
+        self.piccs.push(picc);
+        self.piccs.len() - 1
+    }
+
+    /// Take a card out of the field. The indexes of the cards after it shift down.
+    pub fn remove(&mut self, index: usize) -> Picc {
+        self.piccs.remove(index)
+    }
+
+    pub fn picc(&self, index: usize) -> &Picc {
+        &self.piccs[index]
+    }
+
+    pub fn picc_mut(&mut self, index: usize) -> &mut Picc {
+        &mut self.piccs[index]
+    }
+
+    /// Turn the field off and on again: all cards go back to [`State::Idle`].
+    pub fn reset(&mut self) {
+        for picc in &mut self.piccs {
+            picc.power_off();
+        }
+        self.bit_rate = (BitRate::Kbps106, BitRate::Kbps106);
+    }
+
+    pub fn set_max_bit_rate(&mut self, max: BitRate) {
+        self.max_bit_rate = max;
+    }
+
+    /// Bit rates set by the last [`set_bit_rate`](ll::Reader::set_bit_rate), PCD to PICC and PICC to PCD.
+    pub fn bit_rate(&self) -> (BitRate, BitRate) {
+        self.bit_rate
+    }
+
+    /// Number of frames sent so far.
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    fn short_frame(&mut self, cmd: u8, rx: &mut [u8]) -> Result<usize, Error> {
+        // Collisions in ATQA aren't reported, like most readers do: the bits are ORed.
+        let mut atqa = None;
+        for picc in &mut self.piccs {
+            if let Some(a) = picc.short_frame(cmd) {
+                let a0: [u8; 2] = atqa.unwrap_or_default();
+                atqa = Some([a0[0] | a[0], a0[1] | a[1]]);
+            }
+        }
+        let atqa = atqa.ok_or(Error::Timeout)?;
+        rx[..2].copy_from_slice(&atqa);
+        Ok(16)
+    }
+
+    fn anticoll(&mut self, tx: &[u8], bits: usize, rx: &mut [u8]) -> Result<usize, Error> {
+        let (Some(&sel), Some(_)) = (tx.first(), tx.get(1)) else {
+            return Err(Error::Timeout);
+        };
+        let known = &tx[2..];
+        let known_bits = bits - 16;
+
+        // Each card sends the rest of its UID CLn and BCC, 40 bits in total.
+        let answers: Vec<[u8; 5]> = self
+            .piccs
+            .iter_mut()
+            .filter_map(|picc| picc.anticoll(sel, known, known_bits))
+            .collect();
+        if answers.is_empty() {
+            return Err(Error::Timeout);
+        }
+
+        // The response completes the bits sent.
+        let mut frame = [0; 7];
+        for i in 0..bits {
+            set_bit(&mut frame, i, get_bit(tx, i));
+        }
+        let mut got_bits = 56;
+        for i in known_bits..40 {
+            let ones = answers.iter().filter(|a| get_bit(&a[..], i)).count();
+            if ones != 0 && ones != answers.len() {
+                // Collision: stop here. The bit is set to 1, the reader picks that branch.
+                debug!("anticoll: collision at bit {}", i);
+                set_bit(&mut frame, 16 + i, true);
+                got_bits = 16 + i;
+                break;
+            }
+            set_bit(&mut frame, 16 + i, ones != 0);
+        }
+
+        // Include the collision bit, it's past the bits received.
+        let len = (got_bits / 8 + 1).min(frame.len());
+        if len > rx.len() {
+            return Err(Error::Crc);
+        }
+        rx[..len].copy_from_slice(&frame[..len]);
+        Ok(got_bits)
+    }
+
+    fn standard_frame(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Error> {
+        let mut replies: Vec<(usize, Reply)> = Vec::new();
+        for (i, picc) in self.piccs.iter_mut().enumerate() {
+            match picc.standard_frame(tx) {
+                Reply::Ignore => {}
+                reply => replies.push((i, reply)),
+            }
+        }
+
+        // Cards sending the same bits at the same time don't collide.
+        let Some((i, reply)) = replies.pop() else {
+            return Err(Error::Timeout);
+        };
+        if replies.iter().any(|(_, r)| *r != reply) {
+            debug!("collision: {} cards answered", replies.len() + 1);
+            return Err(Error::Collision);
+        }
+
+        let faults = &mut self.piccs[i].faults;
+        if faults.drop > 0 {
+            faults.drop -= 1;
+            debug!("card {}: dropping the answer", i);
+            return Err(Error::Timeout);
+        }
+        if faults.corrupt > 0 {
+            faults.corrupt -= 1;
+            debug!("card {}: corrupting the answer", i);
+            return Err(Error::Crc);
+        }
+
+        match reply {
+            Reply::Ignore => unreachable!(),
+            Reply::Frame(data) | Reply::Deselect(data) => {
+                if data.len() > rx.len() {
+                    return Err(Error::Crc);
+                }
+                rx[..data.len()].copy_from_slice(&data);
+                Ok(data.len() * 8)
+            }
+            Reply::Nibble(val) => {
+                rx[0] = val & 0x0F;
+                Ok(4)
+            }
+        }
+    }
+}
+
+impl ll::Reader for Field {
+    type Error = Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
+        self.frames += 1;
+        trace!("TX: {:?} {:02x?}", opts, tx);
+        let res = match opts {
+            Frame::ReqA => self.short_frame(picc::REQA, rx),
+            Frame::WupA => self.short_frame(picc::WUPA, rx),
+            Frame::Anticoll { bits } => self.anticoll(tx, bits, rx),
+            Frame::Standard { .. } => self.standard_frame(tx, rx),
+        };
+        match &res {
+            Ok(bits) => trace!("RX: {:02x?} bits: {}", &rx[..(bits + 7) / 8], bits),
+            Err(e) => trace!("RX: {:?}", e),
+        }
+        res
+    }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.max_bit_rate
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        self.bit_rate = (tx, rx);
+        Ok(())
+    }
+}
+
+/// Bit `i` of a frame, LSB first in each byte, like on the air.
+pub(crate) fn get_bit(data: &[u8], i: usize) -> bool {
+    data.get(i / 8).is_some_and(|b| (b >> (i % 8)) & 1 != 0)
+}
+
+pub(crate) fn set_bit(data: &mut [u8], i: usize, val: bool) {
+    let mask = 1 << (i % 8);
+    if val {
+        data[i / 8] |= mask;
+    } else {
+        data[i / 8] &= !mask;
+    }
+}
@@ -0,0 +1,289 @@
+use std::any::Any;
+
+use log::debug;
+
+pub(crate) const REQA: u8 = 0x26;
+pub(crate) const WUPA: u8 = 0x52;
+const SEL: [u8; 3] = [0x93, 0x95, 0x97];
+const CASCADE_TAG: u8 = 0x88;
+/// SAK when the UID isn't complete yet.
+const SAK_CASCADE: u8 = 0x04;
+const HLTA: [u8; 2] = [0x50, 0x00];
+
+/// ISO14443-3 card state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Powered, waiting for REQA or WUPA.
+    Idle,
+    /// Woken up, going through anticollision at cascade level `level`, from 0.
+    Ready { level: usize },
+    /// Selected, talking to the [`Application`].
+    Active,
+    /// Halted with HLTA or DESELECT, only WUPA wakes it up.
+    Halt,
+}
+
+/// Answer of a card to a standard frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reply {
+    /// No answer.
+    Ignore,
+    Frame(Vec<u8>),
+    /// A 4-bit frame, like the ACK and NAK of Type 2 tags.
+    Nibble(u8),
+    /// Send the frame, then go to [`State::Halt`]. For ISO-DEP DESELECT.
+    Deselect(Vec<u8>),
+}
+
+/// What a card does once selected.
+pub trait Application {
+    /// SAK sent at the end of anticollision, telling what the card supports.
+    fn sak(&self) -> u8;
+
+    /// Handle a frame received in [`State::Active`].
+    fn handle(&mut self, frame: &[u8]) -> Reply;
+
+    /// The card left [`State::Active`], or the field went off.
+    fn reset(&mut self) {}
+
+    /// A higher protocol layer is active, like ISO-DEP after RATS. HLTA isn't handled
+    /// by the card anymore, it goes to [`handle`](Self::handle) like any other frame.
+    fn in_protocol(&self) -> bool {
+        false
+    }
+}
+
+/// [`Application`] that can be downcast, to get to it through [`Picc::app`].
+trait AnyApplication: Application {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Application + 'static> AnyApplication for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+/// Ways a card can misbehave.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Faults {
+    /// Send a wrong BCC during anticollision.
+    pub bad_bcc: bool,
+    /// Stay selected on HLTA.
+    pub ignore_hlta: bool,
+    /// Don't answer the next `drop` standard frames.
+    pub drop: usize,
+    /// Corrupt the answers to the next `corrupt` standard frames, they fail the CRC check.
+    pub corrupt: usize,
+}
+
+/// A virtual ISO14443A card.
+pub struct Picc {
+    uid: Vec<u8>,
+    pub atqa: [u8; 2],
+    state: State,
+    /// Woken up from [`State::Halt`], go back there instead of [`State::Idle`] on errors.
+    from_halt: bool,
+    app: Box<dyn AnyApplication>,
+    pub faults: Faults,
+}
+
+impl Picc {
+    /// A card with `uid`, of 4, 7 or 10 bytes. The ATQA tells the UID size.
+    pub fn new(uid: &[u8], app: impl Application + 'static) -> Self {
+        let size = match uid.len() {
+            4 => 0,
+            7 => 1,
+            10 => 2,
+            n => panic!("invalid UID length {}", n),
+        };
+        Self {
+            uid: uid.to_vec(),
+            atqa: [size << 6 | 0x04, 0x00],
+            state: State::Idle,
+            from_halt: false,
+            app: Box::new(app),
+            faults: Faults::default(),
+        }
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// The application, if it's an `A`.
+    pub fn app<A: Application + 'static>(&self) -> Option<&A> {
+        self.app.as_any().downcast_ref()
+    }
+
+    pub fn app_mut<A: Application + 'static>(&mut self) -> Option<&mut A> {
+        self.app.as_any_mut().downcast_mut()
+    }
+
+    fn levels(&self) -> usize {
+        self.uid.len() / 3
+    }
+
+    /// UID CLn of cascade level `level`, and its BCC.
+    fn cl(&self, level: usize) -> [u8; 5] {
+        let mut cl = [0; 5];
+        if level + 1 < self.levels() {
+            cl[0] = CASCADE_TAG;
+            cl[1..4].copy_from_slice(&self.uid[level * 3..][..3]);
+        } else {
+            cl[..4].copy_from_slice(&self.uid[level * 3..][..4]);
+        }
+        cl[4] = cl[0] ^ cl[1] ^ cl[2] ^ cl[3];
+        cl
+    }
+
+    /// Leave the current state after an unexpected frame.
+    fn error(&mut self) {
+        if self.state == State::Active {
+            self.app.reset();
+        }
+        self.state = if self.from_halt { State::Halt } else { State::Idle };
+    }
+
+    pub(crate) fn power_off(&mut self) {
+        self.app.reset();
+        self.state = State::Idle;
+        self.from_halt = false;
+    }
+
+    /// REQA or WUPA. Returns the ATQA, if the card answers.
+    pub(crate) fn short_frame(&mut self, cmd: u8) -> Option<[u8; 2]> {
+        match (self.state, cmd) {
+            (State::Idle, REQA | WUPA) => self.from_halt = false,
+            (State::Halt, WUPA) => self.from_halt = true,
+            (State::Halt, _) => return None,
+            _ => {
+                self.error();
+                return None;
+            }
+        }
+        self.state = State::Ready { level: 0 };
+        Some(self.atqa)
+    }
+
+    /// Anticollision frame, with the first `known_bits` of the UID CLn in `known`.
+    /// Returns the whole UID CLn and BCC, if the card answers.
+    pub(crate) fn anticoll(&mut self, sel: u8, known: &[u8], known_bits: usize) -> Option<[u8; 5]> {
+        let State::Ready { level } = self.state else {
+            return None;
+        };
+        if sel != SEL[level] {
+            self.error();
+            return None;
+        }
+
+        let mut cl = self.cl(level);
+        if (0..known_bits).any(|i| crate::get_bit(known, i) != crate::get_bit(&cl, i)) {
+            return None;
+        }
+        if self.faults.bad_bcc {
+            cl[4] ^= 0xFF;
+        }
+        Some(cl)
+    }
+
+    pub(crate) fn standard_frame(&mut self, tx: &[u8]) -> Reply {
+        match self.state {
+            State::Idle | State::Halt => Reply::Ignore,
+            State::Ready { level } => match *tx {
+                [sel, 0x70, ref cl @ ..] if sel == SEL[level] && cl.len() == 5 => {
+                    if *cl != self.cl(level) {
+                        return Reply::Ignore;
+                    }
+                    if level + 1 < self.levels() {
+                        self.state = State::Ready { level: level + 1 };
+                        Reply::Frame(vec![SAK_CASCADE])
+                    } else {
+                        debug!("selected {:02x?}", self.uid);
+                        self.state = State::Active;
+                        Reply::Frame(vec![self.app.sak()])
+                    }
+                }
+                _ => {
+                    self.error();
+                    Reply::Ignore
+                }
+            },
+            State::Active => {
+                if tx == HLTA && !self.app.in_protocol() {
+                    if !self.faults.ignore_hlta {
+                        self.app.reset();
+                        self.state = State::Halt;
+                    }
+                    return Reply::Ignore;
+                }
+                let reply = self.app.handle(tx);
+                if let Reply::Deselect(_) = reply {
+                    self.app.reset();
+                    self.state = State::Halt;
+                }
+                reply
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+
+    use super::*;
+    use crate::Type2;
+
+    fn select(picc: &mut Picc, cmd: u8) {
+        assert_eq!(picc.short_frame(cmd), Some([0x44, 0x00]));
+        for (level, sel) in SEL[..2].iter().enumerate() {
+            let cl = picc.cl(level);
+            let mut tx = vec![*sel, 0x70];
+            tx.extend_from_slice(&cl);
+            assert!(matches!(picc.standard_frame(&tx), Reply::Frame(_)));
+        }
+        assert_eq!(picc.state(), State::Active);
+    }
+
+    #[test]
+    fn halt_and_wake_up() {
+        let mut picc = Picc::new(&hex!("04 11 22 33 44 55 66"), Type2::new(vec![0; 16]));
+        select(&mut picc, REQA);
+
+        assert_eq!(picc.standard_frame(&HLTA), Reply::Ignore);
+        assert_eq!(picc.state(), State::Halt);
+        assert_eq!(picc.short_frame(REQA), None);
+
+        // Woken up from HALT, errors go back to HALT.
+        select(&mut picc, WUPA);
+        assert_eq!(picc.short_frame(REQA), None);
+        assert_eq!(picc.state(), State::Halt);
+    }
+
+    #[test]
+    fn cascade_levels() {
+        let picc = Picc::new(&hex!("04 11 22 77 88 99 aa bb cc dd"), Type2::new(vec![0; 16]));
+        assert_eq!(picc.atqa, [0x84, 0x00]);
+        assert_eq!(picc.cl(0), hex!("88 04 11 22 bf"));
+        assert_eq!(picc.cl(1), hex!("88 77 88 99 ee"));
+        assert_eq!(picc.cl(2), hex!("aa bb cc dd 00"));
+    }
+
+    #[test]
+    fn anticoll_prefix() {
+        let mut picc = Picc::new(&hex!("01 02 03 04"), Type2::new(vec![0; 16]));
+        picc.short_frame(WUPA).unwrap();
+        assert_eq!(picc.anticoll(0x93, &hex!("01 02"), 16), Some(hex!("01 02 03 04 04")));
+        assert_eq!(picc.anticoll(0x93, &hex!("01 03"), 9), None);
+        assert_eq!(picc.state(), State::Ready { level: 0 });
+    }
+}
@@ -0,0 +1,58 @@
+use crate::picc::{Application, Reply};
+
+const CMD_READ: u8 = 0x30;
+const CMD_WRITE: u8 = 0xA2;
+const ACK: u8 = 0x0A;
+const NAK: u8 = 0x00;
+/// Pages 0 and 1 hold the UID, and can't be written.
+const FIRST_WRITABLE_PAGE: usize = 2;
+
+/// A Type 2 tag, like MIFARE Ultralight or NTAG: memory in 4-byte pages, with READ and WRITE.
+pub struct Type2 {
+    memory: Vec<u8>,
+}
+
+impl Type2 {
+    /// A tag with `memory`, a whole number of pages.
+    pub fn new(memory: Vec<u8>) -> Self {
+        assert!(memory.len() % 4 == 0 && memory.len() >= 16, "memory must be whole pages");
+        Self { memory }
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    fn pages(&self) -> usize {
+        self.memory.len() / 4
+    }
+}
+
+impl Application for Type2 {
+    fn sak(&self) -> u8 {
+        0x00
+    }
+
+    fn handle(&mut self, frame: &[u8]) -> Reply {
+        match *frame {
+            [CMD_READ, page] if (page as usize) < self.pages() => {
+                // 4 pages, rolling over to page 0 at the end.
+                let data = (0..16)
+                    .map(|i| self.memory[(page as usize * 4 + i) % self.memory.len()])
+                    .collect();
+                Reply::Frame(data)
+            }
+            [CMD_WRITE, page, ref data @ ..]
+                if data.len() == 4 && (FIRST_WRITABLE_PAGE..self.pages()).contains(&(page as usize)) =>
+            {
+                self.memory[page as usize * 4..][..4].copy_from_slice(data);
+                Reply::Nibble(ACK)
+            }
+            _ => Reply::Nibble(NAK),
+        }
+    }
+}
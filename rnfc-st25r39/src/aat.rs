@@ -4,7 +4,24 @@ use embedded_hal_async::digital::Wait;
 
 use crate::{Error, Interface, St25r39};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AatStrategy {
+    /// Start at (`a_start`, `b_start`) and move to the best neighbor until no neighbor improves
+    /// the cost, halving the step size each time we get stuck.
+    ///
+    /// Fast, but can get stuck in a local minimum.
+    HillClimb,
+    /// Measure every point in the `a_min..=a_max` x `b_min..=b_max` grid with the given steps.
+    ///
+    /// Slow, but finds the global minimum at the grid's resolution.
+    Grid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct AatConfig {
+    pub strategy: AatStrategy,
     pub a_min: u8,
     pub a_max: u8,
     pub a_start: u8,
@@ -19,66 +36,189 @@ pub struct AatConfig {
     pub amp_weight: u8,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AatError<T> {
+    Interface(T),
+    Timeout,
+    /// `a_min` is above `a_max`, or `b_min` above `b_max`.
+    InvalidConfig,
+    /// The chip variant has no antenna tuning capacitor banks. See [`Capabilities::aat`](crate::Capabilities::aat).
+    Unsupported,
+}
+
+impl<T> From<Error<T>> for AatError<T> {
+    fn from(val: Error<T>) -> Self {
+        match val {
+            Error::Interface(e) => AatError::Interface(e),
+            Error::Timeout => AatError::Timeout,
+        }
+    }
+}
+
+/// Result of antenna tuning.
+///
+/// Persist `a` and `b` and restore them at boot with [`St25r39::set_antenna_tuning`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AatResult {
+    /// Value of the `ant_tune_a` capacitor bank.
+    pub a: u8,
+    /// Value of the `ant_tune_b` capacitor bank.
+    pub b: u8,
+    /// Measured amplitude at (a, b).
+    pub amplitude: u8,
+    /// Measured phase at (a, b).
+    pub phase: u8,
+    /// Cost at (a, b), as computed from the config targets and weights. Lower is better.
+    pub cost: u32,
+}
+
 impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
-    pub async fn aat(&mut self, conf: AatConfig) -> Result<(), Error<I::Error>> {
-        let mut a = conf.a_start;
-        let mut b = conf.b_start;
-        let mut cost = self.aat_measure(a, b, &conf).await?;
+    /// Run antenna auto-tuning.
+    ///
+    /// This turns the field on while measuring, and off when done. The best found
+    /// values are left applied.
+    pub async fn aat(&mut self, conf: AatConfig) -> Result<AatResult, AatError<I::Error>> {
+        if !self.capabilities().aat {
+            return Err(AatError::Unsupported);
+        }
+        if conf.a_min > conf.a_max || conf.b_min > conf.b_max {
+            return Err(AatError::InvalidConfig);
+        }
+
+        self.mode_on().await?;
+        self.regs().io_conf2().modify(|w| w.set_aat_en(true))?;
+        self.regs().op_control().modify(|w| w.set_tx_en(true))?;
+
+        let res = match conf.strategy {
+            AatStrategy::HillClimb => self.aat_hill_climb(&conf).await,
+            AatStrategy::Grid => self.aat_grid(&conf).await,
+        };
+
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                self.mode_off()?;
+                return Err(e.into());
+            }
+        };
+        self.mode_off()?;
+
+        debug!(
+            "aat done: a={} b={} amp={} pha={} cost={}",
+            res.a, res.b, res.amplitude, res.phase, res.cost
+        );
+        self.set_antenna_tuning(res.a, res.b)?;
+
+        Ok(res)
+    }
+
+    /// Set the antenna tuning capacitor banks, for example to restore a previous [`AatResult`].
+    pub fn set_antenna_tuning(&mut self, a: u8, b: u8) -> Result<(), AatError<I::Error>> {
+        if !self.capabilities().aat {
+            return Err(AatError::Unsupported);
+        }
+        self.regs().io_conf2().modify(|w| w.set_aat_en(true))?;
+        self.regs().ant_tune_a().write_value(a)?;
+        self.regs().ant_tune_b().write_value(b)?;
+        Ok(())
+    }
+
+    async fn aat_hill_climb(&mut self, conf: &AatConfig) -> Result<AatResult, Error<I::Error>> {
+        let mut a_step = conf.a_step.max(1);
+        let mut b_step = conf.b_step.max(1);
+
+        let a_start = conf.a_start.clamp(conf.a_min, conf.a_max);
+        let b_start = conf.b_start.clamp(conf.b_min, conf.b_max);
+        let mut best = self.aat_measure(a_start, b_start, conf).await?;
 
         loop {
-            let mut best = None;
-            for (a, b) in [
-                (a.saturating_add(conf.a_step).min(conf.a_max), b),
-                (a.saturating_sub(conf.a_step).max(conf.a_min), b),
-                (a, b.saturating_add(conf.b_step).min(conf.b_max)),
-                (a, b.saturating_sub(conf.b_step).max(conf.b_min)),
+            let (a, b) = (best.a, best.b);
+            let mut improved = false;
+            for (na, nb) in [
+                (a.saturating_add(a_step).min(conf.a_max), b),
+                (a.saturating_sub(a_step).max(conf.a_min), b),
+                (a, b.saturating_add(b_step).min(conf.b_max)),
+                (a, b.saturating_sub(b_step).max(conf.b_min)),
             ] {
-                let cost = self.aat_measure(a, b, &conf).await?;
+                if (na, nb) == (a, b) {
+                    continue;
+                }
+                let res = self.aat_measure(na, nb, conf).await?;
+                if res.cost < best.cost {
+                    best = res;
+                    improved = true;
+                }
+            }
+
+            if !improved {
+                if a_step == 1 && b_step == 1 {
+                    break;
+                }
+                a_step = (a_step / 2).max(1);
+                b_step = (b_step / 2).max(1);
+            }
+        }
 
+        Ok(best)
+    }
+
+    async fn aat_grid(&mut self, conf: &AatConfig) -> Result<AatResult, Error<I::Error>> {
+        let a_step = conf.a_step.max(1);
+        let b_step = conf.b_step.max(1);
+
+        let mut best: Option<AatResult> = None;
+        let mut a = conf.a_min;
+        loop {
+            let mut b = conf.b_min;
+            loop {
+                let res = self.aat_measure(a, b, conf).await?;
                 let new_best = match best {
                     None => true,
-                    Some((old_cost, _, _)) => cost < old_cost,
+                    Some(best) => res.cost < best.cost,
                 };
-
                 if new_best {
-                    best = Some((cost, a, b));
+                    best = Some(res);
                 }
-            }
 
-            let (new_cost, new_a, new_b) = best.unwrap();
-            if new_cost >= cost {
-                break;
+                match b.checked_add(b_step) {
+                    Some(nb) if nb <= conf.b_max => b = nb,
+                    _ => break,
+                }
             }
 
-            cost = new_cost;
-            a = new_a;
-            b = new_b;
+            match a.checked_add(a_step) {
+                Some(na) if na <= conf.a_max => a = na,
+                _ => break,
+            }
         }
-        self.regs().ant_tune_a().write_value(a)?;
-        self.regs().ant_tune_a().write_value(b)?;
 
-        Ok(())
+        // the loop always measures at least once.
+        Ok(unwrap!(best))
     }
 
-    async fn aat_measure(&mut self, a: u8, b: u8, conf: &AatConfig) -> Result<u32, Error<I::Error>> {
+    async fn aat_measure(&mut self, a: u8, b: u8, conf: &AatConfig) -> Result<AatResult, Error<I::Error>> {
         self.regs().ant_tune_a().write_value(a)?;
-        self.regs().ant_tune_a().write_value(b)?;
+        self.regs().ant_tune_b().write_value(b)?;
 
         // Wait for caps to settle.
         Timer::after(Duration::from_millis(1)).await;
 
-        info!("aa");
-        let amp = self.measure_phase().await?;
-        info!("aabb");
-        let pha = 0u8;
-        //let pha = self.measure_phase().await;
-        info!("aabbcc");
+        let amplitude = self.measure_amplitude().await?;
+        let phase = self.measure_phase().await?;
 
         // calculate cost function
-        let cost = amp.abs_diff(conf.amp_target) as u32 * conf.amp_weight as u32
-            + pha.abs_diff(conf.pha_target) as u32 * conf.pha_weight as u32;
+        let cost = amplitude.abs_diff(conf.amp_target) as u32 * conf.amp_weight as u32
+            + phase.abs_diff(conf.pha_target) as u32 * conf.pha_weight as u32;
 
-        info!("a={} b={} amp={} pha={} cost={}", a, b, amp, pha, cost);
-        Ok(cost)
+        trace!("aat: a={} b={} amp={} pha={} cost={}", a, b, amplitude, phase, cost);
+        Ok(AatResult {
+            a,
+            b,
+            amplitude,
+            phase,
+            cost,
+        })
     }
 }
@@ -0,0 +1,234 @@
+//! Analog configuration tables.
+//!
+//! Works like the analog configuration of ST's RFAL: a table of register/mask/value
+//! entries, keyed by direction, technology and bit rate. When the driver configures the
+//! chip for a given technology, all matching entries are applied, from least to most
+//! specific. Entries for [`Technology::Any`] / [`BitRate::Any`] apply to all technologies
+//! and bit rates respectively.
+//!
+//! The built-in [`DEFAULT_ANALOG_CONFIG`] is always applied first. A board-specific table
+//! set with [`St25r39::set_analog_config`](crate::St25r39::set_analog_config) is applied on top of it,
+//! so it only needs to contain the registers that differ for that antenna.
+//!
+//! Register addresses are the ST25R3916 ones. On a ST25R391x they're mapped to the
+//! register of the same name, entries for registers it doesn't have are skipped, and
+//! the default table, which is tuned for the ST25R3916, isn't applied.
+
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+
+use self::{BitRate as Br, Technology as Tech};
+use crate::regs::Reg;
+use crate::{ChipVariant, Error, Interface, St25r39};
+
+/// Register addresses, for use in [`AnalogConfigEntry`].
+///
+/// Space B registers are addressed with a 0x40 offset.
+pub mod reg {
+    pub const MODE: u8 = 0x03;
+    pub const AUX: u8 = 0x0A;
+    pub const RX_CONF1: u8 = 0x0B;
+    pub const RX_CONF2: u8 = 0x0C;
+    pub const RX_CONF3: u8 = 0x0D;
+    pub const RX_CONF4: u8 = 0x0E;
+    pub const ANT_TUNE_A: u8 = 0x26;
+    pub const ANT_TUNE_B: u8 = 0x27;
+    pub const TX_DRIVER: u8 = 0x28;
+    pub const PT_MOD: u8 = 0x29;
+    pub const FIELD_THRESHOLD_ACTV: u8 = 0x2A;
+    pub const FIELD_THRESHOLD_DEACTV: u8 = 0x2B;
+    pub const EMD_SUP_CONF: u8 = 0x45;
+    pub const SUBC_START_TIME: u8 = 0x46;
+    pub const P2P_RX_CONF: u8 = 0x4B;
+    pub const CORR_CONF1: u8 = 0x4C;
+    pub const CORR_CONF2: u8 = 0x4D;
+    pub const SQUELCH_TIMER: u8 = 0x4F;
+    pub const AUX_MOD: u8 = 0x68;
+    pub const TX_DRIVER_TIMING: u8 = 0x69;
+    pub const RES_AM_MOD: u8 = 0x6A;
+    pub const OVERSHOOT_CONF1: u8 = 0x70;
+    pub const OVERSHOOT_CONF2: u8 = 0x71;
+    pub const UNDERSHOOT_CONF1: u8 = 0x72;
+    pub const UNDERSHOOT_CONF2: u8 = 0x73;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Direction {
+    /// We're the reader.
+    Poll,
+    /// We're the card.
+    Listen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Technology {
+    /// Matches all technologies.
+    Any,
+    /// ISO14443A / NFC-A
+    NfcA,
+    /// ISO14443B / NFC-B
+    NfcB,
+    /// FeliCa / NFC-F
+    NfcF,
+    /// ISO15693 / NFC-V
+    NfcV,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitRate {
+    /// Matches all bit rates.
+    Any,
+    /// 26 kbit/s (NFC-V high data rate)
+    Kbps26,
+    Kbps106,
+    Kbps212,
+    Kbps424,
+    Kbps848,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AnalogConfigEntry {
+    pub direction: Direction,
+    pub technology: Technology,
+    pub bit_rate: BitRate,
+    /// Register address, see [`reg`].
+    pub reg: u8,
+    /// Bits of the register to change. Bits not in the mask are left untouched.
+    pub mask: u8,
+    pub value: u8,
+}
+
+impl AnalogConfigEntry {
+    pub const fn new(direction: Direction, technology: Technology, bit_rate: BitRate, reg: u8, mask: u8, value: u8) -> Self {
+        Self {
+            direction,
+            technology,
+            bit_rate,
+            reg,
+            mask,
+            value,
+        }
+    }
+
+    /// 0 = applies to everything, 2 = applies to a single technology+bit rate.
+    fn specificity(&self) -> u8 {
+        (self.technology != Technology::Any) as u8 + (self.bit_rate != BitRate::Any) as u8
+    }
+
+    fn matches(&self, direction: Direction, technology: Technology, bit_rate: BitRate) -> bool {
+        self.direction == direction
+            && (self.technology == Technology::Any || self.technology == technology)
+            && (self.bit_rate == BitRate::Any || self.bit_rate == bit_rate)
+    }
+}
+
+const fn poll(technology: Technology, bit_rate: BitRate, reg: u8, mask: u8, value: u8) -> AnalogConfigEntry {
+    AnalogConfigEntry::new(Direction::Poll, technology, bit_rate, reg, mask, value)
+}
+
+/// ST's recommended analog configuration for the ST25R3916.
+#[rustfmt::skip]
+pub const DEFAULT_ANALOG_CONFIG: &[AnalogConfigEntry] = &[
+    // Common to all technologies
+    poll(Tech::Any, Br::Any, reg::TX_DRIVER, 0xF0, 0x70), // 12% AM
+    poll(Tech::Any, Br::Any, reg::AUX_MOD, 0xFF, 0x10), // internal load modulation, regulator-based AM
+    poll(Tech::Any, Br::Any, reg::OVERSHOOT_CONF1, 0xFF, 0x40),
+    poll(Tech::Any, Br::Any, reg::OVERSHOOT_CONF2, 0xFF, 0x03),
+    poll(Tech::Any, Br::Any, reg::UNDERSHOOT_CONF1, 0xFF, 0x40),
+    poll(Tech::Any, Br::Any, reg::UNDERSHOOT_CONF2, 0xFF, 0x03),
+
+    // NFC-A
+    poll(Tech::NfcA, Br::Any, reg::MODE, 0x04, 0x00), // OOK
+    poll(Tech::NfcA, Br::Kbps106, reg::AUX, 0x04, 0x00), // correlator reception
+    poll(Tech::NfcA, Br::Kbps106, reg::RX_CONF1, 0xFF, 0x08),
+    poll(Tech::NfcA, Br::Kbps106, reg::RX_CONF2, 0xFF, 0x2D),
+    poll(Tech::NfcA, Br::Kbps106, reg::RX_CONF3, 0xFF, 0x00),
+    poll(Tech::NfcA, Br::Kbps106, reg::RX_CONF4, 0xFF, 0x00),
+    poll(Tech::NfcA, Br::Kbps106, reg::CORR_CONF1, 0xFF, 0x51),
+    poll(Tech::NfcA, Br::Kbps106, reg::CORR_CONF2, 0xFF, 0x00),
+
+    // NFC-B
+    poll(Tech::NfcB, Br::Any, reg::MODE, 0x04, 0x04), // AM
+    poll(Tech::NfcB, Br::Any, reg::TX_DRIVER, 0xF0, 0x50), // 10% AM
+    poll(Tech::NfcB, Br::Kbps106, reg::AUX, 0x04, 0x00),
+    poll(Tech::NfcB, Br::Kbps106, reg::RX_CONF1, 0xFF, 0x04),
+    poll(Tech::NfcB, Br::Kbps106, reg::RX_CONF2, 0xFF, 0x3D),
+    poll(Tech::NfcB, Br::Kbps106, reg::RX_CONF3, 0xFF, 0x00),
+    poll(Tech::NfcB, Br::Kbps106, reg::RX_CONF4, 0xFF, 0x00),
+    poll(Tech::NfcB, Br::Kbps106, reg::CORR_CONF1, 0xFF, 0x1B),
+    poll(Tech::NfcB, Br::Kbps106, reg::CORR_CONF2, 0xFF, 0x00),
+
+    // NFC-F
+    poll(Tech::NfcF, Br::Any, reg::MODE, 0x04, 0x04), // AM
+    poll(Tech::NfcF, Br::Any, reg::TX_DRIVER, 0xF0, 0x50), // 10% AM
+    poll(Tech::NfcF, Br::Any, reg::AUX, 0x04, 0x00),
+    poll(Tech::NfcF, Br::Any, reg::RX_CONF1, 0xFF, 0x13),
+    poll(Tech::NfcF, Br::Any, reg::RX_CONF2, 0xFF, 0x3D),
+    poll(Tech::NfcF, Br::Any, reg::RX_CONF3, 0xFF, 0x00),
+    poll(Tech::NfcF, Br::Any, reg::RX_CONF4, 0xFF, 0x00),
+    poll(Tech::NfcF, Br::Any, reg::CORR_CONF1, 0xFF, 0x54),
+    poll(Tech::NfcF, Br::Any, reg::CORR_CONF2, 0xFF, 0x00),
+
+    // NFC-V
+    poll(Tech::NfcV, Br::Any, reg::MODE, 0x04, 0x00), // OOK
+    poll(Tech::NfcV, Br::Any, reg::AUX, 0x04, 0x00),
+    poll(Tech::NfcV, Br::Any, reg::RX_CONF1, 0xFF, 0x13),
+    poll(Tech::NfcV, Br::Any, reg::RX_CONF2, 0xFF, 0x2D),
+    poll(Tech::NfcV, Br::Any, reg::RX_CONF3, 0xFF, 0x00),
+    poll(Tech::NfcV, Br::Any, reg::RX_CONF4, 0xFF, 0x00),
+    poll(Tech::NfcV, Br::Any, reg::CORR_CONF1, 0xFF, 0x13),
+    poll(Tech::NfcV, Br::Any, reg::CORR_CONF2, 0xFF, 0x01),
+];
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Set a board-specific analog configuration table.
+    ///
+    /// It is applied on top of [`DEFAULT_ANALOG_CONFIG`] every time the chip is
+    /// configured for a technology.
+    pub fn set_analog_config(&mut self, table: &'static [AnalogConfigEntry]) {
+        self.analog_config = table;
+    }
+
+    pub(crate) fn apply_analog_config(
+        &mut self,
+        direction: Direction,
+        technology: Technology,
+        bit_rate: BitRate,
+    ) -> Result<(), Error<I::Error>> {
+        trace!("applying analog config: {:?} {:?} {:?}", direction, technology, bit_rate);
+        let default = match self.chip.variant {
+            ChipVariant::St25r3916 => DEFAULT_ANALOG_CONFIG,
+            _ => &[],
+        };
+        for table in [default, self.analog_config] {
+            for specificity in 0..=2 {
+                for e in table {
+                    if e.specificity() == specificity && e.matches(direction, technology, bit_rate) {
+                        self.write_analog_config_entry(e)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn write_analog_config_entry(&mut self, e: &AnalogConfigEntry) -> Result<(), Error<I::Error>> {
+        if self.chip.variant.reg_addr(e.reg).is_none() {
+            warn!(
+                "analog config: no register {:02x} on {:?}, skipping",
+                e.reg, self.chip.variant
+            );
+            return Ok(());
+        }
+        let mut reg = Reg::<_, u8>::new(&mut self.iface, e.reg);
+        if e.mask == 0xFF {
+            reg.write_value(e.value)
+        } else {
+            reg.modify(|v| *v = (*v & !e.mask) | (e.value & e.mask))
+        }
+    }
+}
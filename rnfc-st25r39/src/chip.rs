@@ -0,0 +1,267 @@
+use crate::{regs, Interface};
+
+/// Chip variant, detected from the `ic_identity` register.
+///
+/// Parts within a family report the same identity, so they can't be told apart.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChipVariant {
+    /// ST25R3911B, ST25R3912, ST25R3913, ST25R3914 or ST25R3915.
+    St25r391x,
+    /// ST25R3916 or ST25R3917.
+    St25r3916,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChipInfo {
+    pub variant: ChipVariant,
+    /// Silicon revision, from `ic_identity.ic_rev`.
+    pub revision: u8,
+}
+
+/// Features that differ between chip variants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Capabilities {
+    /// FIFO size in bytes. Frames longer than this can't be transmitted or received.
+    pub fifo_size: usize,
+    /// Capacitive sensor, for [`WakeupConfig::capacitive`](crate::WakeupConfig::capacitive).
+    pub capacitive_wakeup: bool,
+    /// Automatic antenna tuning with two capacitor banks, for [`St25r39::aat`](crate::St25r39::aat).
+    pub aat: bool,
+    /// 10% ASK modulation, for [`start_iso14443b`](crate::St25r39::start_iso14443b) and
+    /// [`start_felica`](crate::St25r39::start_felica).
+    pub am_modulation: bool,
+}
+
+impl ChipVariant {
+    pub(crate) fn from_ic_type(ic_type: regs::IcIdentityIcType) -> Option<Self> {
+        match ic_type {
+            regs::IcIdentityIcType::ST25R3911 => Some(Self::St25r391x),
+            regs::IcIdentityIcType::ST25R3916 => Some(Self::St25r3916),
+            _ => None,
+        }
+    }
+
+    pub const fn capabilities(self) -> Capabilities {
+        match self {
+            Self::St25r391x => Capabilities {
+                fifo_size: 96,
+                // Only some parts of the family have it, and we can't tell which one we have.
+                capacitive_wakeup: false,
+                // Antenna calibration is done with trim switches, not capacitor banks.
+                aat: false,
+                // Needs the modulation depth calibrated with the AM registers, which aren't supported.
+                am_modulation: false,
+            },
+            Self::St25r3916 => Capabilities {
+                fifo_size: 512,
+                capacitive_wakeup: true,
+                aat: true,
+                am_modulation: true,
+            },
+        }
+    }
+
+    /// Number of IRQ status (and mask) registers.
+    pub(crate) const fn irq_regs(self) -> u8 {
+        match self {
+            Self::St25r391x => 3,
+            Self::St25r3916 => 4,
+        }
+    }
+
+    /// `op_control.en_fd` value to run the external field detector.
+    pub(crate) const fn auto_efd(self) -> regs::OpControlEnFd {
+        match self {
+            // Single enable bit, where the ST25R3916 has its manual/auto field.
+            Self::St25r391x => regs::OpControlEnFd::MANUAL_EFD_PDT,
+            Self::St25r3916 => regs::OpControlEnFd::AUTO_EFD,
+        }
+    }
+
+    /// Address on this variant of the register at `addr` in the ST25R3916 map, which is the
+    /// one `regs` is generated from. `None` if the variant has no such register.
+    pub(crate) const fn reg_addr(self, addr: u8) -> Option<u8> {
+        match self {
+            Self::St25r3916 => Some(addr),
+            Self::St25r391x => match addr {
+                // io_conf1 ..= iso14443b_2
+                0x00..=0x07 => Some(addr),
+                // passive_target
+                0x08 => None,
+                // stream_mode ..= gpt2
+                0x09..=0x14 => Some(addr - 1),
+                // ppon2
+                0x15 => None,
+                // irq_mask, 3 registers
+                0x16..=0x18 => Some(addr - 2),
+                0x19 => None,
+                // irq_main, 3 registers
+                0x1A..=0x1C => Some(addr - 3),
+                0x1D => None,
+                // fifo_status1, fifo_status2, collision_status
+                0x1E..=0x20 => Some(addr - 4),
+                // passive_target_status
+                0x21 => None,
+                // num_tx_bytes1 ..= ad_result
+                0x22..=0x25 => Some(addr - 5),
+                // field_threshold_actv is the single field threshold register
+                0x2A => Some(0x29),
+                // regulator_control
+                0x2C => Some(0x2A),
+                // rssi_result ..= capacitance_measure_result
+                0x2D..=0x3E => Some(addr - 1),
+                // ic_identity
+                0x3F => Some(0x3F),
+                // regulator_result, in space B on the ST25R3916
+                0x6C => Some(0x2B),
+                _ => None,
+            },
+        }
+    }
+
+    /// Direct command code on this variant of the ST25R3916 command `cmd`.
+    pub(crate) const fn command(self, cmd: u8) -> u8 {
+        match (self, cmd) {
+            // No separate "clear FIFO", the "clear" command stops and clears the FIFO.
+            (Self::St25r391x, 0xDB) => 0xC2,
+            _ => cmd,
+        }
+    }
+}
+
+/// ST25R391x "RFO normal level definition" register, which sets the driver resistance.
+/// It has no equivalent in the ST25R3916 map, so it's accessed through [`ChipIface::inner`].
+pub(crate) const ST25R391X_RFO_NORMAL_LEVEL: u8 = 0x27;
+
+/// Interface to the detected chip, taking ST25R3916 register addresses and command codes
+/// and translating them for the variant.
+pub(crate) struct ChipIface<I> {
+    pub(crate) inner: I,
+    pub(crate) variant: ChipVariant,
+}
+
+impl<I: Interface> Interface for ChipIface<I> {
+    type Error = I::Error;
+
+    fn do_command(&mut self, cmd: u8) -> Result<(), Self::Error> {
+        self.inner.do_command(self.variant.command(cmd))
+    }
+
+    fn read_reg(&mut self, reg: u8) -> Result<u8, Self::Error> {
+        match self.variant.reg_addr(reg) {
+            Some(addr) => self.inner.read_reg(addr),
+            None => panic!("register {:02x} doesn't exist on {:?}", reg, self.variant),
+        }
+    }
+
+    fn write_reg(&mut self, reg: u8, val: u8) -> Result<(), Self::Error> {
+        match self.variant.reg_addr(reg) {
+            Some(addr) => self.inner.write_reg(addr, val),
+            None => panic!("register {:02x} doesn't exist on {:?}", reg, self.variant),
+        }
+    }
+
+    fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+        self.inner.read_fifo(data)
+    }
+
+    fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.write_fifo(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::fake_st25r39;
+    use crate::{AatError, Command};
+
+    #[test]
+    fn st25r391x_register_map() {
+        let mut st = fake_st25r39(ChipVariant::St25r391x);
+        st.regs().stream_mode().write_value(regs::StreamMode(0x01)).unwrap();
+        st.regs().mask_rx_timer().write_value(0x02).unwrap();
+        st.regs().irq_mask(2).write_value(0x03).unwrap();
+        st.regs().irq_main(0).write_value(0x04).unwrap();
+        st.regs().fifo_status1().write_value(0x05).unwrap();
+        st.regs().num_tx_bytes1().write_value(0x06).unwrap();
+        st.regs().field_threshold_actv().write(|_| {}).unwrap();
+        st.regs().rssi_result().write_value(regs::RssiResult(0x07)).unwrap();
+        st.regs()
+            .wup_timer_control()
+            .write_value(regs::WupTimerControl(0x08))
+            .unwrap();
+        st.regs().capacitance_measure_result().write_value(0x09).unwrap();
+        st.regs().regulator_result().write_value(regs::RegulatorResult(0x0A)).unwrap();
+
+        let regs = &st.iface.inner.regs;
+        assert_eq!(regs[0x08], 0x01);
+        assert_eq!(regs[0x0E], 0x02);
+        assert_eq!(regs[0x16], 0x03);
+        assert_eq!(regs[0x17], 0x04);
+        assert_eq!(regs[0x1A], 0x05);
+        assert_eq!(regs[0x1D], 0x06);
+        assert_eq!(regs[0x2C], 0x07);
+        assert_eq!(regs[0x31], 0x08);
+        assert_eq!(regs[0x3D], 0x09);
+        assert_eq!(regs[0x2B], 0x0A);
+        // Nothing was written above 0x3F, there's no space B.
+        assert!(regs[0x40..].iter().all(|&r| r == 0));
+
+        assert_eq!(ChipVariant::St25r391x.reg_addr(0x2A), Some(0x29));
+        assert_eq!(ChipVariant::St25r391x.reg_addr(0x3F), Some(0x3F));
+        for missing in [0x08, 0x15, 0x19, 0x1D, 0x21, 0x26, 0x27, 0x28, 0x2B, 0x55] {
+            assert_eq!(ChipVariant::St25r391x.reg_addr(missing), None, "{:02x}", missing);
+        }
+        for addr in 0..=0x7F {
+            assert_eq!(ChipVariant::St25r3916.reg_addr(addr), Some(addr));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn st25r391x_missing_register() {
+        let mut st = fake_st25r39(ChipVariant::St25r391x);
+        let _ = st.regs().tx_driver().read();
+    }
+
+    #[test]
+    fn commands() {
+        let clear_fifo = Command::ClearFifo as u8;
+        assert_eq!(ChipVariant::St25r391x.command(clear_fifo), Command::Stop as u8);
+        assert_eq!(ChipVariant::St25r3916.command(clear_fifo), clear_fifo);
+        assert_eq!(
+            ChipVariant::St25r391x.command(Command::TransmitWithCrc as u8),
+            Command::TransmitWithCrc as u8
+        );
+    }
+
+    #[test]
+    fn st25r391x_capabilities() {
+        let mut st = fake_st25r39(ChipVariant::St25r391x);
+        assert_eq!(st.capabilities().fifo_size, 96);
+        assert_eq!(st.set_antenna_tuning(0x80, 0x80), Err(AatError::Unsupported));
+        // ant_tune_a/b weren't touched.
+        assert!(st.iface.inner.regs.iter().all(|&r| r == 0));
+
+        let mut st = fake_st25r39(ChipVariant::St25r3916);
+        assert_eq!(st.capabilities().fifo_size, 512);
+        st.set_antenna_tuning(0x12, 0x34).unwrap();
+        assert_eq!(st.iface.inner.regs[0x26..0x28], [0x12, 0x34]);
+    }
+
+    #[test]
+    fn st25r391x_fifo_rx_bytes() {
+        let mut st = fake_st25r39(ChipVariant::St25r391x);
+        st.iface.inner.regs[0x1A] = 95;
+        assert_eq!(st.fifo_rx_bytes(regs::FifoStatus2(0xC0)).unwrap(), 95);
+
+        let mut st = fake_st25r39(ChipVariant::St25r3916);
+        st.iface.inner.regs[0x1E] = 0x10;
+        assert_eq!(st.fifo_rx_bytes(regs::FifoStatus2(0x40)).unwrap(), 0x110);
+    }
+}
@@ -0,0 +1,65 @@
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+use rnfc_traits::diagnostics::{ChipId, Diagnostics, Rssi};
+
+use crate::{Command, Error, Interface, St25r39};
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Run an amplitude or phase measurement with the field on.
+    async fn measure_with_field_on(&mut self, cmd: Command) -> Result<u8, Error<I::Error>> {
+        self.mode_on().await?;
+        let res = async {
+            self.regs().op_control().modify(|w| w.set_tx_en(true))?;
+            self.cmd_wait(cmd).await?;
+            self.regs().ad_result().read()
+        }
+        .await;
+        self.mode_off()?;
+        res
+    }
+
+    pub(crate) fn update_rssi(&mut self) -> Result<(), Error<I::Error>> {
+        let r = self.regs().rssi_result().read()?;
+        self.last_rssi = Some(Rssi {
+            am: r.rssi_am(),
+            pm: r.rssi_pm(),
+        });
+        Ok(())
+    }
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> Diagnostics for St25r39<I, IrqPin> {
+    type Error = Error<I::Error>;
+
+    async fn chip_id(&mut self) -> Result<ChipId, Self::Error> {
+        let id = self.regs().ic_identity().read()?;
+        Ok(ChipId {
+            chip_type: id.ic_type().0,
+            revision: id.ic_rev().0,
+        })
+    }
+
+    async fn vdd_mv(&mut self) -> Result<Option<u32>, Self::Error> {
+        self.mode_on().await?;
+        let res = self.measure_vdd().await;
+        self.mode_off()?;
+        Ok(Some(res?))
+    }
+
+    /// Returns the raw `regulator_result` register. Bit 0 is `i_lim`, bits 4..8 the regulated voltage.
+    async fn regulator(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(Some(self.regs().regulator_result().read()?.0))
+    }
+
+    async fn amplitude(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(Some(self.measure_with_field_on(Command::MeasureAmplitude).await?))
+    }
+
+    async fn phase(&mut self) -> Result<Option<u8>, Self::Error> {
+        Ok(Some(self.measure_with_field_on(Command::MeasurePhase).await?))
+    }
+
+    fn last_rssi(&self) -> Option<Rssi> {
+        self.last_rssi
+    }
+}
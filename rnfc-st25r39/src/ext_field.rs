@@ -0,0 +1,123 @@
+//! External field detection.
+//!
+//! Detects RF fields emitted by other devices (phones, other readers...). The chip
+//! has two thresholds for each direction: `trg` is used for peer detection, `rfe`
+//! for RF collision avoidance before turning on our own field.
+
+use embedded_hal::digital::InputPin;
+use embedded_hal_async::digital::Wait;
+
+use crate::{regs, ChipVariant, Error, Interface, Interrupt, St25r39};
+
+/// External field detector threshold voltage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FieldThreshold {
+    Mv75 = 0x00,
+    Mv105 = 0x01,
+    Mv150 = 0x02,
+    Mv205 = 0x03,
+    Mv290 = 0x04,
+    Mv400 = 0x05,
+    Mv560 = 0x06,
+    Mv800 = 0x07,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FieldThresholds {
+    /// Peer detection, field on.
+    pub activation_trg: FieldThreshold,
+    /// RF collision avoidance, field on.
+    pub activation_rfe: FieldThreshold,
+    /// Peer detection, field off.
+    pub deactivation_trg: FieldThreshold,
+    /// RF collision avoidance, field off.
+    pub deactivation_rfe: FieldThreshold,
+}
+
+impl Default for FieldThresholds {
+    /// Chip reset values.
+    fn default() -> Self {
+        Self {
+            activation_trg: FieldThreshold::Mv205,
+            activation_rfe: FieldThreshold::Mv205,
+            deactivation_trg: FieldThreshold::Mv150,
+            deactivation_rfe: FieldThreshold::Mv150,
+        }
+    }
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Set the external field detector thresholds.
+    pub fn set_field_thresholds(&mut self, thresholds: FieldThresholds) -> Result<(), Error<I::Error>> {
+        self.regs().field_threshold_actv().write(|w| {
+            w.set_trg(regs::FieldThresholdActvTrg(thresholds.activation_trg as u8));
+            w.set_rfe(regs::FieldThresholdActvRfe(thresholds.activation_rfe as u8));
+        })?;
+        // The ST25R391x has a single threshold for both.
+        if self.chip.variant == ChipVariant::St25r3916 {
+            self.regs().field_threshold_deactv().write(|w| {
+                w.set_trg(regs::FieldThresholdDeactvTrg(thresholds.deactivation_trg as u8));
+                w.set_rfe(regs::FieldThresholdDeactvRfe(thresholds.deactivation_rfe as u8));
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Returns whether an external field is currently detected.
+    ///
+    /// Only valid while our own field is off.
+    pub fn external_field_present(&mut self) -> Result<bool, Error<I::Error>> {
+        self.enable_field_detector()?;
+        Ok(self.regs().aux_display().read()?.efd_o())
+    }
+
+    /// Wait until an external field appears.
+    ///
+    /// Returns immediately if one is already present.
+    pub async fn wait_external_field_on(&mut self) -> Result<(), Error<I::Error>> {
+        self.wait_external_field(true).await
+    }
+
+    /// Wait until the external field disappears.
+    ///
+    /// Returns immediately if there's none.
+    pub async fn wait_external_field_off(&mut self) -> Result<(), Error<I::Error>> {
+        self.wait_external_field(false).await
+    }
+
+    async fn wait_external_field(&mut self, on: bool) -> Result<(), Error<I::Error>> {
+        let irq = match on {
+            true => Interrupt::Eon,
+            false => Interrupt::Eof,
+        };
+
+        self.irq_clear()?;
+        self.irq_set_mask(!(1 << irq as u32))?;
+
+        // Check after unmasking, so we don't miss an event happening in between.
+        if self.external_field_present()? != on {
+            debug!("waiting for external field {}", if on { "on" } else { "off" });
+            loop {
+                self.irq.wait_for_high().await.unwrap();
+                self.irq_update()?;
+                if self.irq(irq) {
+                    break;
+                }
+            }
+        }
+
+        self.irq_set_mask(0)?;
+        Ok(())
+    }
+
+    fn enable_field_detector(&mut self) -> Result<(), Error<I::Error>> {
+        let efd = self.chip.variant.auto_efd();
+        self.regs().op_control().modify(|w| {
+            if w.en_fd() == regs::OpControlEnFd::EFD_OFF {
+                w.set_en_fd(efd);
+            }
+        })
+    }
+}
@@ -0,0 +1,204 @@
+use rnfc_traits::nfcf_ll as ll;
+use rnfc_traits::nfcf_ll::{SensfRes, TimeSlots};
+
+use crate::analog_config::{BitRate, Technology};
+use crate::fmt::Bytes;
+pub use crate::iso14443a::Error;
+use crate::*;
+
+/// Max FeliCa frame length, including the length byte.
+const MAX_FRAME_LEN: usize = 255;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FelicaBitRate {
+    Kbps212,
+    Kbps424,
+}
+
+/// Length of the preamble sent before the sync code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FelicaPreamble {
+    Bits48,
+    Bits64,
+    Bits80,
+    Bits96,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FelicaConfig {
+    pub bit_rate: FelicaBitRate,
+    pub preamble: FelicaPreamble,
+}
+
+impl Default for FelicaConfig {
+    fn default() -> Self {
+        Self {
+            bit_rate: FelicaBitRate::Kbps212,
+            preamble: FelicaPreamble::Bits48,
+        }
+    }
+}
+
+/// An ST25 chip enabled in FeliCa mode.
+pub struct Felica<'d, I: Interface, IrqPin: InputPin + Wait> {
+    inner: &'d mut St25r39<I, IrqPin>,
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Turn on the field in FeliCa mode.
+    ///
+    /// Manchester coding, preamble, sync code and CRC are handled by the chip.
+    pub async fn start_felica(&mut self, config: FelicaConfig) -> Result<Felica<'_, I, IrqPin>, FieldOnError<I::Error>> {
+        if !self.capabilities().am_modulation {
+            return Err(FieldOnError::Unsupported);
+        }
+
+        self.mode_on().await?;
+        self.felica_setup(&config)?;
+        let bit_rate = match config.bit_rate {
+            FelicaBitRate::Kbps212 => BitRate::Kbps212,
+            FelicaBitRate::Kbps424 => BitRate::Kbps424,
+        };
+        match self.field_on(Technology::NfcF, bit_rate).await {
+            Ok(()) => {}
+            Err(e) => {
+                self.mode_off()?;
+                return Err(e);
+            }
+        }
+
+        // Field on guard time. NFC Forum requires at least 20ms for NFC-F.
+        Timer::after(Duration::from_millis(20)).await;
+
+        Ok(Felica { inner: self })
+    }
+
+    fn felica_setup(&mut self, config: &FelicaConfig) -> Result<(), crate::Error<I::Error>> {
+        self.regs().mode().write(|w| {
+            w.set_om(regs::ModeOm::INI_FELICA);
+        })?;
+        self.regs().aux().write(|_| {})?;
+        let rate = match config.bit_rate {
+            FelicaBitRate::Kbps212 => regs::BitRateE::_212,
+            FelicaBitRate::Kbps424 => regs::BitRateE::_424,
+        };
+        self.regs().bit_rate().write(|w| {
+            w.set_rxrate(rate);
+            w.set_txrate(rate);
+        })?;
+        self.regs().iso14443b_2().write(|w| {
+            w.set_f_p(match config.preamble {
+                FelicaPreamble::Bits48 => regs::Iso14443b2FP::_48,
+                FelicaPreamble::Bits64 => regs::Iso14443b2FP::_64,
+                FelicaPreamble::Bits80 => regs::Iso14443b2FP::_80,
+                FelicaPreamble::Bits96 => regs::Iso14443b2FP::_96,
+            });
+        })?;
+        // Ignore the receiver until the minimum FDT of 2672/fc, minus the 64/fc timer granularity.
+        self.set_mask_rx_timer(((2672 - 64) / 64) as u8)?;
+        Ok(())
+    }
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> Drop for Felica<'_, I, IrqPin> {
+    fn drop(&mut self) {
+        if self.inner.mode_off().is_err() {
+            warn!("Failed to set field off on Felica drop");
+        }
+    }
+}
+
+/// Check the length byte of a received frame.
+fn check_len<T>(frame: &[u8]) -> Result<(), Error<T>> {
+    match frame.first() {
+        None => Err(Error::ResponseTooShort),
+        Some(&len) if len as usize != frame.len() => {
+            debug!("bad length byte: {} got {}", len, frame.len());
+            Err(Error::Framing)
+        }
+        Some(_) => Ok(()),
+    }
+}
+
+impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Felica<'d, I, IrqPin> {
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        if tx.len() >= MAX_FRAME_LEN {
+            return Err(Error::FifoOverflow);
+        }
+
+        let mut tx_buf = [0; MAX_FRAME_LEN];
+        tx_buf[0] = tx.len() as u8 + 1;
+        tx_buf[1..][..tx.len()].copy_from_slice(tx);
+        let mut rx_buf = [0; MAX_FRAME_LEN];
+
+        Timer::after(Duration::from_millis(1)).await;
+        let n = self
+            .inner
+            .transceive_framed(&tx_buf[..tx.len() + 1], &mut rx_buf, timeout_1fc)
+            .await?;
+        check_len(&rx_buf[..n])?;
+
+        let n = n - 1;
+        if rx.len() < n {
+            return Err(Error::ResponseTooLong);
+        }
+        rx[..n].copy_from_slice(&rx_buf[1..][..n]);
+        Ok(n)
+    }
+
+    async fn sensf_req(
+        &mut self,
+        system_code: u16,
+        request_code: u8,
+        slots: TimeSlots,
+        res: &mut [SensfRes],
+    ) -> Result<usize, Self::Error> {
+        let this = &mut *self.inner;
+
+        let sc = system_code.to_be_bytes();
+        let tx = [6, 0x00, sc[0], sc[1], request_code, slots as u8];
+
+        Timer::after(Duration::from_millis(1)).await;
+        this.framed_tx(&tx).await?;
+
+        // Responses start 512*64/fc after the request, each slot is 256*64/fc long.
+        let window_64fc = 512 + 256 * slots.count() as u64;
+        let deadline = Instant::now() + Duration::from_micros(window_64fc * 64 * 1_000_000 / 13_560_000 + 1);
+
+        let mut count = 0;
+        while count < res.len() {
+            let mut buf = [0; 20];
+            match this.framed_rx(&mut buf, deadline).await {
+                Ok(n) => match check_len::<I::Error>(&buf[..n])
+                    .ok()
+                    .and_then(|_| SensfRes::parse(&buf[1..n]))
+                {
+                    Some(r) => {
+                        debug!("SENSF_RES: nfcid2={:02x}", Bytes(&r.nfcid2));
+                        if !res[..count].iter().any(|x| x.nfcid2 == r.nfcid2) {
+                            res[count] = r;
+                            count += 1;
+                        }
+                    }
+                    None => debug!("bad SENSF_RES: {:02x}", Bytes(&buf[..n])),
+                },
+                Err(Error::Timeout) => break,
+                Err(Error::Interface(e)) => return Err(Error::Interface(e)),
+                // Probably two cards answering in the same slot, keep listening.
+                Err(_) => debug!("SENSF_RES rx error"),
+            }
+
+            if Instant::now() > deadline {
+                break;
+            }
+            this.framed_rx_restart()?;
+        }
+
+        Ok(count)
+    }
+}
@@ -1,6 +1,7 @@
 use embedded_hal::i2c::I2c;
 
-use super::Interface;
+use super::{fifo_read_cmd, Interface};
+use crate::ChipVariant;
 
 pub struct I2cInterface<T>
 where
@@ -8,6 +9,7 @@ where
 {
     i2c: T,
     address: u8,
+    fifo_read_cmd: u8,
 }
 
 impl<T> I2cInterface<T>
@@ -15,7 +17,11 @@ where
     T: I2c,
 {
     pub fn new(i2c: T, address: u8) -> Self {
-        Self { i2c, address }
+        Self {
+            i2c,
+            address,
+            fifo_read_cmd: fifo_read_cmd(ChipVariant::St25r3916),
+        }
     }
 }
 
@@ -64,7 +70,7 @@ where
     }
 
     fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
-        self.i2c.write_read(self.address, &[0x9F], data)
+        self.i2c.write_read(self.address, &[self.fifo_read_cmd], data)
     }
 
     fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
@@ -73,4 +79,8 @@ where
         buf[1..][..data.len()].copy_from_slice(data);
         self.i2c.write(self.address, &buf[..1 + data.len()])
     }
+
+    fn set_chip_variant(&mut self, variant: ChipVariant) {
+        self.fifo_read_cmd = fifo_read_cmd(variant);
+    }
 }
@@ -6,6 +6,8 @@ use core::fmt::Debug;
 pub use i2c::I2cInterface;
 pub use spi::SpiInterface;
 
+use crate::ChipVariant;
+
 pub trait Interface {
     type Error: Debug;
 
@@ -14,4 +16,20 @@ pub trait Interface {
     fn write_reg(&mut self, reg: u8, val: u8) -> Result<(), Self::Error>;
     fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error>;
     fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Called by [`St25r39::new`](crate::St25r39::new) once the chip variant is known.
+    ///
+    /// The ST25R391x reads the FIFO with a different command, interfaces that can talk to
+    /// one must switch to it. The default does nothing.
+    fn set_chip_variant(&mut self, variant: ChipVariant) {
+        let _ = variant;
+    }
+}
+
+/// FIFO read command for `variant`.
+fn fifo_read_cmd(variant: ChipVariant) -> u8 {
+    match variant {
+        ChipVariant::St25r391x => 0xBF,
+        ChipVariant::St25r3916 => 0x9F,
+    }
 }
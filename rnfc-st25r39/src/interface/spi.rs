@@ -1,14 +1,19 @@
 use embedded_hal::spi::{Operation, SpiDevice};
 
-use super::Interface;
+use super::{fifo_read_cmd, Interface};
+use crate::ChipVariant;
 
 pub struct SpiInterface<T: SpiDevice> {
     spi: T,
+    fifo_read_cmd: u8,
 }
 
 impl<T: SpiDevice> SpiInterface<T> {
     pub fn new(spi: T) -> Self {
-        Self { spi }
+        Self {
+            spi,
+            fifo_read_cmd: fifo_read_cmd(ChipVariant::St25r3916),
+        }
     }
 }
 
@@ -73,10 +78,15 @@ impl<T: SpiDevice> Interface for SpiInterface<T> {
     }
 
     fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
-        self.spi.transaction(&mut [Operation::Write(&[0x9f]), Operation::Read(data)])
+        self.spi
+            .transaction(&mut [Operation::Write(&[self.fifo_read_cmd]), Operation::Read(data)])
     }
 
     fn write_fifo(&mut self, data: &[u8]) -> Result<(), Self::Error> {
         self.spi.transaction(&mut [Operation::Write(&[0x80]), Operation::Write(data)])
     }
+
+    fn set_chip_variant(&mut self, variant: ChipVariant) {
+        self.fifo_read_cmd = fifo_read_cmd(variant);
+    }
 }
@@ -3,6 +3,7 @@ use core::fmt::Debug;
 use embassy_time::{with_timeout, Timer};
 use rnfc_traits::iso14443a_ll as ll;
 
+use crate::analog_config::{BitRate, Technology};
 use crate::fmt::Bytes;
 use crate::*;
 
@@ -68,7 +69,8 @@ pub struct Iso14443a<'d, I: Interface, IrqPin: InputPin + Wait> {
 impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
     pub async fn start_iso14443a(&mut self) -> Result<Iso14443a<'_, I, IrqPin>, FieldOnError<I::Error>> {
         self.mode_on().await?;
-        match self.field_on().await {
+        self.iso14443a_setup()?;
+        match self.field_on(Technology::NfcA, BitRate::Kbps106).await {
             Ok(()) => {}
             Err(e) => {
                 self.mode_off()?;
@@ -81,6 +83,27 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
 
         Ok(Iso14443a { inner: self })
     }
+
+    /// Configure mode and framing for ISO14443A at 106kbps.
+    /// Analog settings come from the analog config tables, applied by `field_on`.
+    pub(crate) fn iso14443a_setup(&mut self) -> Result<(), crate::Error<I::Error>> {
+        self.regs().mode().write(|w| {
+            w.set_om(regs::ModeOm::INI_ISO14443A);
+        })?;
+        self.regs().aux().write(|w| {
+            w.set_nfc_n(0); // todo this changes
+        })?;
+        self.regs().bit_rate().write(|w| {
+            w.set_rxrate(regs::BitRateE::_106);
+            w.set_txrate(regs::BitRateE::_106);
+        })?;
+
+        // defaults
+        self.regs().iso14443a_nfc().write(|_| {})?;
+        // The PICC answers REQA/WUPA after 1172/fc, don't mask any of it.
+        self.set_mask_rx_timer(0)?;
+        Ok(())
+    }
 }
 
 impl<'d, I: Interface, IrqPin: InputPin + Wait> Drop for Iso14443a<'d, I, IrqPin> {
@@ -106,6 +129,10 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
         let mut fwt_ms = 5;
         let is_anticoll = matches!(opts, ll::Frame::Anticoll { .. });
 
+        if tx.len() > this.capabilities().fifo_size {
+            return Err(Error::FifoOverflow);
+        }
+
         let (raw, cmd) = match opts {
             ll::Frame::ReqA => (true, Command::TransmitReqa),
             ll::Frame::WupA => (true, Command::TransmitWupa),
@@ -124,24 +151,28 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
                 (false, Command::TransmitWithCrc)
             }
         };
-        this.regs().corr_conf1().write(|w| {
-            w.0 = 0x13;
-            w.set_corr_s6(!is_anticoll);
-        })?;
+        // Only touch the bits that differ for anticollision, the rest comes from the analog config.
+        // The ST25R391x has neither the correlator register nor the same receiver layout,
+        // it stays as configured.
+        let st25r3916 = this.chip.variant == ChipVariant::St25r3916;
+        if st25r3916 {
+            this.regs().corr_conf1().modify(|w| {
+                w.set_corr_s6(!is_anticoll);
+            })?;
+        }
 
         this.regs().iso14443a_nfc().write(|w| {
             w.set_antcl(is_anticoll);
         })?;
-        this.regs().aux().write(|w| {
+        this.regs().aux().modify(|w| {
             w.set_no_crc_rx(raw);
         })?;
-        this.regs().rx_conf2().write(|w| {
-            // Disable Automatic Gain Control (AGC) for better detection of collisions if using Coherent Receiver
-            w.set_agc_en(!is_anticoll);
-            w.set_agc_m(true); // AGC operates during complete receive period
-            w.set_agc6_3(true); // 0: AGC ratio 3
-            w.set_sqm_dyn(true); // Automatic squelch activation after end of TX
-        })?;
+        if st25r3916 {
+            this.regs().rx_conf2().modify(|w| {
+                // Disable Automatic Gain Control (AGC) for better detection of collisions if using Coherent Receiver
+                w.set_agc_en(!is_anticoll);
+            })?;
+        }
 
         this.irqs = 0; // stop already clears all irqs
         this.cmd(cmd)?;
@@ -188,6 +219,7 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
         }
 
         // If we're here, RX ended without error.
+        this.update_rssi()?;
 
         let stat = this.regs().fifo_status2().read()?;
         if stat.fifo_ovr() {
@@ -200,8 +232,7 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
             return Err(Error::FramingLastByteMissingParity);
         }
 
-        let mut rx_bytes = this.regs().fifo_status1().read()? as usize;
-        rx_bytes |= (stat.fifo_b() as usize) << 8;
+        let mut rx_bytes = this.fifo_rx_bytes(stat)?;
 
         if let ll::Frame::Anticoll { bits } = opts {
             let full_bytes = bits / 8;
@@ -241,4 +272,9 @@ impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso1444
             Ok(rx_bytes * 8)
         }
     }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        Timer::after(Duration::from_micros(time_1fc as u64 * 1_000_000 / 13_560_000 + 1)).await;
+        Ok(())
+    }
 }
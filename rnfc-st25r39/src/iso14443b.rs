@@ -0,0 +1,129 @@
+use rnfc_traits::iso14443b_ll as ll;
+
+use crate::analog_config::{BitRate, Technology};
+pub use crate::iso14443a::Error;
+use crate::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Iso14443bConfig {
+    /// Extra guard time between characters, in etu. 0..=6
+    pub egt: u8,
+    /// Use an 11 etu SOF low phase instead of 10 etu.
+    pub sof_low_11etu: bool,
+    /// Use a 3 etu SOF high phase instead of 2 etu.
+    pub sof_high_3etu: bool,
+    /// Use an 11 etu EOF instead of 10 etu.
+    pub eof_11etu: bool,
+    /// Minimum TR0, in units of 64/fc. The receiver ignores the card for this long after TX.
+    pub tr0_64fc: u8,
+    /// Minimum TR1: `false` = 80/fs, `true` = 64/fs.
+    pub tr1_64fs: bool,
+}
+
+impl Default for Iso14443bConfig {
+    fn default() -> Self {
+        Self {
+            egt: 0,
+            sof_low_11etu: false,
+            sof_high_3etu: false,
+            eof_11etu: false,
+            tr0_64fc: 16, // 1024/fc
+            tr1_64fs: false,
+        }
+    }
+}
+
+/// An ST25 chip enabled in Iso14443b mode.
+pub struct Iso14443b<'d, I: Interface, IrqPin: InputPin + Wait> {
+    inner: &'d mut St25r39<I, IrqPin>,
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Turn on the field in ISO14443B mode at 106kbps.
+    ///
+    /// The modulation depth comes from the analog config tables.
+    pub async fn start_iso14443b(
+        &mut self,
+        config: Iso14443bConfig,
+    ) -> Result<Iso14443b<'_, I, IrqPin>, FieldOnError<I::Error>> {
+        if !self.capabilities().am_modulation {
+            return Err(FieldOnError::Unsupported);
+        }
+
+        self.mode_on().await?;
+        self.iso14443b_setup(&config)?;
+        match self.field_on(Technology::NfcB, BitRate::Kbps106).await {
+            Ok(()) => {}
+            Err(e) => {
+                self.mode_off()?;
+                return Err(e);
+            }
+        }
+
+        // Field on guard time
+        Timer::after(Duration::from_millis(5)).await;
+
+        Ok(Iso14443b { inner: self })
+    }
+
+    fn iso14443b_setup(&mut self, config: &Iso14443bConfig) -> Result<(), crate::Error<I::Error>> {
+        self.regs().mode().write(|w| {
+            w.set_om(regs::ModeOm::INI_ISO14443B);
+        })?;
+        self.regs().aux().write(|_| {})?;
+        self.regs().bit_rate().write(|w| {
+            w.set_rxrate(regs::BitRateE::_106);
+            w.set_txrate(regs::BitRateE::_106);
+        })?;
+        self.regs().iso14443b_1().write(|w| {
+            w.set_egt(config.egt.min(6));
+            w.set_sof_0_11etu(config.sof_low_11etu);
+            w.set_sof_1(match config.sof_high_3etu {
+                false => regs::Iso14443b1Sof1::_2ETU,
+                true => regs::Iso14443b1Sof1::_3ETU,
+            });
+            w.set_eof_11etu(config.eof_11etu);
+        })?;
+        self.regs().iso14443b_2().write(|w| {
+            w.set_tr1(match config.tr1_64fs {
+                false => regs::Iso14443b2Tr1::_80FS80FS,
+                true => regs::Iso14443b2Tr1::_64FS32FS,
+            });
+        })?;
+        self.set_mask_rx_timer(config.tr0_64fc)?;
+        Ok(())
+    }
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> Drop for Iso14443b<'_, I, IrqPin> {
+    fn drop(&mut self) {
+        if self.inner.mode_off().is_err() {
+            warn!("Failed to set field off on Iso14443b drop");
+        }
+    }
+}
+
+impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Iso14443b<'d, I, IrqPin> {
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        Timer::after(Duration::from_millis(1)).await;
+        self.inner.transceive_framed(tx, rx, timeout_1fc).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn mask_rx_timer_not_left_for_iso14443a() {
+        let mut st = crate::test::fake_st25r39(ChipVariant::St25r3916);
+        st.iso14443b_setup(&Iso14443bConfig::default()).unwrap();
+        assert_eq!(st.regs().mask_rx_timer().read().unwrap(), 16);
+
+        st.iso14443a_setup().unwrap();
+        assert_eq!(st.regs().mask_rx_timer().read().unwrap(), 0);
+    }
+}
@@ -6,17 +6,30 @@
 mod fmt;
 
 mod aat;
+pub mod analog_config;
+mod chip;
+mod diagnostics;
+mod ext_field;
+pub mod felica;
 mod interface;
 pub mod iso14443a;
+pub mod iso14443b;
+pub mod nfcv;
 mod regs;
 
-pub use aat::AatConfig;
+pub use aat::{AatConfig, AatError, AatResult, AatStrategy};
+use analog_config::{AnalogConfigEntry, BitRate, Direction, Technology};
+use chip::ChipIface;
+pub use chip::{Capabilities, ChipInfo, ChipVariant};
 use embassy_futures::yield_now;
-use embassy_time::{Duration, Instant};
+use embassy_time::{Duration, Instant, Timer};
 use embedded_hal::digital::InputPin;
 use embedded_hal_async::digital::Wait;
+pub use ext_field::{FieldThreshold, FieldThresholds};
 pub use interface::{I2cInterface, Interface, SpiInterface};
+use rnfc_traits::diagnostics::Rssi;
 
+use self::fmt::Bytes;
 use self::regs::Regs;
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(500);
@@ -239,6 +252,74 @@ pub enum FieldOnError<T> {
     FieldCollision,
     Interface(T),
     Timeout,
+    /// The chip variant can't do this technology, see [`Capabilities`].
+    Unsupported,
+}
+
+/// What to do when turning on the field fails because another device's field is detected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RfCollisionPolicy {
+    /// How many times to retry before returning [`FieldOnError::FieldCollision`]. 0 = don't retry.
+    pub retries: u8,
+    /// Delay before the first retry. It's doubled on each subsequent retry, up to
+    /// [`MAX_BACKOFF`](Self::MAX_BACKOFF).
+    pub backoff: Duration,
+}
+
+impl RfCollisionPolicy {
+    /// Longest delay between retries, unless `backoff` is already longer.
+    pub const MAX_BACKOFF: Duration = Duration::from_secs(1);
+}
+
+impl Default for RfCollisionPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::from_millis(5),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum InitError<T> {
+    Interface(T),
+    Timeout,
+    /// The chip is not one this driver supports. `ic_type` and `ic_rev` are
+    /// the raw fields of the `ic_identity` register.
+    UnsupportedChip {
+        ic_type: u8,
+        ic_rev: u8,
+    },
+}
+
+impl<T> From<Error<T>> for InitError<T> {
+    fn from(val: Error<T>) -> Self {
+        match val {
+            Error::Interface(e) => InitError::Interface(e),
+            Error::Timeout => InitError::Timeout,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WakeupError<T> {
+    Interface(T),
+    Timeout,
+    /// Capacitive wake-up was requested, but the chip variant doesn't have it.
+    /// See [`Capabilities::capacitive_wakeup`].
+    CapacitiveUnsupported,
+}
+
+impl<T> From<Error<T>> for WakeupError<T> {
+    fn from(val: Error<T>) -> Self {
+        match val {
+            Error::Interface(e) => WakeupError::Interface(e),
+            Error::Timeout => WakeupError::Timeout,
+        }
+    }
 }
 
 impl<T> From<Error<T>> for FieldOnError<T> {
@@ -258,25 +339,62 @@ enum Mode {
 }
 
 pub struct St25r39<I: Interface, IrqPin: InputPin + Wait> {
-    iface: I,
+    iface: ChipIface<I>,
     irq: IrqPin,
     irqs: u32,
     mode: Mode,
+    analog_config: &'static [AnalogConfigEntry],
+    rf_collision_policy: RfCollisionPolicy,
+    last_rssi: Option<Rssi>,
+    chip: ChipInfo,
 }
 
 impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
-    pub async fn new(iface: I, irq: IrqPin) -> Result<Self, Error<I::Error>> {
+    pub async fn new(mut iface: I, irq: IrqPin) -> Result<Self, InitError<I::Error>> {
+        let id = Regs::new(&mut iface).ic_identity().read()?;
+        debug!("ic_type = {:02x} ic_rev = {:02x}", id.ic_type().0, id.ic_rev().0);
+        let chip = match ChipVariant::from_ic_type(id.ic_type()) {
+            Some(variant) => ChipInfo {
+                variant,
+                revision: id.ic_rev().0,
+            },
+            _ => {
+                return Err(InitError::UnsupportedChip {
+                    ic_type: id.ic_type().0,
+                    ic_rev: id.ic_rev().0,
+                })
+            }
+        };
+        iface.set_chip_variant(chip.variant);
+
         let mut this = Self {
-            iface,
+            iface: ChipIface {
+                inner: iface,
+                variant: chip.variant,
+            },
             irq,
             irqs: 0,
             mode: Mode::On,
+            analog_config: &[],
+            rf_collision_policy: RfCollisionPolicy::default(),
+            last_rssi: None,
+            chip,
         };
         this.init().await?;
         Ok(this)
     }
 
-    fn regs(&mut self) -> Regs<I> {
+    /// Detected chip variant and revision.
+    pub fn chip_info(&self) -> ChipInfo {
+        self.chip
+    }
+
+    /// Features supported by the detected chip variant.
+    pub fn capabilities(&self) -> Capabilities {
+        self.chip.variant.capabilities()
+    }
+
+    fn regs(&mut self) -> Regs<'_, ChipIface<I>> {
         Regs::new(&mut self.iface)
     }
 
@@ -300,12 +418,11 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
     async fn init(&mut self) -> Result<(), Error<I::Error>> {
         self.cmd(Command::SetDefault)?;
 
-        self.regs().test_unk().write(|w| {
-            w.set_dis_overheat_prot(true);
-        })?;
-
-        let id = self.regs().ic_identity().read()?;
-        trace!("ic_type = {:02x} ic_rev = {:02x}", id.ic_type().0, id.ic_rev().0);
+        if self.chip.variant == ChipVariant::St25r3916 {
+            self.regs().test_unk().write(|w| {
+                w.set_dis_overheat_prot(true);
+            })?;
+        }
 
         // Enable OSC
         self.enable_osc().await?;
@@ -326,24 +443,19 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
             w.set_sup_3v(sup3v);
         })?;
 
-        // Disable MCU_CLK
-        self.regs().io_conf1().write(|w| {
-            w.set_out_cl(regs::IoConf1OutCl::DISABLED);
-            w.set_lf_clk_off(true);
-        })?;
+        // Disable MCU_CLK. The ST25R391x has these bits elsewhere, it keeps the reset value.
+        if self.chip.variant == ChipVariant::St25r3916 {
+            self.regs().io_conf1().write(|w| {
+                w.set_out_cl(regs::IoConf1OutCl::DISABLED);
+                w.set_lf_clk_off(true);
+            })?;
+        }
 
         // Enable minimum non-overlap
         //self.regs().res_am_mod().write(|w| w.set_fa3_f(true))?;
 
         // Set ext field detect activ/deactiv thresholds
-        //self.regs().field_threshold_actv().write(|w| {
-        //    w.set_trg(regs::FieldThresholdActvTrg::_105MV);
-        //    w.set_rfe(regs::FieldThresholdActvRfe::_105MV);
-        //})?;
-        //self.regs().field_threshold_deactv().write(|w| {
-        //    w.set_trg(regs::FieldThresholdDeactvTrg::_75MV);
-        //    w.set_rfe(regs::FieldThresholdDeactvRfe::_75MV);
-        //})?;
+        self.set_field_thresholds(FieldThresholds::default())?;
 
         //self.regs().aux_mod().write(|w| {
         //    w.set_lm_ext(false); // Disable external Load Modulation
@@ -358,8 +470,9 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         //self.regs().ant_tune_a().write_value(0x82)?;
         //self.regs().ant_tune_b().write_value(0x82)?;
 
+        let efd = self.chip.variant.auto_efd();
         self.regs().op_control().modify(|w| {
-            w.set_en_fd(regs::OpControlEnFd::AUTO_EFD);
+            w.set_en_fd(efd);
         })?;
 
         // Adjust regulators
@@ -384,6 +497,15 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         Ok(())
     }
 
+    /// Set how long the receiver ignores the card after TX, in steps of 64/fc.
+    /// Every technology's setup programs this, so a session doesn't inherit the mask of the previous one.
+    pub(crate) fn set_mask_rx_timer(&mut self, steps_64fc: u8) -> Result<(), Error<I::Error>> {
+        self.regs()
+            .timer_emv_control()
+            .modify(|w| w.set_mrt_step(regs::TimerEmvControlMrtStep::_64))?;
+        self.regs().mask_rx_timer().write_value(steps_64fc)
+    }
+
     pub async fn measure_amplitude(&mut self) -> Result<u8, Error<I::Error>> {
         self.cmd_wait(Command::MeasureAmplitude).await?;
         self.regs().ad_result().read()
@@ -434,18 +556,30 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         self.mode = Mode::On;
         self.enable_osc().await?;
 
+        let efd = self.chip.variant.auto_efd();
         self.regs().op_control().modify(|w| {
-            w.set_en_fd(regs::OpControlEnFd::AUTO_EFD);
-        })?;
-        self.regs().tx_driver().write(|w| {
-            w.set_d_res(3);
+            w.set_en_fd(efd);
         })?;
+        match self.chip.variant {
+            ChipVariant::St25r391x => self
+                .iface
+                .inner
+                .write_reg(chip::ST25R391X_RFO_NORMAL_LEVEL, 0x00)
+                .map_err(Error::Interface)?,
+            _ => self.regs().tx_driver().write(|w| {
+                w.set_d_res(3);
+            })?,
+        }
         Ok(())
     }
 
     /// Change into wakeup mode, return immediately.
     /// The IRQ pin will go high on wakeup.
-    pub async fn wait_for_card(&mut self, config: WakeupConfig) -> Result<(), Error<I::Error>> {
+    pub async fn wait_for_card(&mut self, config: WakeupConfig) -> Result<(), WakeupError<I::Error>> {
+        if config.capacitive.is_some() && !self.capabilities().capacitive_wakeup {
+            return Err(WakeupError::CapacitiveUnsupported);
+        }
+
         self.mode_on().await?;
 
         self.mode = Mode::Wakeup;
@@ -559,64 +693,50 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         Ok(())
     }
 
-    async fn field_on(&mut self) -> Result<(), FieldOnError<I::Error>> {
-        self.regs().mode().write(|w| {
-            w.set_om(regs::ModeOm::INI_ISO14443A);
-            w.set_tr_am(false); // use OOK
-        })?;
-        self.regs().tx_driver().write(|w| {
-            w.set_am_mod(regs::TxDriverAmMod::_12PERCENT);
-        })?;
-        self.regs().aux_mod().write(|w| {
-            w.set_lm_dri(true); // Enable internal Load Modulation
-            w.set_dis_reg_am(false); // Enable regulator-based AM
-            w.set_res_am(false);
-        })?;
-
-        // Default over/under shoot protiection
-        self.regs().overshoot_conf1().write_value(0x40.into())?;
-        self.regs().overshoot_conf2().write_value(0x03.into())?;
-        self.regs().undershoot_conf1().write_value(0x40.into())?;
-        self.regs().undershoot_conf2().write_value(0x03.into())?;
+    /// Turn on the field, after the caller has configured the mode and bit rate registers
+    /// for `technology`.
+    async fn field_on(&mut self, technology: Technology, bit_rate: BitRate) -> Result<(), FieldOnError<I::Error>> {
+        self.apply_analog_config(Direction::Poll, technology, bit_rate)?;
 
-        self.regs().aux().write(|w| {
-            w.set_dis_corr(false); // Enable correlator reception
-            w.set_nfc_n(0); // todo this changes
-        })?;
-        /*
-        self.regs().rx_conf1().write_value(0x08.into())?;
-        self.regs().rx_conf2().write_value(0x2D.into())?;
-        self.regs().rx_conf3().write_value(0x00.into())?;
-        self.regs().rx_conf4().write_value(0x00.into())?;
-        self.regs().corr_conf1().write_value(0x51.into())?;
-        self.regs().corr_conf2().write_value(0x00.into())?;
-         */
-
-        self.regs().bit_rate().write(|w| {
-            w.set_rxrate(regs::BitRateE::_106);
-            w.set_txrate(regs::BitRateE::_106);
-        })?;
+        // Field ON
 
-        // defaults
-        self.regs().iso14443a_nfc().write(|_| {})?;
+        // GT is done by software. The ST25R391x has no field on guard timer.
+        if self.chip.variant == ChipVariant::St25r3916 {
+            self.regs().field_on_gt().write_value(0)?;
+        }
+        // The ST25R391x signals the end of the guard time instead of the field on.
+        let field_on_irq = match self.chip.variant {
+            ChipVariant::St25r391x => Interrupt::Cat,
+            _ => Interrupt::Apon,
+        };
 
-        // Field ON
+        let policy = self.rf_collision_policy;
+        let mut backoff = policy.backoff;
+        let mut tries = 0;
+        'out: loop {
+            self.irq_clear()?; // clear
+            self.cmd(Command::InitialRfCollision)?;
 
-        // GT is done by software
-        self.regs().field_on_gt().write_value(0)?;
+            loop {
+                if self.irq(Interrupt::Cac) {
+                    break;
+                }
+                if self.irq(field_on_irq) {
+                    break 'out;
+                }
 
-        self.irq_clear()?; // clear
-        self.cmd(Command::InitialRfCollision)?;
+                self.irq_update()?;
+            }
 
-        loop {
-            if self.irq(Interrupt::Cac) {
+            if tries == policy.retries {
                 return Err(FieldOnError::FieldCollision);
             }
-            if self.irq(Interrupt::Apon) {
-                break;
-            }
+            tries += 1;
 
-            self.irq_update()?;
+            debug!("field collision, retrying in {} ms", backoff.as_millis());
+            Timer::after(backoff).await;
+            let max_backoff = policy.backoff.max(RfCollisionPolicy::MAX_BACKOFF);
+            backoff = backoff.checked_mul(2).unwrap_or(max_backoff).min(max_backoff);
         }
 
         self.regs().op_control().modify(|w| {
@@ -627,6 +747,133 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
         Ok(())
     }
 
+    /// Transmit a byte-oriented frame with CRC, and receive the response.
+    ///
+    /// Framing and CRC flavor come from the currently configured mode. The received
+    /// CRC is checked by the chip and stripped. Returns the response length in bytes.
+    pub(crate) async fn transceive_framed(
+        &mut self,
+        tx: &[u8],
+        rx: &mut [u8],
+        timeout_1fc: u32,
+    ) -> Result<usize, iso14443a::Error<I::Error>> {
+        self.framed_tx(tx).await?;
+
+        let fwt_ms = timeout_1fc / 13560 + 1;
+        self.framed_rx(rx, Instant::now() + Duration::from_millis(fwt_ms as _)).await
+    }
+
+    /// Transmit a byte-oriented frame with CRC, and wait for TX to end.
+    pub(crate) async fn framed_tx(&mut self, tx: &[u8]) -> Result<(), iso14443a::Error<I::Error>> {
+        debug!("TX: {:02x}", Bytes(tx));
+
+        if tx.len() > self.capabilities().fifo_size {
+            return Err(iso14443a::Error::FifoOverflow);
+        }
+
+        self.cmd(Command::Stop)?;
+        self.cmd(Command::ResetRxgain)?;
+
+        let bits = tx.len() * 8;
+        self.regs().num_tx_bytes2().write_value((bits as u8).into())?;
+        self.regs().num_tx_bytes1().write_value((bits >> 8) as u8)?;
+        self.iface.write_fifo(tx).map_err(iso14443a::Error::Interface)?;
+        self.regs().aux().modify(|w| w.set_no_crc_rx(false))?;
+
+        self.irqs = 0; // stop already clears all irqs
+        self.cmd(Command::TransmitWithCrc)?;
+
+        // Wait for tx ended
+        self.irq_wait(Interrupt::Txe).await?;
+        Ok(())
+    }
+
+    /// Receive a byte-oriented frame, if one starts before `rx_start_deadline`.
+    pub(crate) async fn framed_rx(
+        &mut self,
+        rx: &mut [u8],
+        rx_start_deadline: Instant,
+    ) -> Result<usize, iso14443a::Error<I::Error>> {
+        use iso14443a::Error as E;
+
+        // Wait for RX started
+        let timeout = rx_start_deadline.saturating_duration_since(Instant::now());
+        self.irq_wait_timeout(Interrupt::Rxs, timeout).await?;
+
+        // Wait for rx ended or error
+        // The timeout should never hit, it's just for safety.
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        loop {
+            if self.irq(Interrupt::Err1) {
+                return Err(E::Framing);
+            }
+            if self.irq(Interrupt::Crc) {
+                return Err(E::Crc);
+            }
+            if self.irq(Interrupt::Col) {
+                return Err(E::Collision);
+            }
+            if self.irq(Interrupt::Rxe) {
+                break;
+            }
+            if Instant::now() > deadline {
+                return Err(E::Timeout);
+            }
+
+            yield_now().await;
+            self.irq_update()?;
+        }
+
+        // If we're here, RX ended without error.
+        self.update_rssi()?;
+
+        let stat = self.regs().fifo_status2().read()?;
+        if stat.fifo_ovr() {
+            return Err(E::FifoOverflow);
+        }
+        if stat.fifo_unf() {
+            return Err(E::FifoUnderflow);
+        }
+
+        let mut rx_bytes = self.fifo_rx_bytes(stat)?;
+
+        // Remove received CRC
+        if rx_bytes < 2 {
+            return Err(E::ResponseTooShort);
+        }
+        rx_bytes -= 2;
+
+        if rx.len() < rx_bytes {
+            return Err(E::ResponseTooLong);
+        }
+
+        self.iface.read_fifo(&mut rx[..rx_bytes]).map_err(E::Interface)?;
+        debug!("RX: {:02x}", Bytes(&rx[..rx_bytes]));
+        Ok(rx_bytes)
+    }
+
+    /// Number of bytes in the FIFO, given `fifo_status2` read after RX ended.
+    pub(crate) fn fifo_rx_bytes(&mut self, stat: regs::FifoStatus2) -> Result<usize, Error<I::Error>> {
+        let low = self.regs().fifo_status1().read()? as usize;
+        Ok(match self.chip.variant {
+            // 96 bytes fit in fifo_status1, the top bits of fifo_status2 are something else.
+            ChipVariant::St25r391x => low & 0x7F,
+            _ => low | (stat.fifo_b() as usize) << 8,
+        })
+    }
+
+    /// Get ready to receive another frame after [`Self::framed_rx`], without transmitting.
+    pub(crate) fn framed_rx_restart(&mut self) -> Result<(), Error<I::Error>> {
+        self.cmd(Command::ClearFifo)?;
+        self.irqs = 0;
+        self.cmd(Command::UnmaskReceiveData)
+    }
+
+    /// Set what to do when another device's field is detected while turning on ours.
+    pub fn set_rf_collision_policy(&mut self, policy: RfCollisionPolicy) {
+        self.rf_collision_policy = policy;
+    }
+
     async fn measure_vdd(&mut self) -> Result<u32, Error<I::Error>> {
         self.regs()
             .regulator_control()
@@ -663,7 +910,7 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
     }
 
     fn irq_update(&mut self) -> Result<(), Error<I::Error>> {
-        for i in 0..4 {
+        for i in 0..self.chip.variant.irq_regs() {
             self.irqs |= (self.regs().irq_main(i).read()? as u32) << (i * 8);
         }
         Ok(())
@@ -676,7 +923,7 @@ impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
     }
 
     fn irq_set_mask(&mut self, mask: u32) -> Result<(), Error<I::Error>> {
-        for i in 0..4 {
+        for i in 0..self.chip.variant.irq_regs() {
             self.regs().irq_mask(i).write_value((mask >> (i * 8)) as u8)?;
         }
         Ok(())
@@ -694,7 +941,8 @@ pub struct Raw<'a, I: Interface, IrqPin: InputPin + Wait> {
 impl<'a, I: Interface, IrqPin: InputPin + Wait> Raw<'a, I, IrqPin> {
     pub async fn field_on(&mut self) -> Result<(), FieldOnError<I::Error>> {
         self.inner.mode_on().await?;
-        self.inner.field_on().await?;
+        self.inner.iso14443a_setup()?;
+        self.inner.field_on(Technology::NfcA, BitRate::Kbps106).await?;
         Ok(())
     }
     pub async fn field_off(&mut self) -> Result<(), Error<I::Error>> {
@@ -703,10 +951,103 @@ impl<'a, I: Interface, IrqPin: InputPin + Wait> Raw<'a, I, IrqPin> {
     }
     pub async fn driver_hi_z(&mut self) -> Result<(), Error<I::Error>> {
         self.inner.mode_off()?;
-        self.inner.regs().tx_driver().write(|w| {
-            w.set_d_res(15); // hi-z
-        })?;
+        match self.inner.chip.variant {
+            ChipVariant::St25r391x => self
+                .inner
+                .iface
+                .inner
+                .write_reg(chip::ST25R391X_RFO_NORMAL_LEVEL, 0xFF) // all driver segments off
+                .map_err(Error::Interface)?,
+            _ => self.inner.regs().tx_driver().write(|w| {
+                w.set_d_res(15); // hi-z
+            })?,
+        }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod test {
+    use core::convert::Infallible;
+
+    use super::*;
+
+    /// A register file with no chip behind it. Commands and FIFO accesses are ignored.
+    pub(crate) struct FakeIface {
+        pub regs: [u8; 256],
+    }
+
+    impl Interface for FakeIface {
+        type Error = Infallible;
+
+        fn do_command(&mut self, _cmd: u8) -> Result<(), Self::Error> {
+            Ok(())
+        }
+        fn read_reg(&mut self, reg: u8) -> Result<u8, Self::Error> {
+            Ok(self.regs[reg as usize])
+        }
+        fn write_reg(&mut self, reg: u8, val: u8) -> Result<(), Self::Error> {
+            self.regs[reg as usize] = val;
+            Ok(())
+        }
+        fn read_fifo(&mut self, data: &mut [u8]) -> Result<(), Self::Error> {
+            data.fill(0);
+            Ok(())
+        }
+        fn write_fifo(&mut self, _data: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    /// An IRQ line that never fires.
+    pub(crate) struct FakeIrq;
+
+    impl embedded_hal::digital::ErrorType for FakeIrq {
+        type Error = Infallible;
+    }
+
+    impl InputPin for FakeIrq {
+        fn is_high(&mut self) -> Result<bool, Self::Error> {
+            Ok(false)
+        }
+        fn is_low(&mut self) -> Result<bool, Self::Error> {
+            Ok(true)
+        }
+    }
+
+    impl Wait for FakeIrq {
+        async fn wait_for_high(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+        async fn wait_for_low(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+        async fn wait_for_rising_edge(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+        async fn wait_for_falling_edge(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+        async fn wait_for_any_edge(&mut self) -> Result<(), Self::Error> {
+            core::future::pending().await
+        }
+    }
+
+    /// A driver over [`FakeIface`], skipping `new` since it waits on the chip.
+    pub(crate) fn fake_st25r39(variant: ChipVariant) -> St25r39<FakeIface, FakeIrq> {
+        St25r39 {
+            iface: ChipIface {
+                inner: FakeIface { regs: [0; 256] },
+                variant,
+            },
+            irq: FakeIrq,
+            irqs: 0,
+            mode: Mode::Off,
+            analog_config: &[],
+            rf_collision_policy: RfCollisionPolicy::default(),
+            last_rssi: None,
+            chip: ChipInfo { variant, revision: 0 },
+        }
+    }
+}
@@ -0,0 +1,459 @@
+//! ISO15693 / NFC-V.
+//!
+//! The chip has no NFC-V framing, so this uses the subcarrier stream mode and does
+//! the VCD coding and VICC decoding in software, like ST's RFAL.
+
+use rnfc_traits::nfcv_ll as ll;
+
+use crate::analog_config::{BitRate, Technology};
+use crate::fmt::Bytes;
+pub use crate::iso14443a::Error;
+use crate::*;
+
+/// Size of the buffer for the coded streams. Limits the max frame length.
+const STREAM_BUF_LEN: usize = 512;
+
+// VCD coding. Sent LSB first, one bit is 128/fc, a 1 bit is a pause.
+const SOF_1_OF_4: u8 = 0x21;
+const SOF_1_OF_256: u8 = 0x81;
+const EOF: u8 = 0x04;
+
+// VICC response. Received LSB first, one bit per half-bit period (8 subcarrier pulses),
+// a 1 bit means subcarrier present. The receiver starts at the first subcarrier
+// pulse, so the unmodulated part of the SOF is not there.
+const RESP_SOF: u32 = 0b10111;
+const RESP_SOF_LEN: usize = 5;
+
+/// VCD (reader to card) coding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NfcvCoding {
+    /// 1 out of 4, 26.48 kbit/s.
+    OneOf4,
+    /// 1 out of 256, 1.65 kbit/s. Only very short frames fit in the FIFO.
+    OneOf256,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct NfcvConfig {
+    pub coding: NfcvCoding,
+}
+
+impl Default for NfcvConfig {
+    fn default() -> Self {
+        Self {
+            coding: NfcvCoding::OneOf4,
+        }
+    }
+}
+
+/// An ST25 chip enabled in NFC-V mode.
+pub struct Nfcv<'d, I: Interface, IrqPin: InputPin + Wait> {
+    inner: &'d mut St25r39<I, IrqPin>,
+    coding: NfcvCoding,
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> St25r39<I, IrqPin> {
+    /// Turn on the field in NFC-V mode. The VICC response is always decoded at the high data rate, single subcarrier.
+    pub async fn start_nfcv(&mut self, config: NfcvConfig) -> Result<Nfcv<'_, I, IrqPin>, FieldOnError<I::Error>> {
+        self.mode_on().await?;
+        self.nfcv_setup()?;
+        match self.field_on(Technology::NfcV, BitRate::Kbps26).await {
+            Ok(()) => {}
+            Err(e) => {
+                self.mode_off()?;
+                return Err(e);
+            }
+        }
+
+        // Field on guard time
+        Timer::after(Duration::from_millis(5)).await;
+
+        Ok(Nfcv {
+            inner: self,
+            coding: config.coding,
+        })
+    }
+
+    fn nfcv_setup(&mut self) -> Result<(), crate::Error<I::Error>> {
+        self.regs().mode().write(|w| {
+            w.set_om(regs::ModeOm::INI_SUBCARRIER_STREAM);
+        })?;
+        self.regs().aux().write(|w| {
+            w.set_no_crc_rx(true);
+        })?;
+        self.regs().stream_mode().write(|w| {
+            w.set_scf(regs::StreamModeScf::SC424);
+            w.set_scp(regs::StreamModeScp::_8PULSES);
+            w.set_stx(regs::StreamModeStx::_106);
+        })?;
+
+        // Ignore the receiver until t1min = 4192/fc
+        self.set_mask_rx_timer((4192 / 64) as u8)?;
+        Ok(())
+    }
+
+    /// Transmit `buf[..tx_len]` in stream mode, then receive the response stream into `buf`.
+    /// Returns the number of received bits.
+    async fn stream_transceive(
+        &mut self,
+        buf: &mut [u8; STREAM_BUF_LEN],
+        tx_len: usize,
+        timeout_1fc: u32,
+    ) -> Result<usize, Error<I::Error>> {
+        if tx_len > self.capabilities().fifo_size {
+            return Err(Error::FifoOverflow);
+        }
+
+        self.cmd(Command::Stop)?;
+        self.cmd(Command::ResetRxgain)?;
+
+        let bits = tx_len * 8;
+        self.regs().num_tx_bytes2().write_value((bits as u8).into())?;
+        self.regs().num_tx_bytes1().write_value((bits >> 8) as u8)?;
+        self.iface.write_fifo(&buf[..tx_len]).map_err(Error::Interface)?;
+
+        self.irqs = 0; // stop already clears all irqs
+        self.cmd(Command::TransmitWithoutCrc)?;
+
+        // Wait for tx ended
+        self.irq_wait(Interrupt::Txe).await?;
+
+        // Wait for RX started
+        let fwt_ms = timeout_1fc / 13560 + 1;
+        self.irq_wait_timeout(Interrupt::Rxs, Duration::from_millis(fwt_ms as _))
+            .await?;
+
+        // Wait for rx ended or error
+        // The timeout should never hit, it's just for safety.
+        let deadline = Instant::now() + DEFAULT_TIMEOUT;
+        loop {
+            if self.irq(Interrupt::Err1) {
+                return Err(Error::Framing);
+            }
+            if self.irq(Interrupt::Rxe) {
+                break;
+            }
+            if Instant::now() > deadline {
+                return Err(Error::Timeout);
+            }
+
+            yield_now().await;
+            self.irq_update()?;
+        }
+
+        self.update_rssi()?;
+
+        let stat = self.regs().fifo_status2().read()?;
+        if stat.fifo_ovr() {
+            return Err(Error::FifoOverflow);
+        }
+        if stat.fifo_unf() {
+            return Err(Error::FifoUnderflow);
+        }
+
+        let rx_bytes = self.fifo_rx_bytes(stat)?;
+        if rx_bytes > buf.len() {
+            return Err(Error::FifoOverflow);
+        }
+        self.iface.read_fifo(&mut buf[..rx_bytes]).map_err(Error::Interface)?;
+
+        // fifo_lb is the number of valid bits in the last byte, if it's incomplete.
+        let rx_bits = match stat.fifo_lb() {
+            0 => rx_bytes * 8,
+            lb => (rx_bytes.max(1) - 1) * 8 + lb as usize,
+        };
+        trace!("RX stream: {:02x} bits: {}", Bytes(&buf[..rx_bytes]), rx_bits);
+        Ok(rx_bits)
+    }
+}
+
+impl<I: Interface, IrqPin: InputPin + Wait> Drop for Nfcv<'_, I, IrqPin> {
+    fn drop(&mut self) {
+        if self.inner.mode_off().is_err() {
+            warn!("Failed to set field off on Nfcv drop");
+        }
+    }
+}
+
+impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> Nfcv<'d, I, IrqPin> {
+    /// Receive and decode a response, after `buf[..tx_len]` has been filled with the coded request.
+    async fn transceive_coded(
+        &mut self,
+        buf: &mut [u8; STREAM_BUF_LEN],
+        tx_len: usize,
+        rx: &mut [u8],
+        timeout_1fc: u32,
+    ) -> Result<usize, Error<I::Error>> {
+        Timer::after(Duration::from_millis(1)).await;
+        let rx_bits = self.inner.stream_transceive(buf, tx_len, timeout_1fc).await?;
+
+        let mut frame = [0; STREAM_BUF_LEN / 2];
+        let n = decode(&buf[..], rx_bits, &mut frame)?;
+        if n < 2 {
+            return Err(Error::ResponseTooShort);
+        }
+        if crc(&frame[..n - 2]) != u16::from_le_bytes([frame[n - 2], frame[n - 1]]) {
+            return Err(Error::Crc);
+        }
+
+        let n = n - 2;
+        if rx.len() < n {
+            return Err(Error::ResponseTooLong);
+        }
+        rx[..n].copy_from_slice(&frame[..n]);
+        debug!("RX: {:02x}", Bytes(&rx[..n]));
+        Ok(n)
+    }
+}
+
+impl<'d, I: Interface + 'd, IrqPin: InputPin + Wait + 'd> ll::Reader for Nfcv<'d, I, IrqPin> {
+    type Error = Error<I::Error>;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        debug!("TX: {:02x}", Bytes(tx));
+
+        let mut buf = [0; STREAM_BUF_LEN];
+        let tx_len = encode(self.coding, tx, &mut buf).ok_or(Error::FifoOverflow)?;
+        self.transceive_coded(&mut buf, tx_len, rx, timeout_1fc).await
+    }
+
+    async fn next_slot(&mut self, rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        debug!("TX: EOF");
+
+        let mut buf = [0; STREAM_BUF_LEN];
+        buf[0] = EOF;
+        self.transceive_coded(&mut buf, 1, rx, timeout_1fc).await
+    }
+}
+
+/// CRC as defined in ISO15693-3, sent LSB first.
+fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            if crc & 1 != 0 {
+                crc = (crc >> 1) ^ 0x8408;
+            } else {
+                crc >>= 1;
+            }
+        }
+    }
+    !crc
+}
+
+/// Code a request with its CRC, SOF and EOF. Returns the coded length, or `None` if it doesn't fit.
+fn encode(coding: NfcvCoding, data: &[u8], out: &mut [u8]) -> Option<usize> {
+    let crc = crc(data).to_le_bytes();
+    let bytes = data.iter().chain(crc.iter());
+
+    let (sof, coded_byte_len) = match coding {
+        NfcvCoding::OneOf4 => (SOF_1_OF_4, 4),
+        NfcvCoding::OneOf256 => (SOF_1_OF_256, 64),
+    };
+    let len = 2 + (data.len() + 2) * coded_byte_len;
+    if len > out.len() {
+        return None;
+    }
+
+    out[..len].fill(0);
+    out[0] = sof;
+    let mut pos = 1;
+    for &b in bytes {
+        match coding {
+            NfcvCoding::OneOf4 => {
+                // Each pair of bits is 8 slots, with a pause in slot 2*val+1.
+                for i in 0..4 {
+                    let val = (b >> (i * 2)) & 0x03;
+                    out[pos + i] = 1 << (val * 2 + 1);
+                }
+            }
+            NfcvCoding::OneOf256 => {
+                // The byte is 512 slots, with a pause in slot 2*val+1.
+                let slot = b as usize * 2 + 1;
+                out[pos + slot / 8] = 1 << (slot % 8);
+            }
+        }
+        pos += coded_byte_len;
+    }
+    out[pos] = EOF;
+
+    Some(len)
+}
+
+/// Decode a VICC response stream into `out`, including the CRC. Returns the number of decoded bytes.
+fn decode<T>(stream: &[u8], bits: usize, out: &mut [u8]) -> Result<usize, Error<T>> {
+    let bit = |i: usize| (stream[i / 8] >> (i % 8)) & 1;
+    let bits_at = |i: usize, n: usize| (0..n).fold(0u32, |acc, j| acc | ((bit(i + j) as u32) << j));
+
+    if bits < RESP_SOF_LEN || bits_at(0, RESP_SOF_LEN) != RESP_SOF {
+        debug!("nfcv: bad SOF");
+        return Err(Error::Framing);
+    }
+
+    let mut pos = RESP_SOF_LEN;
+    let mut n = 0;
+    loop {
+        if pos + 2 > bits {
+            debug!("nfcv: missing EOF");
+            return Err(Error::Framing);
+        }
+
+        // Logic 0 is subcarrier then no subcarrier, logic 1 the opposite.
+        // EOF is a logic 0 followed by 3 half-bits of subcarrier.
+        let val = match (bit(pos), bit(pos + 1)) {
+            (1, 0) if pos + 5 <= bits && bits_at(pos + 2, 3) == 0b111 => {
+                if n % 8 != 0 {
+                    debug!("nfcv: EOF not at a byte boundary");
+                    return Err(Error::Framing);
+                }
+                return Ok(n / 8);
+            }
+            (1, 0) => 0,
+            (0, 1) => 1,
+            (1, 1) => return Err(Error::Collision),
+            _ => return Err(Error::Framing),
+        };
+
+        if n / 8 >= out.len() {
+            return Err(Error::ResponseTooLong);
+        }
+        if n % 8 == 0 {
+            out[n / 8] = 0;
+        }
+        out[n / 8] |= val << (n % 8);
+
+        n += 1;
+        pos += 2;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Manchester code `data` as a VICC response stream, with SOF and EOF.
+    /// Returns the number of bits.
+    fn response_stream(data: &[u8], out: &mut [u8]) -> usize {
+        let mut n = 0;
+        let mut push = |bits: &[u8]| {
+            for &b in bits {
+                out[n / 8] |= b << (n % 8);
+                n += 1;
+            }
+        };
+        // SOF: 3 half-bits of subcarrier, then a logic 1.
+        push(&[1, 1, 1, 0, 1]);
+        for &byte in data {
+            for i in 0..8 {
+                match (byte >> i) & 1 {
+                    0 => push(&[1, 0]),
+                    _ => push(&[0, 1]),
+                }
+            }
+        }
+        // EOF: a logic 0, then 3 half-bits of subcarrier.
+        push(&[1, 0, 1, 1, 1]);
+        n
+    }
+
+    #[test]
+    fn crc_check_value() {
+        assert_eq!(crc(b"123456789"), 0x906E);
+        // INVENTORY request, single slot.
+        assert_eq!(crc(&[0x26, 0x01, 0x00]), 0x0AF6);
+    }
+
+    #[test]
+    fn encode_inventory() {
+        let mut out = [0; 64];
+        let n = encode(NfcvCoding::OneOf4, &[0x26, 0x01, 0x00], &mut out).unwrap();
+        #[rustfmt::skip]
+        let expected = [
+            SOF_1_OF_4,
+            0x20, 0x08, 0x20, 0x02, // 26
+            0x08, 0x02, 0x02, 0x02, // 01
+            0x02, 0x02, 0x02, 0x02, // 00
+            0x20, 0x08, 0x80, 0x80, // CRC f6
+            0x20, 0x20, 0x02, 0x02, // CRC 0a
+            EOF,
+        ];
+        assert_eq!(out[..n], expected);
+
+        // 1 out of 256: one pause in 512 slots per byte.
+        let mut out = [0; 256];
+        let n = encode(NfcvCoding::OneOf256, &[0x00], &mut out).unwrap();
+        assert_eq!(n, 2 + 3 * 64);
+        assert_eq!(out[0], SOF_1_OF_256);
+        assert_eq!(out[1..65].iter().filter(|&&b| b != 0).count(), 1);
+        assert_eq!(out[1], 0x02);
+        assert_eq!(out[n - 1], EOF);
+
+        assert_eq!(encode(NfcvCoding::OneOf4, &[0; 8], &mut [0; 32]), None);
+    }
+
+    #[test]
+    fn decode_inventory_response() {
+        // Flags, DSFID, UID E0:04:01:00:12:34:56:78 LSB first, CRC.
+        let mut frame = [0; 12];
+        frame[..10].copy_from_slice(&[0x00, 0x00, 0x78, 0x56, 0x34, 0x12, 0x00, 0x01, 0x04, 0xE0]);
+        let frame_crc = crc(&frame[..10]).to_le_bytes();
+        frame[10..].copy_from_slice(&frame_crc);
+
+        let mut stream = [0; 64];
+        let bits = response_stream(&frame, &mut stream);
+        let mut out = [0; 16];
+        let n = decode::<()>(&stream, bits, &mut out).unwrap();
+        assert_eq!(out[..n], frame);
+
+        // The CRC over the data and its own CRC gives the ISO15693-3 residue, 0xF0B8.
+        assert_eq!(!crc(&out[..n]), 0xF0B8);
+    }
+
+    #[test]
+    fn decode_errors() {
+        let mut stream = [0; 16];
+        let bits = response_stream(&[0xA5], &mut stream);
+
+        // Two cards answering different bits: subcarrier in both halves.
+        let mut collision = stream;
+        collision[0] |= 0b0110_0000;
+        assert_eq!(decode::<()>(&collision, bits, &mut [0; 4]), Err(Error::Collision));
+
+        // No subcarrier in either half.
+        let mut invalid = stream;
+        invalid[0] &= !0b0110_0000;
+        assert_eq!(decode::<()>(&invalid, bits, &mut [0; 4]), Err(Error::Framing));
+
+        // Bad SOF, missing EOF.
+        assert_eq!(decode::<()>(&[0b10011], 5, &mut [0; 4]), Err(Error::Framing));
+        assert_eq!(decode::<()>(&stream, bits - 5, &mut [0; 4]), Err(Error::Framing));
+
+        // Doesn't fit.
+        assert_eq!(decode::<()>(&stream, bits, &mut []), Err(Error::ResponseTooLong));
+    }
+
+    #[test]
+    fn mask_rx_timer_not_left_for_iso14443a() {
+        let mut st = crate::test::fake_st25r39(ChipVariant::St25r3916);
+        st.nfcv_setup().unwrap();
+        assert_eq!(st.regs().mask_rx_timer().read().unwrap(), (4192 / 64) as u8);
+
+        // A session after a V one must see the REQA/WUPA answer at 1172/fc.
+        st.iso14443a_setup().unwrap();
+        assert_eq!(st.regs().mask_rx_timer().read().unwrap(), 0);
+        assert!(st.regs().timer_emv_control().read().unwrap().mrt_step() == regs::TimerEmvControlMrtStep::_64);
+    }
+
+    #[test]
+    fn st25r391x_mask_rx_timer_not_left_for_iso14443a() {
+        let mut st = crate::test::fake_st25r39(ChipVariant::St25r391x);
+        st.nfcv_setup().unwrap();
+        assert_eq!(st.iface.inner.regs[0x0E], (4192 / 64) as u8);
+
+        st.iso14443a_setup().unwrap();
+        assert_eq!(st.iface.inner.regs[0x0E], 0);
+    }
+}
@@ -2923,6 +2923,7 @@ impl From<FieldThresholdDeactvRfe> for u8 {
 #[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct IcIdentityIcType(pub u8);
 impl IcIdentityIcType {
+    pub const ST25R3911: Self = Self(0x01);
     pub const ST25R3916: Self = Self(0x05);
 }
 impl From<u8> for IcIdentityIcType {
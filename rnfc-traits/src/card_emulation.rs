@@ -0,0 +1,42 @@
+//! Card emulation: the chip acts as an ISO14443A card, and a reader talks to it.
+
+use core::fmt::Debug;
+
+/// How the emulated card answers the reader during activation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Iso14443aConfig<'a> {
+    /// ATQA, as sent on the air.
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    /// 4, 7 or 10 bytes.
+    pub uid: &'a [u8],
+    /// ATS, starting with the TL byte, without CRC.
+    pub ats: &'a [u8],
+}
+
+/// An activated emulated card, exchanging ISO-DEP APDUs with the reader.
+///
+/// The counterpart of [`iso_dep::Reader`](crate::iso_dep::Reader): the reader sends
+/// a command, the card answers it with a response.
+pub trait IsoDepCard {
+    type Error: Debug;
+
+    /// Wait for the next command from the reader. Returns its length.
+    async fn receive(&mut self, rx: &mut [u8]) -> Result<usize, Self::Error>;
+
+    /// Send the response to the last command received.
+    async fn respond(&mut self, tx: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<T: IsoDepCard> IsoDepCard for &mut T {
+    type Error = T::Error;
+
+    async fn receive(&mut self, rx: &mut [u8]) -> Result<usize, Self::Error> {
+        T::receive(self, rx).await
+    }
+
+    async fn respond(&mut self, tx: &[u8]) -> Result<(), Self::Error> {
+        T::respond(self, tx).await
+    }
+}
@@ -0,0 +1,68 @@
+use core::fmt::Debug;
+
+/// Chip identity, as reported by the chip itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChipId {
+    /// Chip type. The meaning is chip-specific.
+    pub chip_type: u8,
+    /// Chip revision. The meaning is chip-specific.
+    pub revision: u8,
+}
+
+/// Received signal strength of the last reception.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rssi {
+    /// AM channel. Chip-specific units, higher is stronger.
+    pub am: u8,
+    /// PM channel. Chip-specific units, higher is stronger.
+    pub pm: u8,
+}
+
+/// Driver diagnostics, for production testing and field debugging.
+///
+/// Values a chip can't measure are returned as `None`.
+pub trait Diagnostics {
+    type Error: Debug;
+
+    async fn chip_id(&mut self) -> Result<ChipId, Self::Error>;
+
+    /// Supply voltage in mV.
+    async fn vdd_mv(&mut self) -> Result<Option<u32>, Self::Error>;
+
+    /// Result of the last regulator adjustment. The meaning is chip-specific.
+    async fn regulator(&mut self) -> Result<Option<u8>, Self::Error>;
+
+    /// RF amplitude, measured with the field on.
+    async fn amplitude(&mut self) -> Result<Option<u8>, Self::Error>;
+
+    /// RF phase, measured with the field on.
+    async fn phase(&mut self) -> Result<Option<u8>, Self::Error>;
+
+    /// RSSI of the last successful reception.
+    fn last_rssi(&self) -> Option<Rssi>;
+}
+
+impl<T: Diagnostics> Diagnostics for &mut T {
+    type Error = T::Error;
+
+    async fn chip_id(&mut self) -> Result<ChipId, Self::Error> {
+        T::chip_id(self).await
+    }
+    async fn vdd_mv(&mut self) -> Result<Option<u32>, Self::Error> {
+        T::vdd_mv(self).await
+    }
+    async fn regulator(&mut self) -> Result<Option<u8>, Self::Error> {
+        T::regulator(self).await
+    }
+    async fn amplitude(&mut self) -> Result<Option<u8>, Self::Error> {
+        T::amplitude(self).await
+    }
+    async fn phase(&mut self) -> Result<Option<u8>, Self::Error> {
+        T::phase(self).await
+    }
+    fn last_rssi(&self) -> Option<Rssi> {
+        T::last_rssi(self)
+    }
+}
@@ -1,4 +1,4 @@
-pub use crate::iso14443a_ll::Error;
+pub use crate::iso14443a_ll::{BitRate, Error};
 
 pub const UID_MAX_LEN: usize = 10;
 
@@ -10,6 +10,28 @@ pub trait Reader {
     fn uid(&self) -> &[u8];
     fn atqa(&self) -> [u8; 2];
     fn sak(&self) -> u8;
+
+    /// Highest bit rate the reader can switch to with [`set_bit_rate`](Self::set_bit_rate).
+    fn max_bit_rate(&self) -> BitRate {
+        BitRate::Kbps106
+    }
+
+    /// Switch the bit rate used for the following frames, in each direction.
+    ///
+    /// Only called with bit rates up to [`max_bit_rate`](Self::max_bit_rate).
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let _ = (tx, rx);
+        Ok(())
+    }
+
+    /// Wait `time_1fc` before sending the next frame, like the ISO-DEP start-up frame guard time.
+    ///
+    /// The default returns right away, for readers that can't wait or whose round trip
+    /// is already longer than that.
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        let _ = time_1fc;
+        Ok(())
+    }
 }
 
 impl<T: Reader> Reader for &mut T {
@@ -28,4 +50,16 @@ impl<T: Reader> Reader for &mut T {
     fn sak(&self) -> u8 {
         T::sak(self)
     }
+
+    fn max_bit_rate(&self) -> BitRate {
+        T::max_bit_rate(self)
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        T::set_bit_rate(self, tx, rx).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        T::wait_1fc(self, time_1fc).await
+    }
 }
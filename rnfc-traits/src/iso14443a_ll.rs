@@ -10,6 +10,16 @@ pub enum Frame {
     Anticoll { bits: usize },
 }
 
+/// ISO14443A bit rate. Everything except 106kbps is only used after ISO-DEP PPS.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BitRate {
+    Kbps106,
+    Kbps212,
+    Kbps424,
+    Kbps848,
+}
+
 #[non_exhaustive]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
@@ -39,6 +49,28 @@ pub trait Reader {
     type Error: Error;
 
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error>;
+
+    /// Highest bit rate the reader can switch to with [`set_bit_rate`](Self::set_bit_rate).
+    fn max_bit_rate(&self) -> BitRate {
+        BitRate::Kbps106
+    }
+
+    /// Switch the bit rate used for the following frames, in each direction.
+    ///
+    /// Only called with bit rates up to [`max_bit_rate`](Self::max_bit_rate).
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let _ = (tx, rx);
+        Ok(())
+    }
+
+    /// Wait `time_1fc` before sending the next frame, like the ISO-DEP start-up frame guard time.
+    ///
+    /// The default returns right away, for readers that can't wait or whose round trip
+    /// is already longer than that.
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        let _ = time_1fc;
+        Ok(())
+    }
 }
 
 impl<T: Reader> Reader for &mut T {
@@ -47,4 +79,16 @@ impl<T: Reader> Reader for &mut T {
     async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: Frame) -> Result<usize, Self::Error> {
         T::transceive(self, tx, rx, opts).await
     }
+
+    fn max_bit_rate(&self) -> BitRate {
+        T::max_bit_rate(self)
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        T::set_bit_rate(self, tx, rx).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        T::wait_1fc(self, time_1fc).await
+    }
 }
@@ -0,0 +1,18 @@
+use crate::iso14443a_ll::Error;
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Transmit `tx` and receive the response into `rx`. Returns the response length in bytes.
+    ///
+    /// The reader appends CRC_B to `tx`, and checks and strips it from the response.
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, timeout_1fc).await
+    }
+}
@@ -4,7 +4,12 @@
 // This must go FIRST so that other mods see its macros.
 mod fmt;
 
+pub mod card_emulation;
+pub mod diagnostics;
 pub mod iso14443a;
 pub mod iso14443a_ll;
+pub mod iso14443b_ll;
 
 pub mod iso_dep;
+pub mod nfcf_ll;
+pub mod nfcv_ll;
@@ -0,0 +1,89 @@
+use crate::iso14443a_ll::Error;
+
+/// Number of time slots for SENSF_REQ (the TSN field).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TimeSlots {
+    S1 = 0x00,
+    S2 = 0x01,
+    S4 = 0x03,
+    S8 = 0x07,
+    S16 = 0x0F,
+}
+
+impl TimeSlots {
+    pub const fn count(self) -> usize {
+        self as usize + 1
+    }
+}
+
+/// SENSF_RES, the response to SENSF_REQ.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SensfRes {
+    pub nfcid2: [u8; 8],
+    pub pad: [u8; 8],
+    /// Only present if it was asked for, with a nonzero request code.
+    pub request_data: Option<[u8; 2]>,
+}
+
+impl SensfRes {
+    /// Parse a SENSF_RES frame, without the length byte.
+    pub fn parse(frame: &[u8]) -> Option<Self> {
+        if frame.first() != Some(&0x01) {
+            return None;
+        }
+        let request_data = match frame.len() {
+            17 => None,
+            19 => Some([frame[17], frame[18]]),
+            _ => return None,
+        };
+        let mut res = Self {
+            nfcid2: [0; 8],
+            pad: [0; 8],
+            request_data,
+        };
+        res.nfcid2.copy_from_slice(&frame[1..9]);
+        res.pad.copy_from_slice(&frame[9..17]);
+        Some(res)
+    }
+}
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Transmit `tx` and receive the response into `rx`. Returns the response length in bytes.
+    ///
+    /// `tx` and `rx` don't include the length byte nor the CRC, the reader adds, checks and strips them.
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+
+    /// Send SENSF_REQ, and collect the SENSF_RES responses of all time slots into `res`.
+    ///
+    /// Responses that fail to decode (for example due to two cards answering in the same slot)
+    /// are skipped. Returns the number of responses received.
+    async fn sensf_req(
+        &mut self,
+        system_code: u16,
+        request_code: u8,
+        slots: TimeSlots,
+        res: &mut [SensfRes],
+    ) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, timeout_1fc).await
+    }
+
+    async fn sensf_req(
+        &mut self,
+        system_code: u16,
+        request_code: u8,
+        slots: TimeSlots,
+        res: &mut [SensfRes],
+    ) -> Result<usize, Self::Error> {
+        T::sensf_req(self, system_code, request_code, slots, res).await
+    }
+}
@@ -0,0 +1,26 @@
+use crate::iso14443a_ll::Error;
+
+pub trait Reader {
+    type Error: Error;
+
+    /// Transmit a request and receive the response into `rx`. Returns the response length in bytes.
+    ///
+    /// The reader adds SOF, EOF and CRC to `tx`, and checks and strips them from the response.
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+
+    /// Transmit just an EOF, to switch to the next slot during a 16-slot inventory,
+    /// and receive the response into `rx`.
+    async fn next_slot(&mut self, rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error>;
+}
+
+impl<T: Reader> Reader for &mut T {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        T::transceive(self, tx, rx, timeout_1fc).await
+    }
+
+    async fn next_slot(&mut self, rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        T::next_slot(self, rx, timeout_1fc).await
+    }
+}
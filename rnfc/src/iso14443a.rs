@@ -1,5 +1,5 @@
 use heapless::Vec;
-use rnfc_traits::iso14443a::{Reader, UID_MAX_LEN};
+use rnfc_traits::iso14443a::{BitRate, Reader, UID_MAX_LEN};
 use rnfc_traits::iso14443a_ll as ll;
 use rnfc_traits::iso14443a_ll::{Frame, Reader as LLReader};
 
@@ -325,4 +325,147 @@ impl<'d, T: LLReader + 'd> Reader for Card<'d, T> {
     fn sak(&self) -> u8 {
         self.sak
     }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.reader.max_bit_rate()
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        self.reader.set_bit_rate(tx, rx).await
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        self.reader.wait_1fc(time_1fc).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+    use rnfc_sim::{Faults, Field, Picc, State, Type2};
+
+    use super::*;
+
+    fn ntag(uid: &[u8]) -> Picc {
+        let mut memory = vec![0; 180];
+        memory[..3].copy_from_slice(&uid[..3]);
+        memory[4..8].copy_from_slice(&uid[3..7]);
+        Picc::new(uid, Type2::new(memory))
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn search_multiple_cards() {
+        let mut field = Field::new();
+        // The 7 and 10 byte UIDs have the same UID CL1. The poller can't resolve a collision
+        // in the first bit it asks for, so the UIDs differ a few bits after that.
+        field.add(Picc::new(&hex!("08 02 03 04"), Type2::new(vec![0; 64])));
+        field.add(ntag(&hex!("04 11 22 34 44 55 66")));
+        field.add(Picc::new(&hex!("04 11 22 77 88 99 aa bb cc dd"), Type2::new(vec![0; 64])));
+
+        let mut poller = Poller::new(&mut field);
+        let mut uids = poller.search::<4>().await.unwrap();
+        uids.sort();
+        assert_eq!(uids.len(), 3);
+        assert_eq!(uids[0], hex!("04 11 22 34 44 55 66"));
+        assert_eq!(uids[1], hex!("04 11 22 77 88 99 aa bb cc dd"));
+        assert_eq!(uids[2], hex!("08 02 03 04"));
+
+        // All of them were halted.
+        for i in 0..3 {
+            assert_eq!(field.picc(i).state(), State::Halt);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn search_empty_field() {
+        let mut field = Field::new();
+        let mut poller = Poller::new(&mut field);
+        assert!(poller.search::<4>().await.unwrap().is_empty());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn select_by_id_among_several() {
+        let mut field = Field::new();
+        field.add(ntag(&hex!("04 11 22 33 44 55 66")));
+        field.add(ntag(&hex!("04 11 22 33 44 55 77")));
+
+        let mut poller = Poller::new(&mut field);
+        let mut card = poller.select_by_id(&hex!("04 11 22 33 44 55 77")).await.unwrap();
+        assert_eq!(card.uid(), hex!("04 11 22 33 44 55 77"));
+        assert_eq!(card.atqa(), [0x44, 0x00]);
+        assert_eq!(card.sak(), 0x00);
+
+        // READ page 0 gets the UID from the memory of the selected card only.
+        let mut rx = [0; 16];
+        let n = card.transceive(&[0x30, 0x00], &mut rx, 65536).await.unwrap();
+        assert_eq!(n, 16);
+        assert_eq!(rx[..8], hex!("04 11 22 00 33 44 55 77"));
+
+        assert_eq!(field.picc(0).state(), State::Idle);
+        assert_eq!(field.picc(1).state(), State::Active);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn select_any_with_collision() {
+        let mut field = Field::new();
+        field.add(Picc::new(&hex!("01 02 03 04"), Type2::new(vec![0; 64])));
+        field.add(Picc::new(&hex!("01 02 03 05"), Type2::new(vec![0; 64])));
+
+        // The cards differ in bit 24, the card with a 1 there wins.
+        let mut poller = Poller::new(&mut field);
+        let card = poller.select_any().await.unwrap();
+        assert_eq!(card.uid(), hex!("01 02 03 05"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn bad_bcc() {
+        let mut field = Field::new();
+        let i = field.add(Picc::new(&hex!("01 02 03 04"), Type2::new(vec![0; 64])));
+        field.picc_mut(i).faults = Faults {
+            bad_bcc: true,
+            ..Default::default()
+        };
+
+        let mut poller = Poller::new(&mut field);
+        assert!(matches!(poller.select_any().await, Err(Error::Protocol)));
+        assert!(poller.search::<4>().await.unwrap().is_empty());
+
+        // Selecting by ID doesn't need anticollision.
+        assert!(poller.select_by_id(&hex!("01 02 03 04")).await.is_ok());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn search_card_ignoring_hlta() {
+        let mut field = Field::new();
+        let i = field.add(ntag(&hex!("04 11 22 33 44 55 66")));
+        field.add(ntag(&hex!("04 99 88 77 66 55 44")));
+        field.picc_mut(i).faults = Faults {
+            ignore_hlta: true,
+            ..Default::default()
+        };
+
+        // The card that doesn't halt keeps showing up, but the search still finds both.
+        let mut poller = Poller::new(&mut field);
+        let uids = poller.search::<4>().await.unwrap();
+        assert_eq!(uids.len(), 2);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn select_fails_when_sak_lost_then_recovers() {
+        let mut field = Field::new();
+        let i = field.add(Picc::new(&hex!("01 02 03 04"), Type2::new(vec![0; 64])));
+        field.picc_mut(i).faults = Faults {
+            corrupt: 1,
+            ..Default::default()
+        };
+
+        // The SAK of the first SELECT is lost. The card is already selected, so it
+        // doesn't answer the retries, and the whole selection fails.
+        let mut poller = Poller::new(&mut field);
+        assert!(poller.select_any().await.is_err());
+
+        // Waking it up again works.
+        let card = poller.select_any().await.unwrap();
+        assert_eq!(card.uid(), hex!("01 02 03 04"));
+    }
 }
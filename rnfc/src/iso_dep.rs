@@ -1,4 +1,4 @@
-use rnfc_traits::iso14443a::{Error as _, Reader as Iso14443aReader};
+use rnfc_traits::iso14443a::{BitRate, Error as _, Reader as Iso14443aReader};
 use rnfc_traits::iso14443a_ll::ErrorKind;
 use rnfc_traits::iso_dep::Reader as IsoDepReader;
 
@@ -15,7 +15,6 @@ pub struct IsoDepA<T: Iso14443aReader> {
     fsc: usize,
 
     /// Start-up frame guard time, in units of 1/Fc
-    #[allow(unused)] // TODO implement support in lower layers.
     sfgt_1fc: u32,
 
     /// Framr Waiting Time, in units of 1/Fc
@@ -23,6 +22,11 @@ pub struct IsoDepA<T: Iso14443aReader> {
 
     /// Block count spin bit: 0 or 1
     block_num: u8,
+
+    /// Bit rate negotiated with PPS, PCD to PICC.
+    tx_bit_rate: BitRate,
+    /// Bit rate negotiated with PPS, PICC to PCD.
+    rx_bit_rate: BitRate,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -70,11 +74,17 @@ where
         let mut fsci = 2;
         let mut sfgi = 0;
         let mut fwi = 4;
+        let mut ta = 0;
 
         if ats.len() >= 2 {
             let t0 = ats[1];
             // format byte present.
             fsci = (t0 & 0xF) as usize;
+            if t0 & 0x10 != 0 {
+                if let Some(&x) = ats.get(2) {
+                    ta = x;
+                }
+            }
             if t0 & 0x20 != 0 {
                 let tb_idx = if t0 & 0x10 != 0 { 3 } else { 2 };
                 if let Some(tb) = ats.get(tb_idx) {
@@ -97,13 +107,66 @@ where
 
         debug!("fsc= {}, sfgt={}/fc, fwt={}/fc", fsc, sfgt_1fc, fwt_1fc);
 
-        Ok(Self {
+        let mut this = Self {
             card,
             fsc,
             sfgt_1fc,
             fwt_1fc,
             block_num: 0,
-        })
+            tx_bit_rate: BitRate::Kbps106,
+            rx_bit_rate: BitRate::Kbps106,
+        };
+
+        // The card isn't ready for the next frame until SFGT after the ATS.
+        this.card.wait_1fc(this.sfgt_1fc).await.map_err(Error::Iso14443a)?;
+
+        let (tx_bit_rate, rx_bit_rate) = select_bit_rates(ta, this.card.max_bit_rate());
+        if (tx_bit_rate, rx_bit_rate) != (BitRate::Kbps106, BitRate::Kbps106) {
+            this.pps(tx_bit_rate, rx_bit_rate).await?;
+        }
+
+        Ok(this)
+    }
+
+    /// Switch bit rates with a PPS request, then apply them to the reader.
+    ///
+    /// If the card doesn't accept the PPS, it stays in protocol state at 106 kbps: keep
+    /// going with that.
+    async fn pps(&mut self, tx_bit_rate: BitRate, rx_bit_rate: BitRate) -> Result<(), Error<T::Error>> {
+        debug!("pps: tx={:?} rx={:?}", tx_bit_rate, rx_bit_rate);
+
+        // PPSS with CID 0, PPS0 with PPS1 present, PPS1 = DSI, DRI
+        let pps1 = (rx_bit_rate as u8) << 2 | tx_bit_rate as u8;
+        let req = [0xD0, 0x11, pps1];
+        let mut res = [0; 1];
+        let res_len = match self.card.transceive(&req, &mut res, self.fwt_1fc).await {
+            Ok(len) => len,
+            Err(e) if e.kind() == ErrorKind::Other => {
+                warn!("Trx PPS failed: {:?}", e);
+                return Err(Error::Iso14443a(e));
+            }
+            Err(e) => {
+                warn!("Trx PPS failed: {:?}, staying at 106 kbps", e);
+                return Ok(());
+            }
+        };
+        if res[..res_len] != [0xD0] {
+            warn!("bad PPS response, staying at 106 kbps");
+            return Ok(());
+        }
+
+        self.card
+            .set_bit_rate(tx_bit_rate, rx_bit_rate)
+            .await
+            .map_err(Error::Iso14443a)?;
+        self.tx_bit_rate = tx_bit_rate;
+        self.rx_bit_rate = rx_bit_rate;
+        Ok(())
+    }
+
+    /// Bit rates in use, PCD to PICC and PICC to PCD.
+    pub fn bit_rates(&self) -> (BitRate, BitRate) {
+        (self.tx_bit_rate, self.rx_bit_rate)
     }
 
     pub fn inner(&self) -> &T {
@@ -131,6 +194,34 @@ where
     }
 }
 
+/// Pick the highest bit rates allowed by the ATS TA(1) byte, up to `max`.
+/// Returns the PCD to PICC and PICC to PCD bit rates.
+fn select_bit_rates(ta: u8, max: BitRate) -> (BitRate, BitRate) {
+    // bit 4 is RFU, ignore the whole byte if set.
+    if ta & 0x08 != 0 {
+        return (BitRate::Kbps106, BitRate::Kbps106);
+    }
+
+    let highest = |mask: u8| {
+        [(0x04, BitRate::Kbps848), (0x02, BitRate::Kbps424), (0x01, BitRate::Kbps212)]
+            .into_iter()
+            .find(|&(bit, rate)| mask & bit != 0 && rate <= max)
+            .map(|(_, rate)| rate)
+            .unwrap_or(BitRate::Kbps106)
+    };
+
+    // DR (PCD to PICC) in bits 1-3, DS (PICC to PCD) in bits 5-7.
+    let dr = ta & 0x07;
+    let ds = (ta >> 4) & 0x07;
+    if ta & 0x80 != 0 {
+        // Same bit rate required in both directions.
+        let rate = highest(dr & ds);
+        (rate, rate)
+    } else {
+        (highest(dr), highest(ds))
+    }
+}
+
 impl<T: Iso14443aReader> IsoDepReader for IsoDepA<T>
 where
     T::Error: crate::fmt::Format,
@@ -278,6 +369,10 @@ mod test {
     struct MockReader {
         expected: Vec<(&'static [u8], Result<&'static [u8], ErrorKind>)>,
         pos: usize,
+        max_bit_rate: BitRate,
+        bit_rate: (BitRate, BitRate),
+        /// Calls to `wait_1fc`: frames sent before it, and the time.
+        waits: Vec<(usize, u32)>,
     }
 
     macro_rules! mock {
@@ -293,6 +388,9 @@ mod test {
                     $((&hex_literal::hex!($tx), mock!(@res $rx)),)*
                 ],
                 pos: 0,
+                max_bit_rate: BitRate::Kbps106,
+                bit_rate: (BitRate::Kbps106, BitRate::Kbps106),
+                waits: Vec::new(),
             }
         };
     }
@@ -334,6 +432,20 @@ mod test {
         fn uid(&self) -> &[u8] {
             todo!()
         }
+
+        fn max_bit_rate(&self) -> BitRate {
+            self.max_bit_rate
+        }
+
+        async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+            self.bit_rate = (tx, rx);
+            Ok(())
+        }
+
+        async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+            self.waits.push((self.pos, time_1fc));
+            Ok(())
+        }
     }
 
     macro_rules! trx {
@@ -394,6 +506,83 @@ mod test {
         assert_eq!(x.fwt_1fc, 1048576);
     }
 
+    #[test_log::test(tokio::test)]
+    async fn test_pps() {
+        // TA supports 212 and 424 both ways, reader supports 848.
+        let mut mock = mock!(
+            "e0 80" => "04 38 33 81",
+            "d0 11 0a" => "d0",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.bit_rates(), (BitRate::Kbps424, BitRate::Kbps424));
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps424, BitRate::Kbps424));
+
+        // Limited by the reader.
+        let mut mock = mock!(
+            "e0 80" => "04 38 33 81",
+            "d0 11 05" => "d0",
+        );
+        mock.max_bit_rate = BitRate::Kbps212;
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps212, BitRate::Kbps212));
+
+        // Different bit rates per direction: DR=848, DS=212
+        let mut mock = mock!(
+            "e0 80" => "04 38 14 81",
+            "d0 11 07" => "d0",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps848, BitRate::Kbps212));
+
+        // Same bit rate required both ways: DR=212..848, DS=212
+        let mut mock = mock!(
+            "e0 80" => "04 38 97 81",
+            "d0 11 05" => "d0",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps212, BitRate::Kbps212));
+
+        // SFGI 1: SFGT is waited after the ATS, before the PPS.
+        let mut mock = mock!(
+            "e0 80" => "05 78 33 81 01",
+            "d0 11 0a" => "d0",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.card.waits, [(1, 256 * 16 * 2)]);
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps424, BitRate::Kbps424));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_pps_fallback() {
+        // Bad PPS response: the card is still usable at 106 kbps.
+        let mut mock = mock!(
+            "e0 80" => "04 38 33 81",
+            "d0 11 0a" => "d1",
+            "02 12 34" => "02 56 78",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = &mut IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.bit_rates(), (BitRate::Kbps106, BitRate::Kbps106));
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps106, BitRate::Kbps106));
+        trx!(x, "12 34" => "56 78");
+
+        // No PPS response.
+        let mut mock = mock!(
+            "e0 80" => "04 38 33 81",
+            "d0 11 0a" => timeout,
+            "02 12 34" => "02 56 78",
+        );
+        mock.max_bit_rate = BitRate::Kbps848;
+        let x = &mut IsoDepA::new(mock).await.unwrap();
+        assert_eq!(x.bit_rates(), (BitRate::Kbps106, BitRate::Kbps106));
+        assert_eq!(x.card.bit_rate, (BitRate::Kbps106, BitRate::Kbps106));
+        trx!(x, "12 34" => "56 78");
+    }
+
     // B.2.1 Exchange of I-blocks. Scenario 1
     #[test_log::test(tokio::test)]
     async fn test_exchange_iblocks() {
@@ -694,4 +883,131 @@ mod test {
         x.fsc = 10;
         trx!(x, "12 34" => Error::Communication);
     }
+
+    fn echo_card(ats: &[u8]) -> rnfc_sim::Picc {
+        // Answers with the command reversed, and 90 00.
+        let app = rnfc_sim::IsoDep::new(ats, |apdu| {
+            let mut res: Vec<u8> = apdu.iter().rev().copied().collect();
+            res.extend_from_slice(&[0x90, 0x00]);
+            res
+        });
+        rnfc_sim::Picc::new(&hex!("04 11 22 33 44 55 66"), app)
+    }
+
+    /// The card selected in a simulated field. Unlike [`crate::iso14443a::Card`], gives
+    /// access to the field, to change the cards in the middle of a test.
+    struct SimCard<'a>(&'a mut rnfc_sim::Field);
+
+    impl<'a> SimCard<'a> {
+        async fn select(field: &'a mut rnfc_sim::Field) -> Self {
+            let mut poller = crate::iso14443a::Poller::new(&mut *field);
+            poller.select_any().await.unwrap();
+            Self(field)
+        }
+    }
+
+    impl Iso14443aReader for SimCard<'_> {
+        type Error = rnfc_sim::Error;
+
+        async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+            let opts = rnfc_traits::iso14443a_ll::Frame::Standard { timeout_1fc };
+            let bits = rnfc_traits::iso14443a_ll::Reader::transceive(self.0, tx, rx, opts).await?;
+            Ok(bits / 8)
+        }
+
+        fn atqa(&self) -> [u8; 2] {
+            todo!()
+        }
+
+        fn sak(&self) -> u8 {
+            todo!()
+        }
+
+        fn uid(&self) -> &[u8] {
+            todo!()
+        }
+
+        fn max_bit_rate(&self) -> BitRate {
+            rnfc_traits::iso14443a_ll::Reader::max_bit_rate(self.0)
+        }
+
+        async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+            rnfc_traits::iso14443a_ll::Reader::set_bit_rate(self.0, tx, rx).await
+        }
+    }
+
+    async fn sim_trx<T: Reader>(x: &mut T, tx: &[u8]) -> Result<Vec<u8>, T::Error> {
+        let mut rx = [0; 1024];
+        let n = x.transceive(tx, &mut rx).await?;
+        Ok(rx[..n].to_vec())
+    }
+
+    fn echo(tx: &[u8]) -> Vec<u8> {
+        let mut res: Vec<u8> = tx.iter().rev().copied().collect();
+        res.extend_from_slice(&[0x90, 0x00]);
+        res
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_sim_chaining() {
+        let mut field = rnfc_sim::Field::new();
+        // FSCI 2: FSC 32, so the reader chains the long commands.
+        field.add(echo_card(&hex!("05 72 80 81 02")));
+        let mut poller = crate::iso14443a::Poller::new(&mut field);
+        let card = poller.select_any().await.unwrap();
+        let mut x = IsoDepA::new(card).await.unwrap();
+        assert_eq!(x.fsc, 32);
+
+        let short = hex!("00 a4 04 00");
+        assert_eq!(sim_trx(&mut x, &short).await.unwrap(), echo(&short));
+
+        // Chained both ways.
+        let long: Vec<u8> = (0..600).map(|i| i as u8).collect();
+        assert_eq!(sim_trx(&mut x, &long).await.unwrap(), echo(&long));
+        assert_eq!(sim_trx(&mut x, &short).await.unwrap(), echo(&short));
+
+        x.deselect().await.unwrap();
+        drop(x);
+        assert_eq!(field.picc(0).state(), rnfc_sim::State::Halt);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_sim_faults() {
+        let mut field = rnfc_sim::Field::new();
+        let i = field.add(echo_card(&hex!("05 72 80 81 02")));
+        let mut x = IsoDepA::new(SimCard::select(&mut field).await).await.unwrap();
+
+        // Lost and corrupted answers are recovered with R(NAK) and R(ACK).
+        let long: Vec<u8> = (0..100).map(|i| i as u8).collect();
+        x.inner_mut().0.picc_mut(i).faults.drop = 2;
+        assert_eq!(sim_trx(&mut x, &long).await.unwrap(), echo(&long));
+        x.inner_mut().0.picc_mut(i).faults.corrupt = 3;
+        assert_eq!(sim_trx(&mut x, &long).await.unwrap(), echo(&long));
+
+        // Waiting time extensions.
+        x.inner_mut().0.picc_mut(i).app_mut::<rnfc_sim::IsoDep>().unwrap().wtx = Some(4);
+        assert_eq!(sim_trx(&mut x, &long).await.unwrap(), echo(&long));
+
+        // The card is gone for good.
+        x.inner_mut().0.picc_mut(i).faults.drop = usize::MAX;
+        assert_eq!(sim_trx(&mut x, &long).await, Err(Error::Communication));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_sim_pps() {
+        let mut field = rnfc_sim::Field::new();
+        field.set_max_bit_rate(BitRate::Kbps848);
+        // TA: 212 and 424 both ways.
+        field.add(echo_card(&hex!("05 78 33 81 02")));
+        let mut poller = crate::iso14443a::Poller::new(&mut field);
+        let card = poller.select_any().await.unwrap();
+        let mut x = IsoDepA::new(card).await.unwrap();
+        assert_eq!(x.bit_rates(), (BitRate::Kbps424, BitRate::Kbps424));
+        assert_eq!(sim_trx(&mut x, &hex!("12 34")).await.unwrap(), hex!("34 12 90 00"));
+        drop(x);
+
+        assert_eq!(field.bit_rate(), (BitRate::Kbps424, BitRate::Kbps424));
+        let app = field.picc(0).app::<rnfc_sim::IsoDep>().unwrap();
+        assert_eq!(app.pps1(), Some(0x0A));
+    }
 }
@@ -8,3 +8,4 @@ pub use rnfc_traits as traits;
 
 pub mod iso14443a;
 pub mod iso_dep;
+pub mod trace;
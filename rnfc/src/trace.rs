@@ -0,0 +1,932 @@
+//! Recording and replaying of the exchanges with a card.
+//!
+//! The recorders wrap a reader at one of the layers, [`iso14443a_ll`](ll::Reader),
+//! [`iso14443a`](Iso14443aReader) or [`iso_dep`](IsoDepReader), and write every exchange
+//! to a [`Sink`]. The replayers implement the same traits from a recorded trace, and fail
+//! with [`ReplayError::Diverged`] as soon as the code under test sends something else.
+//!
+//! # Format
+//!
+//! A trace is a sequence of records, with no header or padding. Integers marked `varint`
+//! are unsigned LEB128, the others are single bytes.
+//!
+//! ```text
+//! record      = tag timestamp:varint duration:varint body
+//! tag 0x00    Reader:   max_bit_rate
+//! tag 0x01    Card:     max_bit_rate atqa:2 sak uid_len uid
+//! tag 0x02    SetBitRate: tx_bit_rate rx_bit_rate status
+//! tag 0x10    ReqA       exchange
+//! tag 0x11    WupA       exchange
+//! tag 0x12    Anticoll   bits:varint exchange
+//! tag 0x13    Standard   timeout_1fc:varint exchange
+//! tag 0x14    Card       timeout_1fc:varint exchange
+//! tag 0x15    IsoDep     exchange
+//! exchange    = tx_len:varint tx status [len:varint rx_len:varint rx]
+//! status      = 0 (ok) | 1 (other error) | 2 (timeout) | 3 (corruption)
+//! bit rate    = 0 (106 kbps) | 1 (212 kbps) | 2 (424 kbps) | 3 (848 kbps)
+//! ```
+//!
+//! `len` and `rx` are only present when the status is ok. `len` is the value returned by
+//! `transceive`: bits for `iso14443a_ll`, bytes for the others.
+
+use rnfc_traits::iso14443a::{BitRate, Reader as Iso14443aReader};
+use rnfc_traits::iso14443a_ll::{self as ll, Error as _, ErrorKind};
+use rnfc_traits::iso_dep::Reader as IsoDepReader;
+
+const TAG_READER: u8 = 0x00;
+const TAG_CARD: u8 = 0x01;
+const TAG_SET_BIT_RATE: u8 = 0x02;
+const TAG_REQA: u8 = 0x10;
+const TAG_WUPA: u8 = 0x11;
+const TAG_ANTICOLL: u8 = 0x12;
+const TAG_STANDARD: u8 = 0x13;
+const TAG_CARD_FRAME: u8 = 0x14;
+const TAG_ISO_DEP: u8 = 0x15;
+
+/// Where the recorders write the trace.
+pub trait Sink {
+    /// Current time, for the record timestamps. The unit is up to the sink, it's
+    /// only stored. It's allowed to wrap around.
+    fn now(&mut self) -> u32;
+
+    /// Append `data` to the trace. Each record is written in several calls.
+    fn write(&mut self, data: &[u8]);
+}
+
+impl<T: Sink> Sink for &mut T {
+    fn now(&mut self) -> u32 {
+        T::now(self)
+    }
+    fn write(&mut self, data: &[u8]) {
+        T::write(self, data)
+    }
+}
+
+/// Kind of exchange, with the options it was sent with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Frame {
+    /// [`ll::Frame::ReqA`].
+    ReqA,
+    /// [`ll::Frame::WupA`].
+    WupA,
+    /// [`ll::Frame::Anticoll`].
+    Anticoll { bits: usize },
+    /// [`ll::Frame::Standard`].
+    Standard { timeout_1fc: u32 },
+    /// [`iso14443a::Reader::transceive`](Iso14443aReader::transceive).
+    Card { timeout_1fc: u32 },
+    /// [`iso_dep::Reader::transceive`](IsoDepReader::transceive).
+    IsoDep,
+}
+
+impl From<&ll::Frame> for Frame {
+    fn from(frame: &ll::Frame) -> Self {
+        match *frame {
+            ll::Frame::ReqA => Frame::ReqA,
+            ll::Frame::WupA => Frame::WupA,
+            ll::Frame::Anticoll { bits } => Frame::Anticoll { bits },
+            ll::Frame::Standard { timeout_1fc } => Frame::Standard { timeout_1fc },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Event<'a> {
+    /// Start of an `iso14443a_ll` trace.
+    Reader { max_bit_rate: BitRate },
+    /// Start of an `iso14443a` trace, with the card that was selected.
+    Card {
+        uid: &'a [u8],
+        atqa: [u8; 2],
+        sak: u8,
+        max_bit_rate: BitRate,
+    },
+    SetBitRate {
+        tx: BitRate,
+        rx: BitRate,
+        result: Result<(), ErrorKind>,
+    },
+    Exchange {
+        frame: Frame,
+        tx: &'a [u8],
+        /// Data received. Empty if the exchange failed.
+        rx: &'a [u8],
+        result: Result<usize, ErrorKind>,
+    },
+}
+
+/// One entry of a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Record<'a> {
+    /// [`Sink::now`] at the start of the exchange.
+    pub timestamp: u32,
+    /// Time the exchange took, in the units of [`Sink::now`].
+    pub duration: u32,
+    pub event: Event<'a>,
+}
+
+/// The trace is truncated or malformed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DecodeError;
+
+impl<'a> Record<'a> {
+    /// Serialize the record, passing the bytes to `write`.
+    pub fn encode(&self, mut write: impl FnMut(&[u8])) {
+        let tag = match self.event {
+            Event::Reader { .. } => TAG_READER,
+            Event::Card { .. } => TAG_CARD,
+            Event::SetBitRate { .. } => TAG_SET_BIT_RATE,
+            Event::Exchange { frame, .. } => match frame {
+                Frame::ReqA => TAG_REQA,
+                Frame::WupA => TAG_WUPA,
+                Frame::Anticoll { .. } => TAG_ANTICOLL,
+                Frame::Standard { .. } => TAG_STANDARD,
+                Frame::Card { .. } => TAG_CARD_FRAME,
+                Frame::IsoDep => TAG_ISO_DEP,
+            },
+        };
+        write(&[tag]);
+        write_varint(&mut write, self.timestamp);
+        write_varint(&mut write, self.duration);
+
+        match self.event {
+            Event::Reader { max_bit_rate } => write(&[encode_bit_rate(max_bit_rate)]),
+            Event::Card {
+                uid,
+                atqa,
+                sak,
+                max_bit_rate,
+            } => {
+                write(&[encode_bit_rate(max_bit_rate), atqa[0], atqa[1], sak, uid.len() as u8]);
+                write(uid);
+            }
+            Event::SetBitRate { tx, rx, result } => {
+                write(&[encode_bit_rate(tx), encode_bit_rate(rx), encode_status(result.err())]);
+            }
+            Event::Exchange { frame, tx, rx, result } => {
+                match frame {
+                    Frame::Anticoll { bits } => write_varint(&mut write, bits as u32),
+                    Frame::Standard { timeout_1fc } | Frame::Card { timeout_1fc } => write_varint(&mut write, timeout_1fc),
+                    Frame::ReqA | Frame::WupA | Frame::IsoDep => {}
+                }
+                write_varint(&mut write, tx.len() as u32);
+                write(tx);
+                write(&[encode_status(result.err())]);
+                if let Ok(len) = result {
+                    write_varint(&mut write, len as u32);
+                    write_varint(&mut write, rx.len() as u32);
+                    write(rx);
+                }
+            }
+        }
+    }
+
+    /// Parse the record at the start of `data`. Returns it, and the rest of `data`.
+    pub fn decode(data: &'a [u8]) -> Result<(Self, &'a [u8]), DecodeError> {
+        let mut c = Cursor(data);
+        let tag = c.u8()?;
+        let timestamp = c.varint()?;
+        let duration = c.varint()?;
+
+        let frame = match tag {
+            TAG_READER => {
+                let max_bit_rate = decode_bit_rate(c.u8()?)?;
+                return Ok((Self::new(timestamp, duration, Event::Reader { max_bit_rate }), c.0));
+            }
+            TAG_CARD => {
+                let max_bit_rate = decode_bit_rate(c.u8()?)?;
+                let atqa = [c.u8()?, c.u8()?];
+                let sak = c.u8()?;
+                let len = c.u8()? as usize;
+                let uid = c.bytes(len)?;
+                let event = Event::Card {
+                    uid,
+                    atqa,
+                    sak,
+                    max_bit_rate,
+                };
+                return Ok((Self::new(timestamp, duration, event), c.0));
+            }
+            TAG_SET_BIT_RATE => {
+                let tx = decode_bit_rate(c.u8()?)?;
+                let rx = decode_bit_rate(c.u8()?)?;
+                let result = match decode_status(c.u8()?)? {
+                    None => Ok(()),
+                    Some(e) => Err(e),
+                };
+                let event = Event::SetBitRate { tx, rx, result };
+                return Ok((Self::new(timestamp, duration, event), c.0));
+            }
+            TAG_REQA => Frame::ReqA,
+            TAG_WUPA => Frame::WupA,
+            TAG_ANTICOLL => Frame::Anticoll {
+                bits: c.varint()? as usize,
+            },
+            TAG_STANDARD => Frame::Standard {
+                timeout_1fc: c.varint()?,
+            },
+            TAG_CARD_FRAME => Frame::Card {
+                timeout_1fc: c.varint()?,
+            },
+            TAG_ISO_DEP => Frame::IsoDep,
+            _ => return Err(DecodeError),
+        };
+
+        let len = c.varint()? as usize;
+        let tx = c.bytes(len)?;
+        let (rx, result) = match decode_status(c.u8()?)? {
+            None => {
+                let res = c.varint()? as usize;
+                let len = c.varint()? as usize;
+                (c.bytes(len)?, Ok(res))
+            }
+            Some(e) => (&[][..], Err(e)),
+        };
+        let event = Event::Exchange { frame, tx, rx, result };
+        Ok((Self::new(timestamp, duration, event), c.0))
+    }
+
+    fn new(timestamp: u32, duration: u32, event: Event<'a>) -> Self {
+        Self {
+            timestamp,
+            duration,
+            event,
+        }
+    }
+}
+
+/// Iterate over the records of a trace.
+pub fn records(trace: &[u8]) -> Records<'_> {
+    Records { data: trace }
+}
+
+/// Iterator returned by [`records`].
+pub struct Records<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.data.is_empty() {
+            return None;
+        }
+        match Record::decode(self.data) {
+            Ok((record, rest)) => {
+                self.data = rest;
+                Some(Ok(record))
+            }
+            Err(e) => {
+                self.data = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+struct Cursor<'a>(&'a [u8]);
+
+impl<'a> Cursor<'a> {
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], DecodeError> {
+        if self.0.len() < len {
+            return Err(DecodeError);
+        }
+        let (res, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(res)
+    }
+
+    fn varint(&mut self) -> Result<u32, DecodeError> {
+        let mut val = 0u32;
+        for i in 0..5 {
+            let b = self.u8()?;
+            val |= ((b & 0x7F) as u32) << (i * 7);
+            if b & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        Err(DecodeError)
+    }
+}
+
+fn write_varint(write: &mut impl FnMut(&[u8]), mut val: u32) {
+    let mut buf = [0; 5];
+    let mut n = 0;
+    loop {
+        buf[n] = (val & 0x7F) as u8;
+        val >>= 7;
+        if val == 0 {
+            break;
+        }
+        buf[n] |= 0x80;
+        n += 1;
+    }
+    write(&buf[..=n]);
+}
+
+fn encode_bit_rate(bit_rate: BitRate) -> u8 {
+    match bit_rate {
+        BitRate::Kbps106 => 0,
+        BitRate::Kbps212 => 1,
+        BitRate::Kbps424 => 2,
+        BitRate::Kbps848 => 3,
+    }
+}
+
+fn decode_bit_rate(val: u8) -> Result<BitRate, DecodeError> {
+    match val {
+        0 => Ok(BitRate::Kbps106),
+        1 => Ok(BitRate::Kbps212),
+        2 => Ok(BitRate::Kbps424),
+        3 => Ok(BitRate::Kbps848),
+        _ => Err(DecodeError),
+    }
+}
+
+fn encode_status(err: Option<ErrorKind>) -> u8 {
+    match err {
+        None => 0,
+        Some(ErrorKind::Timeout) => 2,
+        Some(ErrorKind::Corruption) => 3,
+        Some(_) => 1,
+    }
+}
+
+fn decode_status(val: u8) -> Result<Option<ErrorKind>, DecodeError> {
+    match val {
+        0 => Ok(None),
+        1 => Ok(Some(ErrorKind::Other)),
+        2 => Ok(Some(ErrorKind::Timeout)),
+        3 => Ok(Some(ErrorKind::Corruption)),
+        _ => Err(DecodeError),
+    }
+}
+
+fn record(sink: &mut impl Sink, timestamp: u32, event: Event<'_>) {
+    let duration = sink.now().wrapping_sub(timestamp);
+    Record::new(timestamp, duration, event).encode(|data| sink.write(data));
+}
+
+fn record_exchange<E>(
+    sink: &mut impl Sink,
+    timestamp: u32,
+    frame: Frame,
+    tx: &[u8],
+    rx: &[u8],
+    result: &Result<usize, E>,
+    kind: impl FnOnce(&E) -> ErrorKind,
+) {
+    let (rx, result) = match result {
+        Ok(n) => {
+            let rx_len = match frame {
+                // The collision bit is past the bits received, keep it.
+                Frame::Anticoll { .. } => n / 8 + 1,
+                Frame::ReqA | Frame::WupA | Frame::Standard { .. } => n.div_ceil(8),
+                Frame::Card { .. } | Frame::IsoDep => *n,
+            };
+            (&rx[..rx_len.min(rx.len())], Ok(*n))
+        }
+        Err(e) => (&[][..], Err(kind(e))),
+    };
+    record(sink, timestamp, Event::Exchange { frame, tx, rx, result });
+}
+
+/// Records the exchanges of an [`iso14443a_ll::Reader`](ll::Reader).
+pub struct Recorder<T, S> {
+    reader: T,
+    sink: S,
+}
+
+impl<T: ll::Reader, S: Sink> Recorder<T, S> {
+    pub fn new(reader: T, mut sink: S) -> Self {
+        let timestamp = sink.now();
+        let max_bit_rate = reader.max_bit_rate();
+        record(&mut sink, timestamp, Event::Reader { max_bit_rate });
+        Self { reader, sink }
+    }
+
+    pub fn into_inner(self) -> (T, S) {
+        (self.reader, self.sink)
+    }
+}
+
+impl<T: ll::Reader, S: Sink> ll::Reader for Recorder<T, S> {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Self::Error> {
+        let frame = Frame::from(&opts);
+        let timestamp = self.sink.now();
+        let res = self.reader.transceive(tx, rx, opts).await;
+        record_exchange(&mut self.sink, timestamp, frame, tx, rx, &res, |e| e.kind());
+        res
+    }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.reader.max_bit_rate()
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let timestamp = self.sink.now();
+        let res = self.reader.set_bit_rate(tx, rx).await;
+        let result = res.as_ref().map(|_| ()).map_err(|e| e.kind());
+        record(&mut self.sink, timestamp, Event::SetBitRate { tx, rx, result });
+        res
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        self.reader.wait_1fc(time_1fc).await
+    }
+}
+
+/// Records the exchanges with an [`iso14443a::Reader`](Iso14443aReader) card.
+pub struct CardRecorder<T, S> {
+    card: T,
+    sink: S,
+}
+
+impl<T: Iso14443aReader, S: Sink> CardRecorder<T, S> {
+    pub fn new(card: T, mut sink: S) -> Self {
+        let timestamp = sink.now();
+        let event = Event::Card {
+            uid: card.uid(),
+            atqa: card.atqa(),
+            sak: card.sak(),
+            max_bit_rate: card.max_bit_rate(),
+        };
+        record(&mut sink, timestamp, event);
+        Self { card, sink }
+    }
+
+    pub fn into_inner(self) -> (T, S) {
+        (self.card, self.sink)
+    }
+}
+
+impl<T: Iso14443aReader, S: Sink> Iso14443aReader for CardRecorder<T, S> {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        let frame = Frame::Card { timeout_1fc };
+        let timestamp = self.sink.now();
+        let res = self.card.transceive(tx, rx, timeout_1fc).await;
+        record_exchange(&mut self.sink, timestamp, frame, tx, rx, &res, |e| e.kind());
+        res
+    }
+
+    fn uid(&self) -> &[u8] {
+        self.card.uid()
+    }
+    fn atqa(&self) -> [u8; 2] {
+        self.card.atqa()
+    }
+    fn sak(&self) -> u8 {
+        self.card.sak()
+    }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.card.max_bit_rate()
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        let timestamp = self.sink.now();
+        let res = self.card.set_bit_rate(tx, rx).await;
+        let result = res.as_ref().map(|_| ()).map_err(|e| e.kind());
+        record(&mut self.sink, timestamp, Event::SetBitRate { tx, rx, result });
+        res
+    }
+
+    async fn wait_1fc(&mut self, time_1fc: u32) -> Result<(), Self::Error> {
+        self.card.wait_1fc(time_1fc).await
+    }
+}
+
+/// Records the exchanges of an [`iso_dep::Reader`](IsoDepReader).
+///
+/// [`iso_dep::Reader`](IsoDepReader) errors don't have a kind, they're all recorded
+/// as [`ErrorKind::Other`].
+pub struct IsoDepRecorder<T, S> {
+    reader: T,
+    sink: S,
+}
+
+impl<T: IsoDepReader, S: Sink> IsoDepRecorder<T, S> {
+    pub fn new(reader: T, sink: S) -> Self {
+        Self { reader, sink }
+    }
+
+    pub fn into_inner(self) -> (T, S) {
+        (self.reader, self.sink)
+    }
+}
+
+impl<T: IsoDepReader, S: Sink> IsoDepReader for IsoDepRecorder<T, S> {
+    type Error = T::Error;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        let timestamp = self.sink.now();
+        let res = self.reader.transceive(tx, rx).await;
+        record_exchange(&mut self.sink, timestamp, Frame::IsoDep, tx, rx, &res, |_| ErrorKind::Other);
+        res
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReplayError {
+    /// The recorded exchange failed with this error.
+    Recorded(ErrorKind),
+    /// The exchange doesn't match record number `index` of the trace, counting from 0.
+    Diverged { index: usize },
+    /// All the records have been replayed.
+    End,
+    /// The trace is malformed.
+    Decode,
+}
+
+impl ll::Error for ReplayError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Recorded(kind) => *kind,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Position in the trace being replayed.
+struct Replay<'a> {
+    data: &'a [u8],
+    index: usize,
+}
+
+impl<'a> Replay<'a> {
+    fn new(trace: &'a [u8]) -> Self {
+        Self { data: trace, index: 0 }
+    }
+
+    fn next(&mut self) -> Result<(usize, Event<'a>), ReplayError> {
+        if self.data.is_empty() {
+            debug!("trace: no records left");
+            return Err(ReplayError::End);
+        }
+        let (record, rest) = Record::decode(self.data).map_err(|_| ReplayError::Decode)?;
+        self.data = rest;
+        self.index += 1;
+        Ok((self.index - 1, record.event))
+    }
+
+    fn diverged(index: usize) -> ReplayError {
+        debug!("trace: diverged at record {}", index);
+        ReplayError::Diverged { index }
+    }
+
+    fn exchange(&mut self, frame: Frame, tx: &[u8], rx: &mut [u8]) -> Result<usize, ReplayError> {
+        let (index, event) = self.next()?;
+        match event {
+            Event::Exchange {
+                frame: f,
+                tx: t,
+                rx: r,
+                result,
+            } if f == frame && t == tx && r.len() <= rx.len() => {
+                rx[..r.len()].copy_from_slice(r);
+                result.map_err(ReplayError::Recorded)
+            }
+            _ => Err(Self::diverged(index)),
+        }
+    }
+
+    fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), ReplayError> {
+        let (index, event) = self.next()?;
+        match event {
+            Event::SetBitRate { tx: t, rx: r, result } if t == tx && r == rx => result.map_err(ReplayError::Recorded),
+            _ => Err(Self::diverged(index)),
+        }
+    }
+}
+
+/// Replays a trace written by a [`Recorder`], as an [`iso14443a_ll::Reader`](ll::Reader).
+pub struct Replayer<'a> {
+    replay: Replay<'a>,
+    max_bit_rate: BitRate,
+}
+
+impl<'a> Replayer<'a> {
+    pub fn new(trace: &'a [u8]) -> Result<Self, ReplayError> {
+        let mut replay = Replay::new(trace);
+        match replay.next()? {
+            (_, Event::Reader { max_bit_rate }) => Ok(Self { replay, max_bit_rate }),
+            (index, _) => Err(Replay::diverged(index)),
+        }
+    }
+
+    /// All the records have been replayed.
+    pub fn is_done(&self) -> bool {
+        self.replay.data.is_empty()
+    }
+}
+
+impl ll::Reader for Replayer<'_> {
+    type Error = ReplayError;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], opts: ll::Frame) -> Result<usize, Self::Error> {
+        self.replay.exchange(Frame::from(&opts), tx, rx)
+    }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.max_bit_rate
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        self.replay.set_bit_rate(tx, rx)
+    }
+}
+
+/// Replays a trace written by a [`CardRecorder`], as an [`iso14443a::Reader`](Iso14443aReader).
+pub struct CardReplayer<'a> {
+    replay: Replay<'a>,
+    uid: &'a [u8],
+    atqa: [u8; 2],
+    sak: u8,
+    max_bit_rate: BitRate,
+}
+
+impl<'a> CardReplayer<'a> {
+    pub fn new(trace: &'a [u8]) -> Result<Self, ReplayError> {
+        let mut replay = Replay::new(trace);
+        match replay.next()? {
+            (
+                _,
+                Event::Card {
+                    uid,
+                    atqa,
+                    sak,
+                    max_bit_rate,
+                },
+            ) => Ok(Self {
+                replay,
+                uid,
+                atqa,
+                sak,
+                max_bit_rate,
+            }),
+            (index, _) => Err(Replay::diverged(index)),
+        }
+    }
+
+    /// All the records have been replayed.
+    pub fn is_done(&self) -> bool {
+        self.replay.data.is_empty()
+    }
+}
+
+impl Iso14443aReader for CardReplayer<'_> {
+    type Error = ReplayError;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8], timeout_1fc: u32) -> Result<usize, Self::Error> {
+        self.replay.exchange(Frame::Card { timeout_1fc }, tx, rx)
+    }
+
+    fn uid(&self) -> &[u8] {
+        self.uid
+    }
+    fn atqa(&self) -> [u8; 2] {
+        self.atqa
+    }
+    fn sak(&self) -> u8 {
+        self.sak
+    }
+
+    fn max_bit_rate(&self) -> BitRate {
+        self.max_bit_rate
+    }
+
+    async fn set_bit_rate(&mut self, tx: BitRate, rx: BitRate) -> Result<(), Self::Error> {
+        self.replay.set_bit_rate(tx, rx)
+    }
+}
+
+/// Replays a trace written by an [`IsoDepRecorder`], as an [`iso_dep::Reader`](IsoDepReader).
+pub struct IsoDepReplayer<'a> {
+    replay: Replay<'a>,
+}
+
+impl<'a> IsoDepReplayer<'a> {
+    pub fn new(trace: &'a [u8]) -> Self {
+        Self {
+            replay: Replay::new(trace),
+        }
+    }
+
+    /// All the records have been replayed.
+    pub fn is_done(&self) -> bool {
+        self.replay.data.is_empty()
+    }
+}
+
+impl IsoDepReader for IsoDepReplayer<'_> {
+    type Error = ReplayError;
+
+    async fn transceive(&mut self, tx: &[u8], rx: &mut [u8]) -> Result<usize, Self::Error> {
+        self.replay.exchange(Frame::IsoDep, tx, rx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use hex_literal::hex;
+    use rnfc_sim::{Field, IsoDep, Picc, Type2};
+
+    use super::*;
+    use crate::iso14443a::Poller;
+    use crate::iso_dep::{Error as IsoDepError, IsoDepA};
+
+    /// Keeps the trace in memory, the clock ticks once per call.
+    #[derive(Default)]
+    struct VecSink {
+        data: Vec<u8>,
+        time: u32,
+    }
+
+    impl Sink for VecSink {
+        fn now(&mut self) -> u32 {
+            self.time += 1;
+            self.time
+        }
+        fn write(&mut self, data: &[u8]) {
+            self.data.extend_from_slice(data);
+        }
+    }
+
+    fn echo_card() -> Picc {
+        let app = IsoDep::new(&hex!("05 78 80 81 02"), |apdu| {
+            let mut res: Vec<u8> = apdu.iter().rev().copied().collect();
+            res.extend_from_slice(&[0x90, 0x00]);
+            res
+        });
+        Picc::new(&hex!("04 11 22 33 44 55 66"), app)
+    }
+
+    #[test]
+    fn encode_decode() {
+        let records = [
+            Record::new(
+                0,
+                0,
+                Event::Reader {
+                    max_bit_rate: BitRate::Kbps424,
+                },
+            ),
+            Record::new(
+                1,
+                0,
+                Event::Card {
+                    uid: &hex!("01 02 03 04"),
+                    atqa: [0x04, 0x00],
+                    sak: 0x20,
+                    max_bit_rate: BitRate::Kbps106,
+                },
+            ),
+            Record::new(
+                0xFFFF_FFFF,
+                200,
+                Event::SetBitRate {
+                    tx: BitRate::Kbps212,
+                    rx: BitRate::Kbps848,
+                    result: Err(ErrorKind::Other),
+                },
+            ),
+            Record::new(
+                300,
+                1,
+                Event::Exchange {
+                    frame: Frame::Anticoll { bits: 21 },
+                    tx: &hex!("93 25 01"),
+                    rx: &hex!("93 25 01 02 03 04 04"),
+                    result: Ok(56),
+                },
+            ),
+            Record::new(
+                301,
+                2,
+                Event::Exchange {
+                    frame: Frame::Standard { timeout_1fc: 65536 },
+                    tx: &hex!("30 00"),
+                    rx: &[],
+                    result: Err(ErrorKind::Timeout),
+                },
+            ),
+            Record::new(
+                302,
+                3,
+                Event::Exchange {
+                    frame: Frame::IsoDep,
+                    tx: &[],
+                    rx: &hex!("90 00"),
+                    result: Ok(2),
+                },
+            ),
+        ];
+
+        let mut trace = Vec::new();
+        for r in &records {
+            r.encode(|data| trace.extend_from_slice(data));
+        }
+        let decoded: Vec<_> = super::records(&trace).map(Result::unwrap).collect();
+        assert_eq!(decoded, records);
+
+        assert_eq!(super::records(&trace[..trace.len() - 1]).last(), Some(Err(DecodeError)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn replay_poller() {
+        let mut field = Field::new();
+        field.add(Picc::new(&hex!("08 02 03 04"), Type2::new(vec![0; 64])));
+        field.add(echo_card());
+
+        let mut sink = VecSink::default();
+        let mut poller = Poller::new(Recorder::new(&mut field, &mut sink));
+        let uids = poller.search::<4>().await.unwrap();
+        assert_eq!(uids.len(), 2);
+
+        let mut poller = Poller::new(Replayer::new(&sink.data).unwrap());
+        assert_eq!(poller.search::<4>().await.unwrap(), uids);
+
+        // Going past the recorded exchanges.
+        let mut poller = Poller::new(Replayer::new(&sink.data).unwrap());
+        poller.search::<4>().await.unwrap();
+        assert!(matches!(
+            poller.select_any().await,
+            Err(crate::iso14443a::Error::Lower(ReplayError::End))
+        ));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn replay_card() {
+        let mut field = Field::new();
+        field.add(echo_card());
+
+        let mut sink = VecSink::default();
+        let mut poller = Poller::new(&mut field);
+        let card = poller.select_any().await.unwrap();
+        let mut x = IsoDepA::new(CardRecorder::new(card, &mut sink)).await.unwrap();
+        let mut rx = [0; 64];
+        let n = x.transceive(&hex!("00 a4 04 00"), &mut rx).await.unwrap();
+        assert_eq!(&rx[..n], hex!("00 04 a4 00 90 00"));
+        x.deselect().await.unwrap();
+
+        let replayer = CardReplayer::new(&sink.data).unwrap();
+        assert_eq!(replayer.uid(), hex!("04 11 22 33 44 55 66"));
+        assert_eq!(replayer.sak(), 0x20);
+        let mut x = IsoDepA::new(replayer).await.unwrap();
+        let n = x.transceive(&hex!("00 a4 04 00"), &mut rx).await.unwrap();
+        assert_eq!(&rx[..n], hex!("00 04 a4 00 90 00"));
+        x.deselect().await.unwrap();
+
+        // A different command than the recorded one.
+        let mut x = IsoDepA::new(CardReplayer::new(&sink.data).unwrap()).await.unwrap();
+        assert_eq!(
+            x.transceive(&hex!("00 b0 00 00"), &mut rx).await,
+            Err(IsoDepError::Iso14443a(ReplayError::Diverged { index: 2 }))
+        );
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn replay_iso_dep() {
+        let mut field = Field::new();
+        field.add(echo_card());
+
+        let mut sink = VecSink::default();
+        let mut poller = Poller::new(&mut field);
+        let card = poller.select_any().await.unwrap();
+        let mut x = IsoDepRecorder::new(IsoDepA::new(card).await.unwrap(), &mut sink);
+        let mut rx = [0; 64];
+        let n = x.transceive(&hex!("01 02 03"), &mut rx).await.unwrap();
+        assert_eq!(&rx[..n], hex!("03 02 01 90 00"));
+        // The answer doesn't fit.
+        let res = x.transceive(&hex!("04 05"), &mut rx[..2]).await;
+        assert_eq!(res, Err(IsoDepError::RxFrameTooBig));
+
+        let mut x = IsoDepReplayer::new(&sink.data);
+        let n = x.transceive(&hex!("01 02 03"), &mut rx).await.unwrap();
+        assert_eq!(&rx[..n], hex!("03 02 01 90 00"));
+        assert_eq!(
+            x.transceive(&hex!("04 05"), &mut rx[..2]).await,
+            Err(ReplayError::Recorded(ErrorKind::Other))
+        );
+        assert!(x.is_done());
+        assert_eq!(x.transceive(&hex!("04 05"), &mut rx).await, Err(ReplayError::End));
+
+        let records: Vec<_> = super::records(&sink.data).map(Result::unwrap).collect();
+        assert_eq!(records.len(), 2);
+        assert!(records[0].timestamp < records[1].timestamp);
+    }
+}